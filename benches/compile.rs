@@ -0,0 +1,115 @@
+//! Baseline timings for the lexer, the expression parser and the full `compile_str` path.
+//! Inputs are generated so they can be scaled up once the lexer gets faster.
+//!
+//! Run with `cargo bench --bench compile`.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use std::hint::black_box;
+
+use compiler_pilot::{
+    lexer::Lexer,
+    parser::{compile_str, AstNode},
+    utils::{FnLst, VarLst},
+};
+
+/// `functions` functions, each with `statements` reassignments of its parameters
+fn generate_source(functions: usize, statements: usize) -> String {
+    let mut src = String::new();
+    for i in 0..functions {
+        src.push_str(&format!("fn step_{i}(int counter, int total) int {{\n"));
+        for _ in 0..statements {
+            src.push_str("    total = total + counter * (counter - 1)\n");
+            src.push_str("    counter = counter - 1\n");
+        }
+        src.push_str("    return total\n}\n");
+    }
+    src
+}
+
+/// A single function returning an expression nested `depth` parentheses deep
+fn generate_nested_expression(depth: usize) -> String {
+    let mut expr = String::from("x");
+    for i in 0..depth {
+        let op = ["+", "*", "-", "/"][i % 4];
+        expr = format!("({expr} {op} {})", i + 1);
+    }
+    format!("fn nested(int x) int {{\n    return {expr}\n}}\n")
+}
+
+/// A single function returning the sum of `terms` copies of its parameter, as in `x + x + x`
+fn generate_long_sum(terms: usize) -> String {
+    format!("fn sum(int x) int {{\n    return {}\n}}\n", vec!["x"; terms].join(" + "))
+}
+
+fn lex_all(src: &str) -> usize {
+    let mut lexer = Lexer::new(src);
+    while lexer.next().is_some() {}
+    lexer.tokens.len()
+}
+
+fn bench_lexer(c: &mut Criterion) {
+    let mut group = c.benchmark_group("lex");
+    group.sample_size(10);
+    for functions in [1, 4] {
+        let src = generate_source(functions, 4);
+        group.throughput(Throughput::Bytes(src.len() as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(functions), &src, |b, src| {
+            b.iter(|| lex_all(black_box(src)))
+        });
+    }
+    group.finish();
+}
+
+fn bench_nested_expression(c: &mut Criterion) {
+    let mut group = c.benchmark_group("parse_nested_expression");
+    for depth in [8, 32] {
+        let src = generate_nested_expression(depth);
+        let mut lexer = Lexer::new(&src);
+        while lexer.next().is_some() {}
+
+        group.bench_with_input(BenchmarkId::from_parameter(depth), &lexer.tokens, |b, tokens| {
+            b.iter(|| {
+                let mut var_lst = VarLst::new();
+                let mut fn_lst = FnLst::new();
+                AstNode::generate_function(black_box(tokens), &mut var_lst, &mut fn_lst).unwrap()
+            })
+        });
+    }
+    group.finish();
+}
+
+/// Operators of equal precedence are folded in a loop, so this shouldn't overflow the stack
+fn bench_long_sum(c: &mut Criterion) {
+    let mut group = c.benchmark_group("parse_long_sum");
+    group.sample_size(10);
+    for terms in [10_000] {
+        let src = generate_long_sum(terms);
+        let mut lexer = Lexer::new(&src);
+        while lexer.next().is_some() {}
+
+        group.bench_with_input(BenchmarkId::from_parameter(terms), &lexer.tokens, |b, tokens| {
+            b.iter(|| {
+                let mut var_lst = VarLst::new();
+                let mut fn_lst = FnLst::new();
+                AstNode::generate_function(black_box(tokens), &mut var_lst, &mut fn_lst).unwrap()
+            })
+        });
+    }
+    group.finish();
+}
+
+fn bench_compile_str(c: &mut Criterion) {
+    let mut group = c.benchmark_group("compile_str");
+    group.sample_size(10);
+    for functions in [1, 4] {
+        let src = generate_source(functions, 4);
+        group.throughput(Throughput::Bytes(src.len() as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(functions), &src, |b, src| {
+            b.iter(|| compile_str(black_box(src)).unwrap())
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_lexer, bench_nested_expression, bench_long_sum, bench_compile_str);
+criterion_main!(benches);
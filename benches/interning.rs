@@ -0,0 +1,78 @@
+//! Counts the heap allocations made while compiling a source file with many repeated
+//! identifiers, to keep an eye on how much the string interner saves.
+//!
+//! Run with `cargo bench --bench interning`.
+
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Instant,
+};
+
+use compiler_pilot::{
+    lexer::Lexer,
+    parser::AstNode,
+    utils::{FnLst, VarLst},
+};
+
+struct CountingAlloc;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+static BYTES: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        BYTES.fetch_add(layout.size(), Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAlloc = CountingAlloc;
+
+fn source(functions: usize) -> String {
+    let mut src = String::new();
+    for i in 0..functions {
+        src.push_str(&format!("fn step_{i}(int counter, int total) int {{\n"));
+        for _ in 0..10 {
+            src.push_str("    total = total + counter * counter\n");
+            src.push_str("    counter = counter - 1\n");
+        }
+        src.push_str("    return total\n}\n");
+    }
+    src
+}
+
+/// Runs `f` and reports how many allocations (and bytes) it made
+fn measure<T>(label: &str, f: impl FnOnce() -> T) -> T {
+    let allocs_before = ALLOCATIONS.load(Ordering::Relaxed);
+    let bytes_before = BYTES.load(Ordering::Relaxed);
+    let start = Instant::now();
+
+    let res = f();
+
+    let elapsed = start.elapsed();
+    let allocs = ALLOCATIONS.load(Ordering::Relaxed) - allocs_before;
+    let bytes = BYTES.load(Ordering::Relaxed) - bytes_before;
+    println!("{label:>6}: {allocs:>10} allocations, {bytes:>12} bytes in {elapsed:?}");
+    res
+}
+
+fn main() {
+    let src = source(10);
+    println!("source: {} bytes", src.len());
+
+    let mut lexer = Lexer::new(&src);
+    measure("lex", || while lexer.next().is_some() {});
+
+    let mut var_lst = VarLst::new();
+    let mut fn_lst = FnLst::new();
+    let funcs = measure("parse", || AstNode::generate_program(&lexer.tokens, &mut var_lst, &mut fn_lst))
+        .expect("benchmark source should parse");
+    println!("parsed {} functions", funcs.len());
+}
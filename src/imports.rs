@@ -0,0 +1,235 @@
+use std::{collections::HashMap, fs, path::{Path, PathBuf}};
+
+use anyhow::{Result, anyhow};
+
+use crate::{lexer::{decode_utf8, unescape, Diagnostic, Keyword, Lexer, Span, TokenType}, parser::{analyze_tokens, compile_tokens, lex_to_end, Function, ParserOptions}, utils::{FnLst, FnSignature}};
+
+/// The functions of every file a program imports, directly or through other imports
+#[derive(Debug, Default)]
+pub struct Imports {
+    funcs: Vec<Function>,
+    /// The functions of `funcs` that the importing file can call
+    visible: Visible,
+}
+
+/// An `import "path"` or `import "path" as alias` statement
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Import {
+    pub path: String,
+    /// With an alias, the file's functions are only callable qualified by it, as in `m.sqrt(x)`
+    pub alias: Option<String>,
+    pub span: Span,
+}
+
+/// Returns the `import` statements at the top of `source`, in order
+pub fn scan_imports(source: &str) -> Result<Vec<Import>> {
+    let mut imports = vec![];
+    let mut lexer = Lexer::new(source);
+    let mut next = lexer.next();
+    while let Some(token) = next {
+        match token.token_type {
+            TokenType::NewLine => {
+                next = lexer.next();
+                continue;
+            }
+            TokenType::Keyword if token.keyword() == Some(Keyword::Import) => {}
+            _ => break,
+        }
+
+        let span = token.span;
+        let path = match lexer.next() {
+            Some(path) if path.token_type == TokenType::StringLiteral && path.value.len() >= 2 => {
+                unescape(&path.value[1..path.value.len()-1])
+                    .map_err(|e| anyhow!("{} ({}:{})", e, span.line, span.col))?
+            }
+            _ => return Err(anyhow!("expected a quoted path after `import` ({}:{})", span.line, span.col)),
+        };
+
+        next = lexer.next();
+        let mut alias = None;
+        if next.as_ref().and_then(|t| t.keyword()) == Some(Keyword::As) {
+            match lexer.next() {
+                Some(name) if name.token_type == TokenType::Object => alias = Some(name.value.to_string()),
+                _ => return Err(anyhow!("expected a module name after `as` ({}:{})", span.line, span.col)),
+            }
+            next = lexer.next();
+        }
+        imports.push(Import { path, alias, span });
+    }
+    Ok(imports)
+}
+
+/// Compiles every file that `source`, the contents of the file at `path`, imports. Relative
+/// paths are resolved against the directory of the file containing the import.
+pub fn resolve_imports(path: &Path, source: &str, options: &ParserOptions) -> Result<Imports> {
+    let path = canonicalize(path)?;
+    let mut loader = Loader {
+        options: options.clone(),
+        funcs: vec![],
+        exports: HashMap::new(),
+        stack: vec![path.clone()],
+    };
+    let visible = loader.load_imports(&path, source)?;
+    Ok(Imports { funcs: loader.funcs, visible })
+}
+
+/// Like `compile_lexed`, for a program whose imports were resolved by `resolve_imports`. The
+/// imported functions come first in the returned list.
+pub fn compile_lexed_with_imports(lexer: &Lexer, options: ParserOptions, imports: Imports) -> Result<Vec<Function>> {
+    let fn_lst = imports.visible.fn_lst(&imports.funcs)?;
+    let mut funcs = imports.funcs;
+    funcs.extend(compile_tokens(lexer, options, fn_lst)?);
+    Ok(funcs)
+}
+
+/// Like `compile_lexed_with_imports`, but recovers from errors in the program where it can, as
+/// `parser::analyze_str` does. Lint warnings are left to the caller.
+pub fn analyze_lexed_with_imports(lexer: &Lexer, options: ParserOptions, imports: Imports) -> (Option<Vec<Function>>, Vec<Diagnostic>) {
+    let fn_lst = match imports.visible.fn_lst(&imports.funcs) {
+        Ok(fn_lst) => fn_lst,
+        Err(e) => return (None, vec![Diagnostic::error(e.to_string(), Span::default())]),
+    };
+    let (program, diagnostics) = analyze_tokens(lexer, options, fn_lst);
+    let Some(program) = program else { return (None, diagnostics) };
+
+    let mut funcs = imports.funcs;
+    funcs.extend(program);
+    (Some(funcs), diagnostics)
+}
+
+/// Compiles the program in the file at `path` along with every file it imports
+pub fn compile_file(path: &Path) -> Result<Vec<Function>> {
+    compile_file_with_options(path, ParserOptions::default())
+}
+
+/// Like `compile_file`, with `options` controlling how literals are typed
+pub fn compile_file_with_options(path: &Path, options: ParserOptions) -> Result<Vec<Function>> {
+    let bytes = read(path)?;
+    let source = decode_utf8(&bytes)?;
+    let imports = resolve_imports(path, source, &options)?;
+
+    let mut lexer = Lexer::new(source);
+    lex_to_end(&mut lexer).map_err(|e| anyhow!("{}", e))?;
+    compile_lexed_with_imports(&lexer, options, imports)
+}
+
+/// The imported functions a file can call, as indices into the list of every imported function
+#[derive(Debug, Default)]
+struct Visible {
+    /// The `pub` functions of the files imported without an alias
+    unqualified: Vec<usize>,
+    /// The alias of each file imported with `as`, and the `pub` functions of that file
+    aliased: Vec<(String, Vec<usize>)>,
+}
+
+impl Visible {
+    /// Registers the functions under the names they're called by, such as `m.sqrt` for `sqrt`
+    /// from a file imported `as m`
+    fn fn_lst(&self, funcs: &[Function]) -> Result<FnLst> {
+        let mut fn_lst = FnLst::new();
+        let signature = |func: &Function| FnSignature { module: Some(func.module), ..func.fn_signature() };
+        for &i in self.unqualified.iter() {
+            fn_lst.insert(funcs[i].name.clone(), signature(&funcs[i]))?;
+        }
+        for (alias, exports) in self.aliased.iter() {
+            for &i in exports.iter() {
+                fn_lst.insert(format!("{}.{}", alias, funcs[i].name), signature(&funcs[i]))?;
+            }
+        }
+        Ok(fn_lst)
+    }
+}
+
+/// Compiles imported files, each of them once no matter how many files import it
+struct Loader {
+    options: ParserOptions,
+    /// The functions of every file compiled so far
+    funcs: Vec<Function>,
+    /// Indices into `funcs` of the `pub` functions of each file compiled so far
+    exports: HashMap<PathBuf, Vec<usize>>,
+    /// The files being compiled, innermost last, for detecting cyclic imports
+    stack: Vec<PathBuf>,
+}
+
+impl Loader {
+    /// Compiles the files imported by `source`, the contents of `path`, returning the indices of
+    /// the functions `source` can call
+    fn load_imports(&mut self, path: &Path, source: &str) -> Result<Visible> {
+        let dir = path.parent().unwrap_or(Path::new("."));
+        let mut visible = Visible::default();
+        for Import { path: import, alias, span } in scan_imports(source)? {
+            let exports = self.load(&dir.join(&import))
+                .map_err(|e| anyhow!("in import of `{}` ({}:{}): {}", import, span.line, span.col, e))?;
+            match alias {
+                Some(alias) => {
+                    if visible.aliased.iter().any(|(a, _)| *a == alias) {
+                        return Err(anyhow!("module alias `{}` is used by more than one import ({}:{})", alias, span.line, span.col));
+                    }
+                    visible.aliased.push((alias, exports));
+                }
+                None => {
+                    for i in exports {
+                        if !visible.unqualified.contains(&i) {
+                            visible.unqualified.push(i);
+                        }
+                    }
+                }
+            }
+        }
+        Ok(visible)
+    }
+
+    /// Compiles the file at `path` unless it was already, returning the indices of its `pub`
+    /// functions
+    fn load(&mut self, path: &Path) -> Result<Vec<usize>> {
+        let path = canonicalize(path)?;
+        if let Some(start) = self.stack.iter().position(|p| *p == path) {
+            let cycle = self.stack[start..].iter().chain([&path])
+                .map(|p| display_name(p))
+                .collect::<Vec<String>>();
+            return Err(anyhow!("cyclic import: {}", cycle.join(" -> ")));
+        }
+        if let Some(exports) = self.exports.get(&path) {
+            return Ok(exports.clone());
+        }
+
+        let bytes = read(&path)?;
+        let source = decode_utf8(&bytes)?;
+
+        self.stack.push(path.clone());
+        let visible = self.load_imports(&path, source)?;
+        let fn_lst = visible.fn_lst(&self.funcs)?;
+
+        let mut lexer = Lexer::new(source);
+        lex_to_end(&mut lexer).map_err(|e| anyhow!("in `{}`: {}", display_name(&path), e))?;
+        let funcs = compile_tokens(&lexer, self.options.clone(), fn_lst)
+            .map_err(|e| anyhow!("in `{}`: {}", display_name(&path), e))?;
+        self.stack.pop();
+
+        // Modules are numbered from 1, after the importing file's 0
+        let module = self.exports.len() + 1;
+        let mut exports = vec![];
+        for mut func in funcs {
+            func.module = module;
+            if func.is_public() {
+                exports.push(self.funcs.len());
+            }
+            self.funcs.push(func);
+        }
+        self.exports.insert(path, exports.clone());
+        Ok(exports)
+    }
+}
+
+fn canonicalize(path: &Path) -> Result<PathBuf> {
+    fs::canonicalize(path).map_err(|e| anyhow!("cannot find `{}`: {}", path.display(), e))
+}
+
+fn read(path: &Path) -> Result<Vec<u8>> {
+    fs::read(path).map_err(|e| anyhow!("cannot read `{}`: {}", path.display(), e))
+}
+
+/// Returns the file name of `path`, which is enough to tell files apart in most messages
+fn display_name(path: &Path) -> String {
+    path.file_name().map_or_else(|| path.display().to_string(), |name| name.to_string_lossy().into_owned())
+}
@@ -0,0 +1,711 @@
+use std::{cmp::Ordering, collections::HashMap, fmt::Display, rc::Rc};
+
+use anyhow::{Result, anyhow};
+
+use crate::lexer::{unescape, TRIPLE_QUOTE};
+use crate::parser::{
+    format_float, ArrayLiteral, AstNode, BlockExpression, Call, Cast, CodeBlock, Conditional, DataType, ElseBranch, Function, Index, Literal, Operation,
+    OperationType, TupleLiteral, UnaryOperation, UnaryOperationType, Value, Variable,
+};
+
+/// A value produced while evaluating a program
+#[derive(Debug, Clone, PartialEq)]
+pub enum RuntimeValue {
+    Int(i64),
+    UInt(u64),
+    Byte(u8),
+    Float(f64),
+    Bool(bool),
+    Char(char),
+    String(String),
+    Vec(Vec<RuntimeValue>),
+    Tuple(Vec<RuntimeValue>),
+    /// The result of a function without a return type
+    Unit,
+}
+
+impl RuntimeValue {
+    /// Parses `text` as a value of type `dtype`. Unlike a literal in the source, strings and chars
+    /// aren't quoted, which makes this suitable for command-line arguments.
+    pub fn parse(text: &str, dtype: &DataType) -> Result<Self> {
+        let parsed = match dtype {
+            dt if dt.is_string() => Some(RuntimeValue::String(text.to_string())),
+            DataType::I64 => text.replace('_', "").parse().ok().map(RuntimeValue::Int),
+            DataType::U64 => text.replace('_', "").parse().ok().map(RuntimeValue::UInt),
+            DataType::U8 => text.replace('_', "").parse().ok().map(RuntimeValue::Byte),
+            DataType::F64 => text.parse().ok().filter(|f: &f64| f.is_finite()).map(RuntimeValue::Float),
+            DataType::Bool => text.parse().ok().map(RuntimeValue::Bool),
+            DataType::Char => text.parse().ok().map(RuntimeValue::Char),
+            DataType::String | DataType::Vec { inner: _ } | DataType::Array { .. } | DataType::Tuple(_) | DataType::Unit => None,
+        };
+        parsed.ok_or_else(|| anyhow!("`{}` is not a valid `{}`", text, dtype))
+    }
+
+    fn from_literal(lit: &Literal) -> Result<Self> {
+        let text = &*lit.value;
+        let unquoted = |quote: &str| text.strip_prefix(quote).and_then(|t| t.strip_suffix(quote));
+
+        match &lit.dtype {
+            DataType::Char => match unquoted("'").and_then(|t| unescape(t).ok()).map(|t| t.chars().collect::<Vec<char>>()).as_deref() {
+                Some([c]) => Ok(RuntimeValue::Char(*c)),
+                _ => Err(anyhow!("malformed char literal: `{}`", text)),
+            },
+            dt if dt.is_string() => {
+                // Triple-quoted strings are kept as written, except that their line endings are
+                // `\n` whatever the source file uses
+                if let Some(content) = unquoted(TRIPLE_QUOTE) {
+                    return Ok(RuntimeValue::String(content.replace("\r\n", "\n").replace('\r', "\n")));
+                }
+                let content = unquoted("\"").ok_or_else(|| anyhow!("malformed string literal: `{}`", text))?;
+                Ok(RuntimeValue::String(unescape(content)?))
+            }
+            dt => Self::parse(text, dt),
+        }
+    }
+
+    /// Converts an integer to a float, or a `byte` to a wider integer, when it's stored somewhere
+    /// of that type, which the type checker allows
+    fn widen_to(self, dtype: &DataType) -> Self {
+        match (self, dtype) {
+            (RuntimeValue::Byte(b), DataType::I64) => RuntimeValue::Int(b as i64),
+            (RuntimeValue::Byte(b), DataType::U64) => RuntimeValue::UInt(b as u64),
+            (v, DataType::F64) if v.as_integer().is_some() => RuntimeValue::Float(v.as_integer().unwrap() as f64),
+            (v, _) => v,
+        }
+    }
+
+    /// Returns the literal for this value as a `dtype`, if it's a number or a bool
+    pub(crate) fn to_literal(&self, dtype: &DataType) -> Option<Literal> {
+        let text = match self.clone().widen_to(dtype) {
+            RuntimeValue::Int(v) => v.to_string(),
+            RuntimeValue::UInt(v) => v.to_string(),
+            RuntimeValue::Byte(v) => v.to_string(),
+            RuntimeValue::Float(v) => format_float(v),
+            RuntimeValue::Bool(v) => v.to_string(),
+            _ => return None,
+        };
+        Literal::parse_typed(&text, dtype.clone()).ok()
+    }
+
+    fn as_bool(&self, expr: &str) -> Result<bool> {
+        match self {
+            RuntimeValue::Bool(b) => Ok(*b),
+            v => Err(anyhow!("condition `{}` evaluated to `{}` rather than a `bool`", expr, v)),
+        }
+    }
+
+    fn as_integer(&self) -> Option<i128> {
+        match self {
+            RuntimeValue::Int(v) => Some(*v as i128),
+            RuntimeValue::UInt(v) => Some(*v as i128),
+            RuntimeValue::Byte(v) => Some(*v as i128),
+            _ => None,
+        }
+    }
+}
+
+impl Display for RuntimeValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RuntimeValue::Int(v) => write!(f, "{}", v),
+            RuntimeValue::UInt(v) => write!(f, "{}", v),
+            RuntimeValue::Byte(v) => write!(f, "{}", v),
+            RuntimeValue::Float(v) => write!(f, "{}", format_float(*v)),
+            RuntimeValue::Bool(v) => write!(f, "{}", v),
+            RuntimeValue::Char(v) => write!(f, "{}", v),
+            RuntimeValue::String(v) => write!(f, "{}", v),
+            RuntimeValue::Vec(items) => {
+                let items = items.iter().map(|v| v.to_string()).collect::<Vec<String>>();
+                write!(f, "[{}]", items.join(", "))
+            }
+            RuntimeValue::Tuple(items) => {
+                let items = items.iter().map(|v| v.to_string()).collect::<Vec<String>>();
+                write!(f, "({})", items.join(", "))
+            }
+            RuntimeValue::Unit => write!(f, "()"),
+        }
+    }
+}
+
+/// How deeply calls may nest before a program is stopped, so that unbounded recursion is an error
+/// rather than a stack overflow. Each call takes a few kilobytes of the native stack in a debug
+/// build, so deep recursion needs a thread with a larger stack than the default.
+pub const MAX_CALL_DEPTH: usize = 1000;
+
+/// The variables visible while a function runs, innermost scope last, and the functions it can call
+pub struct Frame<'a> {
+    scopes: Vec<HashMap<Rc<str>, RuntimeValue>>,
+    program: &'a [Function],
+    /// The number of calls this frame's function is nested in
+    depth: usize,
+    /// The `Function::module` of the running function, which its calls to functions of the same
+    /// file are looked up in
+    module: usize,
+}
+
+impl<'a> Frame<'a> {
+    fn new(program: &'a [Function]) -> Self {
+        Frame { scopes: vec![HashMap::new()], program, depth: 0, module: 0 }
+    }
+
+    fn get(&self, name: &str) -> Option<&RuntimeValue> {
+        self.scopes.iter().rev().find_map(|scope| scope.get(name))
+    }
+
+    fn declare(&mut self, name: Rc<str>, value: RuntimeValue) {
+        self.scopes.last_mut().unwrap().insert(name, value);
+    }
+
+    fn assign(&mut self, name: &str, value: RuntimeValue) -> Result<()> {
+        match self.scopes.iter_mut().rev().find_map(|scope| scope.get_mut(name)) {
+            Some(slot) => {
+                *slot = value;
+                Ok(())
+            }
+            None => Err(anyhow!("cannot assign to undeclared variable `{}`", name)),
+        }
+    }
+}
+
+/// Evaluation of a `Value` node. Every `Value` implements this, so a `Box<dyn Value>` can be
+/// evaluated without knowing which node it holds.
+pub trait Evaluate {
+    fn eval(&self, frame: &mut Frame) -> Result<RuntimeValue>;
+}
+
+impl Evaluate for Literal {
+    fn eval(&self, _frame: &mut Frame) -> Result<RuntimeValue> {
+        RuntimeValue::from_literal(self)
+    }
+}
+
+impl Evaluate for Variable {
+    fn eval(&self, frame: &mut Frame) -> Result<RuntimeValue> {
+        frame.get(&self.name)
+            .cloned()
+            .ok_or_else(|| anyhow!("variable `{}` was read before it was assigned", self.name))
+    }
+}
+
+impl Evaluate for Operation {
+    fn eval(&self, frame: &mut Frame) -> Result<RuntimeValue> {
+        let lhs = self.opd_1.eval(frame)?;
+        let rhs = self.opd_2.eval(frame)?;
+        apply_binary(self.op, lhs, rhs)
+    }
+}
+
+impl Evaluate for UnaryOperation {
+    fn eval(&self, frame: &mut Frame) -> Result<RuntimeValue> {
+        match (self.op, self.operand.eval(frame)?) {
+            (UnaryOperationType::Negate, RuntimeValue::Int(v)) => v.checked_neg()
+                .map(RuntimeValue::Int)
+                .ok_or_else(|| anyhow!("integer overflow negating `{}`", v)),
+            (UnaryOperationType::Negate, RuntimeValue::Float(v)) => Ok(RuntimeValue::Float(-v)),
+            (UnaryOperationType::Not, RuntimeValue::Bool(v)) => Ok(RuntimeValue::Bool(!v)),
+            (UnaryOperationType::BitNot, RuntimeValue::Int(v)) => Ok(RuntimeValue::Int(!v)),
+            (UnaryOperationType::BitNot, RuntimeValue::UInt(v)) => Ok(RuntimeValue::UInt(!v)),
+            (UnaryOperationType::BitNot, RuntimeValue::Byte(v)) => Ok(RuntimeValue::Byte(!v)),
+            (op, v) => Err(anyhow!("cannot apply `{}` to `{}`", op.as_str(), v)),
+        }
+    }
+}
+
+impl Evaluate for Index {
+    fn eval(&self, frame: &mut Frame) -> Result<RuntimeValue> {
+        let base = self.base.eval(frame)?;
+        let index = self.index.eval(frame)?;
+        let position = index.as_integer()
+            .ok_or_else(|| anyhow!("index `{}` is not an integer", index))?;
+
+        match base {
+            RuntimeValue::Vec(items) => usize::try_from(position).ok()
+                .and_then(|i| items.get(i).cloned())
+                .ok_or_else(|| anyhow!("index {} is out of bounds for length {}", position, items.len())),
+            RuntimeValue::String(s) => usize::try_from(position).ok()
+                .and_then(|i| s.as_bytes().get(i).copied())
+                .map(RuntimeValue::Byte)
+                .ok_or_else(|| anyhow!("index {} is out of bounds for string of length {}", position, s.len())),
+            v => Err(anyhow!("cannot index into `{}`", v)),
+        }
+    }
+}
+
+impl Evaluate for Call {
+    fn eval(&self, frame: &mut Frame) -> Result<RuntimeValue> {
+        let args = self.args.iter().map(|a| a.eval(frame)).collect::<Result<Vec<RuntimeValue>>>()?;
+
+        if &*self.name == "print" {
+            println!("{}", args[0]);
+            return Ok(RuntimeValue::Unit);
+        }
+        if &*self.name == "len" {
+            let len = match &args[0] {
+                RuntimeValue::String(s) => s.len(),
+                RuntimeValue::Vec(items) => items.len(),
+                v => return Err(anyhow!("cannot take the length of `{}`", v)),
+            };
+            return Ok(RuntimeValue::Int(len as i64));
+        }
+
+        let module = self.module.unwrap_or(frame.module);
+        let func = frame.program.iter()
+            .find(|f| f.module == module && f.name == self.function_name())
+            .ok_or_else(|| anyhow!("call to undefined function `{}`", self.name))?;
+        if frame.depth >= MAX_CALL_DEPTH {
+            return Err(anyhow!("calls nested more than {} deep, in `{}`", MAX_CALL_DEPTH, func.name));
+        }
+        call_function(frame.program, func, args, frame.depth + 1)
+    }
+}
+
+impl Evaluate for ArrayLiteral {
+    fn eval(&self, frame: &mut Frame) -> Result<RuntimeValue> {
+        let inner = match &self.dtype {
+            DataType::Vec { inner } | DataType::Array { inner, .. } => inner,
+            dt => return Err(anyhow!("array literal has non-array type `{}`", dt)),
+        };
+        let items = self.elements.iter()
+            .map(|e| e.eval(frame).map(|v| v.widen_to(inner)))
+            .collect::<Result<Vec<RuntimeValue>>>()?;
+        Ok(RuntimeValue::Vec(items))
+    }
+}
+
+impl Evaluate for BlockExpression {
+    fn eval(&self, frame: &mut Frame) -> Result<RuntimeValue> {
+        // The parser rejects `return`, and `break`/`continue` outside a loop in the block, so
+        // control always reaches the trailing expression
+        frame.scopes.push(HashMap::new());
+        let res = exec_statements(&self.block, frame).and_then(|_| match &self.value {
+            Some(value) => value.eval(frame),
+            None => Ok(RuntimeValue::Unit),
+        });
+        frame.scopes.pop();
+        res
+    }
+}
+
+impl Evaluate for TupleLiteral {
+    fn eval(&self, frame: &mut Frame) -> Result<RuntimeValue> {
+        let items = self.elements.iter().map(|e| e.eval(frame)).collect::<Result<Vec<RuntimeValue>>>()?;
+        Ok(RuntimeValue::Tuple(items))
+    }
+}
+
+impl Evaluate for Conditional {
+    fn eval(&self, frame: &mut Frame) -> Result<RuntimeValue> {
+        let branch = match self.condition.eval(frame)?.as_bool(&self.condition.value())? {
+            true => &self.then_value,
+            false => &self.else_value,
+        };
+
+        // A branch may be an integer when the other is a float
+        Ok(branch.eval(frame)?.widen_to(&self.dtype))
+    }
+}
+
+impl Evaluate for Cast {
+    fn eval(&self, frame: &mut Frame) -> Result<RuntimeValue> {
+        let value = self.value.eval(frame)?;
+        let res = match (&value, self.dtype()) {
+            (RuntimeValue::Float(v), DataType::F64) => Some(RuntimeValue::Float(*v)),
+            (v, DataType::F64) => v.as_integer().map(|i| RuntimeValue::Float(i as f64)),
+            // Floats are truncated toward zero, like `as` in Rust, but must be in range
+            (RuntimeValue::Float(v), dtype) if v.is_finite() => integer_as(v.trunc() as i128, &dtype),
+            (v, dtype) => v.as_integer().and_then(|i| integer_as(i, &dtype)),
+        };
+        res.ok_or_else(|| anyhow!("cannot cast `{}` to `{}`", value, self.dtype()))
+    }
+}
+
+/// Returns `i` as a value of the integer type `dtype`, if it's in range
+fn integer_as(i: i128, dtype: &DataType) -> Option<RuntimeValue> {
+    match dtype {
+        DataType::I64 => i64::try_from(i).ok().map(RuntimeValue::Int),
+        DataType::U64 => u64::try_from(i).ok().map(RuntimeValue::UInt),
+        DataType::U8 => u8::try_from(i).ok().map(RuntimeValue::Byte),
+        _ => None,
+    }
+}
+
+/// Concatenates two strings. Either may be a `byte[]` built from an array literal rather than a
+/// string, which is typed as a string too, as long as the result is valid UTF-8.
+fn concat(lhs: RuntimeValue, rhs: RuntimeValue) -> Result<RuntimeValue> {
+    let mut bytes = vec![];
+    for v in [&lhs, &rhs] {
+        match v {
+            RuntimeValue::String(s) => bytes.extend_from_slice(s.as_bytes()),
+            RuntimeValue::Vec(items) => for item in items {
+                match item {
+                    RuntimeValue::Byte(b) => bytes.push(*b),
+                    _ => return Err(anyhow!("cannot concatenate `{}` and `{}`", lhs, rhs)),
+                }
+            },
+            _ => return Err(anyhow!("cannot concatenate `{}` and `{}`", lhs, rhs)),
+        }
+    }
+    String::from_utf8(bytes)
+        .map(RuntimeValue::String)
+        .map_err(|_| anyhow!("concatenating `{}` and `{}` doesn't give valid UTF-8", lhs, rhs))
+}
+
+fn apply_binary(op: OperationType, lhs: RuntimeValue, rhs: RuntimeValue) -> Result<RuntimeValue> {
+    let expr = format!("{} {} {}", lhs, op.as_str(), rhs);
+
+    // Only strings can be added that aren't numbers, see `Operation::gen_return_t`
+    if op == OperationType::Add && [&lhs, &rhs].iter().all(|v| matches!(v, RuntimeValue::String(_) | RuntimeValue::Vec(_))) {
+        return concat(lhs, rhs);
+    }
+
+    if op == OperationType::In {
+        let RuntimeValue::Vec(items) = rhs else {
+            return Err(anyhow!("cannot look for `{}` in `{}`", lhs, rhs));
+        };
+        for item in items {
+            if apply_binary(OperationType::Eq, lhs.clone(), item)? == RuntimeValue::Bool(true) {
+                return Ok(RuntimeValue::Bool(true));
+            }
+        }
+        return Ok(RuntimeValue::Bool(false));
+    }
+
+    // Integers are promoted to floats when mixed with them, matching `Operation::gen_return_t`
+    let (lhs, rhs) = match (lhs, rhs) {
+        (RuntimeValue::Float(a), b) if b.as_integer().is_some() => (RuntimeValue::Float(a), RuntimeValue::Float(b.as_integer().unwrap() as f64)),
+        (a, RuntimeValue::Float(b)) if a.as_integer().is_some() => (RuntimeValue::Float(a.as_integer().unwrap() as f64), RuntimeValue::Float(b)),
+        pair => pair,
+    };
+
+    if op.is_comparison() {
+        let ordering = compare(&lhs, &rhs)
+            .ok_or_else(|| anyhow!("cannot compare `{}` and `{}`", lhs, rhs))?;
+        let res = match op {
+            OperationType::Eq => ordering == Ordering::Equal,
+            OperationType::NotEq => ordering != Ordering::Equal,
+            OperationType::LessThan => ordering == Ordering::Less,
+            OperationType::LessThanOrEq => ordering != Ordering::Greater,
+            OperationType::GreaterThan => ordering == Ordering::Greater,
+            OperationType::GreaterThanOrEq => ordering != Ordering::Less,
+            _ => unreachable!(),
+        };
+        return Ok(RuntimeValue::Bool(res));
+    }
+
+    match (&lhs, &rhs) {
+        (RuntimeValue::Float(a), RuntimeValue::Float(b)) => {
+            // Unlike float division, which gives an infinity, there's no sensible `x % 0.0`
+            if *b == 0.0 && op == OperationType::Mod {
+                return Err(anyhow!("modulo by zero in `{}`", expr));
+            }
+            let res = match op {
+                OperationType::Add => a + b,
+                OperationType::Subtract => a - b,
+                OperationType::Mult => a * b,
+                OperationType::Div => a / b,
+                OperationType::Mod => a % b,
+                _ => unreachable!(),
+            };
+            Ok(RuntimeValue::Float(res))
+        }
+        _ if lhs.as_integer().is_some() && rhs.as_integer().is_some() => {
+            let (a, b) = (lhs.as_integer().unwrap(), rhs.as_integer().unwrap());
+            if b == 0 && [OperationType::Div, OperationType::Mod].contains(&op) {
+                return Err(anyhow!("division by zero in `{}`", expr));
+            }
+            // Both operands fit in 64 bits, so only a product can overflow an i128, as
+            // `u64::MAX * u64::MAX` does
+            let res = match op {
+                OperationType::Add => a.checked_add(b),
+                OperationType::Subtract => a.checked_sub(b),
+                OperationType::Mult => a.checked_mul(b),
+                OperationType::Div => a.checked_div(b),
+                OperationType::Mod => a.checked_rem(b),
+                _ => unreachable!(),
+            };
+
+            // A `byte` mixed with a wider integer takes the wider type, as in `Operation::gen_return_t`
+            let target = if matches!(lhs, RuntimeValue::Byte(_)) { &rhs } else { &lhs };
+            let wrapped = res.and_then(|res| match target {
+                RuntimeValue::Int(_) => i64::try_from(res).ok().map(RuntimeValue::Int),
+                RuntimeValue::UInt(_) => u64::try_from(res).ok().map(RuntimeValue::UInt),
+                _ => u8::try_from(res).ok().map(RuntimeValue::Byte),
+            });
+            wrapped.ok_or_else(|| anyhow!("integer overflow in `{}`", expr))
+        }
+        _ => Err(anyhow!("cannot apply `{}` to `{}` and `{}`", op.as_str(), lhs, rhs)),
+    }
+}
+
+/// Orders two values of the same kind, or returns `None` if they can't be compared
+fn compare(lhs: &RuntimeValue, rhs: &RuntimeValue) -> Option<Ordering> {
+    match (lhs, rhs) {
+        (RuntimeValue::Int(a), RuntimeValue::Int(b)) => Some(a.cmp(b)),
+        (RuntimeValue::UInt(a), RuntimeValue::UInt(b)) => Some(a.cmp(b)),
+        (RuntimeValue::Byte(a), RuntimeValue::Byte(b)) => Some(a.cmp(b)),
+        (a, b) if a.as_integer().is_some() && b.as_integer().is_some() => Some(a.as_integer().cmp(&b.as_integer())),
+        (RuntimeValue::Float(a), RuntimeValue::Float(b)) => a.partial_cmp(b),
+        (RuntimeValue::Bool(a), RuntimeValue::Bool(b)) => Some(a.cmp(b)),
+        (RuntimeValue::Char(a), RuntimeValue::Char(b)) => Some(a.cmp(b)),
+        (RuntimeValue::String(a), RuntimeValue::String(b)) => Some(a.cmp(b)),
+        _ => None,
+    }
+}
+
+/// How control leaves a block
+enum Flow {
+    Normal,
+    Break,
+    Continue,
+    Return(RuntimeValue),
+}
+
+/// Runs the statements of `block` in a new scope
+fn exec_block(block: &CodeBlock, frame: &mut Frame) -> Result<Flow> {
+    frame.scopes.push(HashMap::new());
+    let res = exec_statements(block, frame);
+    frame.scopes.pop();
+    res
+}
+
+fn exec_statements(block: &CodeBlock, frame: &mut Frame) -> Result<Flow> {
+    for stmt in block.statements.iter() {
+        let flow = match stmt {
+            AstNode::AssignmentStatement(assignment) => {
+                let value = assignment.src.eval(frame)?.widen_to(&assignment.dst.dtype);
+                if assignment.is_declaration {
+                    frame.declare(assignment.dst.name.clone(), value);
+                }
+                else {
+                    frame.assign(&assignment.dst.name, value)?;
+                }
+                Flow::Normal
+            }
+            AstNode::ExpressionStatement(val) => {
+                val.eval(frame)?;
+                Flow::Normal
+            }
+            AstNode::IndexAssignment(assignment) => {
+                let index = assignment.index.eval(frame)?;
+                let position = index.as_integer()
+                    .ok_or_else(|| anyhow!("index `{}` is not an integer", index))?;
+                let value = assignment.src.eval(frame)?;
+
+                let mut items = match frame.get(&assignment.dst.name) {
+                    Some(RuntimeValue::Vec(items)) => items.clone(),
+                    Some(v) => return Err(anyhow!("cannot index into `{}`", v)),
+                    None => return Err(anyhow!("variable `{}` was read before it was assigned", assignment.dst.name)),
+                };
+                let len = items.len();
+                let slot = usize::try_from(position).ok()
+                    .and_then(|i| items.get_mut(i))
+                    .ok_or_else(|| anyhow!("index {} is out of bounds for length {}", position, len))?;
+                *slot = match &assignment.dst.dtype {
+                    DataType::Vec { inner } | DataType::Array { inner, .. } => value.widen_to(inner),
+                    _ => value,
+                };
+                frame.assign(&assignment.dst.name, RuntimeValue::Vec(items))?;
+                Flow::Normal
+            }
+            AstNode::If(if_obj) => {
+                let mut arm = if_obj;
+                loop {
+                    if arm.condition.eval(frame)?.as_bool(&arm.condition.value())? {
+                        break exec_block(&arm.body, frame)?;
+                    }
+                    match &arm.else_branch {
+                        Some(ElseBranch::If(next)) => arm = next,
+                        Some(ElseBranch::Block(else_body)) => break exec_block(else_body, frame)?,
+                        None => break Flow::Normal,
+                    }
+                }
+            }
+            AstNode::Loop(loop_obj) => {
+                let mut flow = Flow::Normal;
+                while loop_obj.condition.eval(frame)?.as_bool(&loop_obj.condition.value())? {
+                    match exec_block(&loop_obj.body, frame)? {
+                        Flow::Break => break,
+                        Flow::Return(value) => {
+                            flow = Flow::Return(value);
+                            break;
+                        }
+                        Flow::Normal | Flow::Continue => {}
+                    }
+                }
+                flow
+            }
+            AstNode::ReturnStatement(ret) => {
+                let value = match &ret.value {
+                    Some(val) => val.eval(frame)?,
+                    None => RuntimeValue::Unit,
+                };
+                Flow::Return(value)
+            }
+            AstNode::Break => Flow::Break,
+            AstNode::Continue => Flow::Continue,
+            AstNode::CodeBlock(inner) => exec_block(inner, frame)?,
+            AstNode::Operation(op) => {
+                op.eval(frame)?;
+                Flow::Normal
+            }
+            AstNode::Variable(_) | AstNode::Function(_) => Flow::Normal,
+        };
+
+        if !matches!(flow, Flow::Normal) {
+            return Ok(flow);
+        }
+    }
+    Ok(Flow::Normal)
+}
+
+/// Checks that `given` arguments are enough for `func`'s parameters, counting their defaults
+fn check_arg_count(func: &Function, given: usize) -> Result<()> {
+    let required = func.defaults.iter().filter(|d| d.is_none()).count();
+    if given < required || given > func.parameters.len() {
+        return Err(anyhow!(
+            "function `{}` takes {} arguments but {} were given", func.name, func.parameters.len(), given
+        ));
+    }
+    Ok(())
+}
+
+/// Evaluates a value that doesn't depend on any variables or functions, returning `None` if it
+/// fails to evaluate
+pub(crate) fn eval_constant(val: &dyn Value) -> Option<RuntimeValue> {
+    val.eval(&mut Frame::new(&[])).ok()
+}
+
+/// Calls `func` with `args`, which must already match its parameter types. Omitted trailing
+/// arguments take the parameters' defaults. `program` holds the functions that `func` may call.
+pub fn eval_function(program: &[Function], func: &Function, args: Vec<RuntimeValue>) -> Result<RuntimeValue> {
+    call_function(program, func, args, 0)
+}
+
+/// Like `eval_function`, for a call nested in `depth` others
+fn call_function(program: &[Function], func: &Function, mut args: Vec<RuntimeValue>, depth: usize) -> Result<RuntimeValue> {
+    check_arg_count(func, args.len())?;
+
+    let mut frame = Frame { depth, module: func.module, ..Frame::new(program) };
+    for (param, default) in func.parameters.iter().zip(func.defaults.iter()).skip(args.len()) {
+        // Defaults are constant expressions, so they don't need the caller's variables
+        let default = default.as_ref().unwrap().eval(&mut frame)?;
+        args.push(default.widen_to(&param.dtype));
+    }
+    for (param, arg) in func.parameters.iter().zip(args) {
+        frame.declare(param.name.clone(), arg.widen_to(&param.dtype));
+    }
+
+    match exec_block(&func.body, &mut frame)? {
+        Flow::Return(value) => Ok(value.widen_to(func.return_type())),
+        Flow::Normal => Ok(RuntimeValue::Unit),
+        Flow::Break | Flow::Continue => Err(anyhow!("`break` or `continue` outside of a loop in `{}`", func.name)),
+    }
+}
+
+/// Parses command-line arguments against the parameter types of `func`
+pub fn parse_args(func: &Function, args: &[String]) -> Result<Vec<RuntimeValue>> {
+    check_arg_count(func, args.len())?;
+
+    func.parameters.iter()
+        .zip(args)
+        .map(|(param, arg)| {
+            RuntimeValue::parse(arg, &param.dtype)
+                .map_err(|e| anyhow!("invalid argument for parameter `{}`: {}", param.name, e))
+        })
+        .collect()
+}
+
+/// Runs the program's `main` function with the given command-line arguments
+pub fn run_main(funcs: &[Function], args: &[String]) -> Result<RuntimeValue> {
+    // Imported files may have a `main` of their own
+    let main = funcs.iter()
+        .find(|f| f.name == "main" && f.module == 0)
+        .ok_or_else(|| anyhow!("no `main` function to run"))?;
+
+    let args = parse_args(main, args)?;
+    eval_function(funcs, main, args)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::compile_str;
+
+    /// Compiles `src` and returns what its `main` evaluates to with `args`
+    fn run(src: &str, args: &[&str]) -> RuntimeValue {
+        let funcs = compile_str(src).unwrap();
+        run_main(&funcs, &args.iter().map(|a| a.to_string()).collect::<Vec<String>>()).unwrap()
+    }
+
+    #[test]
+    fn omitted_arguments_take_their_default() {
+        let src = "fn greet(int a, int times = 3) int {\n    return a * times\n}\nfn main() int {\n    return greet(2)\n}\n";
+        assert_eq!(run(src, &[]), RuntimeValue::Int(6));
+    }
+
+    #[test]
+    fn returned_values_convert_like_assigned_ones() {
+        let src = "fn s() string {\n    return \"hi\"\n}\nfn main() float {\n    return 1\n}\n";
+        assert_eq!(run(src, &[]), RuntimeValue::Float(1.0));
+    }
+
+    #[test]
+    fn compound_assignments_update_their_target() {
+        let src = "fn main(int x) int {\n    x *= 3\n    x -= 1\n    x %= 4\n    x /= 2\n    return x\n}\n";
+        assert_eq!(run(src, &["5"]), RuntimeValue::Int(1));
+    }
+
+    #[test]
+    fn call_arguments_split_at_top_level_commas() {
+        let add = "fn add(int a, int b) int {\n    return a + b\n}\n";
+        let result = run(&format!("{}fn main() int {{\n    return add(1 + 2, 3 * 4)\n}}\n", add), &[]);
+        assert_eq!(result, RuntimeValue::Int(15));
+        let result = run(&format!("{}fn main() int {{\n    return add(add(1, 2), 3)\n}}\n", add), &[]);
+        assert_eq!(result, RuntimeValue::Int(6));
+    }
+
+    #[test]
+    fn a_single_trailing_comma_is_allowed() {
+        let src = "fn add(int a, int b) int {\n    return a + b\n}\nfn main() int {\n    int[] xs = [1, 2, 3,]\n    return add(xs[2], 2,)\n}\n";
+        assert_eq!(run(src, &[]), RuntimeValue::Int(5));
+    }
+
+    #[test]
+    fn blocks_yield_their_trailing_expression() {
+        let src = "fn main() int {\n    int x = {\n        int a = 1\n        a + 2\n    }\n    return x\n}\n";
+        assert_eq!(run(src, &[]), RuntimeValue::Int(3));
+    }
+
+    #[test]
+    fn chained_assignments_assign_every_target() {
+        let src = "fn main() int {\n    int a = 0\n    int b = 0\n    a = b = 5\n    return a + b\n}\n";
+        assert_eq!(run(src, &[]), RuntimeValue::Int(10));
+    }
+
+    #[test]
+    fn strings_concatenate_and_index_at_run_time() {
+        let funcs = compile_str("fn main() string {\n    string s = \"a\" + \"b\"\n    return s\n}\n").unwrap();
+        assert_eq!(run_main(&funcs, &[]).unwrap(), RuntimeValue::String("ab".to_string()));
+
+        let funcs = compile_str("fn main(int i) byte {\n    string s = \"ab\"\n    return s[i]\n}\n").unwrap();
+        assert_eq!(run_main(&funcs, &["1".to_string()]).unwrap(), RuntimeValue::Byte(b'b'));
+        let e = run_main(&funcs, &["2".to_string()]).unwrap_err();
+        assert_eq!(e.to_string(), "index 2 is out of bounds for string of length 2");
+    }
+
+    #[test]
+    fn else_if_chains_run_the_first_arm_that_holds() {
+        let src = "fn main(int x) int {\n    if x > 0 {\n        return 1\n    } else if x < 0 {\n        return 2\n    } else {\n        return 3\n    }\n}\n";
+        for (arg, expected) in [("5", 1), ("-5", 2), ("0", 3)] {
+            assert_eq!(run(src, &[arg]), RuntimeValue::Int(expected));
+        }
+    }
+
+    #[test]
+    fn if_expressions_take_the_value_of_their_branch() {
+        let src = "fn main(bool c) int {\n    int x = if c { 1 } else { 2 }\n    return x\n}\n";
+        for (arg, expected) in [("true", 1), ("false", 2)] {
+            assert_eq!(run(src, &[arg]), RuntimeValue::Int(expected));
+        }
+    }
+}
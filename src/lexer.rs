@@ -1,16 +1,22 @@
-use std::collections::HashSet;
+use std::{collections::HashSet, fmt::Display, sync::LazyLock};
+use anyhow::{Result, anyhow};
 use serde::{Serialize, Deserialize};
 use regex::Regex;
 
 
-#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Hash)]
 pub enum TokenType {
     ArithmeticOperator,
+    /// `!` or `~`, which unlike `-` and `+` have no binary form
+    UnaryOperator,
     AssignmentOperator,
     ComparisonOperator,
     IntegerLiteral,
     FloatLiteral,
     StringLiteral,
+    /// A `b"..."` or `hex"..."` literal, whose bytes aren't interpreted as UTF-8
+    ByteStringLiteral,
+    CharLiteral,
     BooleanLiteral,
     Unknown,
     Keyword,
@@ -26,17 +32,154 @@ pub enum TokenType {
     Period,
     RangeDescriptor,
     SemiColon,
+    QuestionMark,
+    Colon,
     NewLine,
+    Comment,
+    /// A `///` comment on a line of its own, which documents the function after it
+    DocComment,
+    /// Emitted once after the last token so parsers can report truncated input
+    Eof,
+}
+
+/// Location of a token in the source. `start` and `end` are byte offsets, while `line` and `col`
+/// are 1-based and refer to where the token starts (with tabs expanded to the lexer's tab width).
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub line: usize,
+    pub col: usize,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// Tokens compare equal only if their spans do too. `eq_ignore_span` compares just the type and
+/// text, e.g. for tokens lexed from different sources.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Hash)]
 pub struct Token<'a> {
     pub token_type: TokenType,
+    /// The token's text as written in the source
     pub value: &'a str,
+    pub span: Span,
+    /// The plain spelling of a number literal whose `value` isn't, such as `255` for `0xFF` or
+    /// `1000` for `1_000`, so that formatters can keep `value` and the parser needs neither
+    pub normalized: Option<String>,
+}
+
+impl Token<'_> {
+    /// Returns the keyword this token holds, if it's a keyword token
+    pub fn keyword(&self) -> Option<Keyword> {
+        match self.token_type {
+            TokenType::Keyword => Keyword::new(self.value),
+            _ => None,
+        }
+    }
+
+    /// Returns the plain spelling of the token's text, which is `value` unless it's a number
+    /// literal written with `_` separators or in hex
+    pub fn normalized_value(&self) -> &str {
+        self.normalized.as_deref().unwrap_or(self.value)
+    }
+
+    /// Returns true if the tokens have the same type and text, wherever they are in the source
+    pub fn eq_ignore_span(&self, other: &Token) -> bool {
+        self.token_type == other.token_type && self.value == other.value
+    }
+
+    /// Returns a copy of this token for `source`, where its text starts `shift` bytes later
+    fn moved_to<'b>(&self, source: &'b str, shift: isize) -> Token<'b> {
+        let start = (self.span.start as isize + shift) as usize;
+        let end = (self.span.end as isize + shift) as usize;
+        Token {
+            token_type: self.token_type,
+            value: &source[start..end],
+            span: Span { start, end, ..self.span },
+            normalized: self.normalized.clone(),
+        }
+    }
+
+    /// Returns the text of `src` that the token covers, which must be the source it was lexed
+    /// from. Unlike `value`, this isn't tied to the lexer's lifetime.
+    pub fn span_text<'s>(&self, src: &'s str) -> &'s str {
+        &src[self.span.start..self.span.end]
+    }
+}
+
+/// Whether a diagnostic stops compilation
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    /// Reported, but the program still compiles (and `--check` passes)
+    Warning,
+}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Severity::Error => write!(f, "Error"),
+            Severity::Warning => write!(f, "Warning"),
+        }
+    }
+}
+
+/// A problem found by `Lexer::validate_syntax`, located at the token it's about. Diagnostics from
+/// later phases may have no location, in which case their span is the default one.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    pub span: Span,
+}
+
+impl Diagnostic {
+    pub(crate) fn error(message: String, span: Span) -> Self {
+        Diagnostic { severity: Severity::Error, message, span }
+    }
+
+    pub(crate) fn warning(message: String, span: Span) -> Self {
+        Diagnostic { severity: Severity::Warning, message, span }
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // Lines are 1-based, so line 0 means there's no location to report
+        if self.span.line == 0 {
+            return write!(f, "{}: {}", self.severity, self.message);
+        }
+        write!(f, "{}: {} ({}:{})", self.severity, self.message, self.span.line, self.span.col)
+    }
+}
+
+pub const DEFAULT_TAB_WIDTH: usize = 4;
+
+/// Opens and closes a string literal that may span multiple lines
+pub const TRIPLE_QUOTE: &str = "\"\"\"";
+
+/// Controls which tokens that carry no meaning for the parser are emitted
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LexerOptions {
+    /// The block parser relies on `NewLine` tokens to separate statements, but expression-only
+    /// input such as a REPL line doesn't need them
+    pub emit_newlines: bool,
+    /// Emits `//` comments as `Comment` tokens instead of skipping them. Doc comments are always
+    /// emitted, since the parser attaches them to functions.
+    pub emit_comments: bool,
+}
+
+impl Default for LexerOptions {
+    fn default() -> Self {
+        LexerOptions {
+            emit_newlines: true,
+            emit_comments: false,
+        }
+    }
 }
 
 /// Preconditions:
-/// - All commented text must be striped before feeding source code to the lexer
 /// - Not required, but removing redundant whitespace characters could improve compilation speed
 #[derive(Default)]
 pub struct Lexer<'a> {
@@ -45,156 +188,360 @@ pub struct Lexer<'a> {
     pub tokens: Vec<Token<'a>>,
     pub variables: HashSet<&'a str>,
     pub functions: HashSet<&'a str>,
+    /// Number of columns a tab advances to (the next multiple of) when reporting locations
+    pub tab_width: usize,
+    /// Identifiers longer than this are reported by `validate_syntax`, as a warning
+    pub max_identifier_len: Option<usize>,
+    pub options: LexerOptions,
+    line: usize,
+    col: usize,
 }
 
 impl<'a> Lexer<'a> {
     pub fn new(s: &'a str) -> Self {
         Lexer {
             source: s,
+            tab_width: DEFAULT_TAB_WIDTH,
+            line: 1,
+            col: 1,
             ..Lexer::default()
         }
     }
 
-    pub fn next(&mut self) -> Option<Token> {
-        if self.pos >= self.source.len() {
-            return None;
-        }
+    /// Lexes raw bytes, failing with the offset (and line:col) of the first byte that isn't valid
+    /// UTF-8 instead of requiring the caller to decode the source first
+    pub fn from_bytes(bytes: &'a [u8]) -> Result<Self> {
+        Ok(Self::new(decode_utf8(bytes)?))
+    }
 
-        let source_bytes = self.source.as_bytes();
-        let mut counter = self.pos;
+    pub fn with_tab_width(mut self, tab_width: usize) -> Self {
+        assert!(tab_width > 0, "tab width must be at least 1");
+        self.tab_width = tab_width;
+        self
+    }
 
-        let mut token_type = TokenType::Unknown;
+    pub fn with_max_identifier_len(mut self, max_len: usize) -> Self {
+        self.max_identifier_len = Some(max_len);
+        self
+    }
 
-        while counter < self.source.len() {
-            let curr_char = source_bytes[counter] as char;
-            match token_type {
-                TokenType::Unknown => {
-                    if let Some((literal, lit_type)) = Self::starts_with_literal(&self.source[self.pos..]) {
-                        counter += literal;
-                        token_type = lit_type;
-                        break;
-                    }
-                    else if let Some((literal, lit_type)) = Self::starts_with_dots(&self.source[self.pos..]) {
-                        counter += literal;
-                        token_type = lit_type;
-                        break;
-                    }
-                    else if let Some(keyword_len) = Self::starts_with_kw(&self.source[self.pos..]) {
-                        token_type = TokenType::Keyword;
-                        counter += keyword_len;
-                        break;
-                    }
-                    else if let Some(dt_len) = Self::starts_with_dt(&self.source[self.pos..]) {
-                        token_type = TokenType::DataType;
-                        counter += dt_len;
-                        break;
-                    }
-                    else if let Some(op_len) = Self::starts_with_cmp_op(&self.source[self.pos..]) {
-                        token_type = TokenType::ComparisonOperator;
-                        counter += op_len;
-                        break;
-                    }
-                    else if let Some(op_len) = Self::starts_with_assign_op(&self.source[self.pos..]) {
-                        token_type = TokenType::AssignmentOperator;
-                        counter += op_len;
-                        break;
+    pub fn with_options(mut self, options: LexerOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Returns the (line, column) the next token will be scanned from
+    pub fn location(&self) -> (usize, usize) {
+        (self.line, self.col)
+    }
+
+    /// Advances the line/column counters over `text`, which must directly follow the current location
+    fn advance_location(&mut self, text: &str) {
+        let mut chars = text.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                // `\r\n` is a single line break, as is a lone `\r`
+                '\r' | '\n' => {
+                    if c == '\r' && chars.peek() == Some(&'\n') {
+                        chars.next();
                     }
-                    else if let Some(object_name_len) = Self::starts_with_object_name(&self.source[self.pos..]) {
-                        counter += object_name_len;
-                        let object_name = &self.source[self.pos..counter];
+                    self.line += 1;
+                    self.col = 1;
+                }
+                '\t' => self.col += self.tab_width - (self.col - 1) % self.tab_width,
+                _ => self.col += 1,
+            }
+        }
+    }
 
-                        if KEYWORDS.contains(&object_name) || DATA_TYPES.contains(&object_name) {
-                            panic!("object name cannot be a keyword or data-type. ")
+    /// Returns the number of tokens lexed so far
+    pub fn token_count(&self) -> usize {
+        self.tokens.len()
+    }
+
+    /// Returns the byte offset into `source` where the next token will be scanned from
+    pub fn position(&self) -> usize {
+        self.pos
+    }
+
+    /// Moves the scan position to `pos` without touching the tokens lexed so far
+    pub fn seek(&mut self, pos: usize) {
+        assert!(self.source.is_char_boundary(pos), "cannot seek to byte offset `{}`", pos);
+        self.pos = pos;
+        self.line = 1;
+        self.col = 1;
+        self.advance_location(&self.source[..pos]);
+    }
+
+    /// Resets the lexer to the start of its source, discarding all tokens and the
+    /// variables/functions they declared, so the source can be lexed again from scratch
+    pub fn rewind(&mut self) {
+        self.seek(0);
+        self.tokens.clear();
+        self.variables.clear();
+        self.functions.clear();
+    }
+
+    /// Returns a lexer with settings like this one's, for lexing `source`
+    fn with_settings_of<'b>(&self, source: &'b str) -> Lexer<'b> {
+        Lexer {
+            source,
+            tab_width: self.tab_width,
+            max_identifier_len: self.max_identifier_len,
+            options: self.options,
+            line: 1,
+            col: 1,
+            ..Lexer::default()
+        }
+    }
+
+    /// Lexes `new_source`, which must be this lexer's source with line `line` (1-based) edited,
+    /// reusing the tokens of every other line. This lexer must have been run to the end of its
+    /// source. The result is the same as lexing `new_source` from scratch, which is what happens
+    /// when the edit adds or removes a line, touches a multi-line string, or changes the names
+    /// the line declares.
+    pub fn relex_line<'b>(&self, new_source: &'b str, line: usize) -> Lexer<'b> {
+        let full_relex = || {
+            let mut lexer = self.with_settings_of(new_source);
+            while lexer.next().is_some() {}
+            lexer
+        };
+
+        let Some(old_start) = line_start(self.source, line) else { return full_relex() };
+        let old_end = self.source[old_start..].find(['\r', '\n']).map_or(self.source.len(), |i| old_start + i);
+        let suffix = &self.source[old_end..];
+        let unchanged = new_source.len() >= old_start + suffix.len() &&
+            new_source.starts_with(&self.source[..old_start]) &&
+            new_source.ends_with(suffix);
+        if !unchanged || self.tokens.last().is_none_or(|t| t.token_type != TokenType::Eof) {
+            return full_relex();
+        }
+        let new_end = new_source.len() - suffix.len();
+        let new_line = &new_source[old_start..new_end];
+        let old_line = &self.source[old_start..old_end];
+        if new_line.contains(['\r', '\n']) || new_line.contains(TRIPLE_QUOTE) || old_line.contains(TRIPLE_QUOTE) {
+            return full_relex();
+        }
+
+        let crosses = |t: &Token, offset: usize| t.span.start < offset && t.span.end > offset;
+        if self.tokens.iter().any(|t| crosses(t, old_start) || crosses(t, old_end)) {
+            return full_relex();
+        }
+        let before = self.tokens.iter().take_while(|t| t.span.start < old_start).count();
+        let after = before + self.tokens[before..].iter().take_while(|t| t.span.start < old_end).count();
+
+        // The edited line is lexed on its own, after the tokens before it, so it sees the names
+        // they declared
+        let mut lexer = self.with_settings_of(&new_source[..new_end]);
+        lexer.tokens = self.tokens[..before].iter().map(|t| t.moved_to(new_source, 0)).collect();
+        (lexer.variables, lexer.functions) = declared_names(&lexer.tokens);
+        lexer.seek(old_start);
+        while lexer.next().is_some() {}
+        lexer.tokens.pop();
+
+        let old_declared = declared_names(&self.tokens[..after]);
+        let new_declared = declared_names(&lexer.tokens);
+        let same_names = |old: &HashSet<&str>, new: &HashSet<&str>| old.len() == new.len() && old.iter().all(|n| new.contains(n));
+        if !same_names(&old_declared.0, &new_declared.0) || !same_names(&old_declared.1, &new_declared.1) {
+            return full_relex();
+        }
+
+        // Only the newline ending the edited line (or `Eof`) can start on it after the edit
+        let shift = new_end as isize - old_end as isize;
+        let end_col = lexer.col;
+        let mut tokens = lexer.tokens;
+        tokens.extend(self.tokens[after..].iter().map(|t| {
+            let mut moved = t.moved_to(new_source, shift);
+            if moved.span.line == line {
+                moved.span.col = end_col;
+            }
+            moved
+        }));
+        let mut lexer = self.with_settings_of(new_source);
+        (lexer.variables, lexer.functions) = declared_names(&tokens);
+        lexer.tokens = tokens;
+        lexer.seek(new_source.len());
+        lexer
+    }
+
+    // Tokens borrow the lexer's source, so this can't be an `Iterator`
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> Option<Token<'_>> {
+        // Suppressed tokens and trailing whitespace go round again, rather than recursing once per
+        // token skipped
+        loop {
+            if self.pos >= self.source.len() {
+                if self.tokens.last().is_some_and(|t| t.token_type == TokenType::Eof) {
+                    return None;
+                }
+                let span = Span { start: self.pos, end: self.pos, line: self.line, col: self.col };
+                let token = Token { token_type: TokenType::Eof, value: "", span, normalized: None };
+                self.tokens.push(token.clone());
+                return Some(token);
+            }
+
+            let source_bytes = self.source.as_bytes();
+            let mut counter = self.pos;
+
+            let mut token_type = TokenType::Unknown;
+
+            while counter < self.source.len() {
+                let curr_char = source_bytes[counter] as char;
+                match token_type {
+                    TokenType::Unknown => {
+                        if self.source[self.pos..].starts_with(TRIPLE_QUOTE) {
+                            // An unterminated string runs to the end of the source, and is reported
+                            // by `validate_syntax`
+                            counter += Self::triple_quoted_len(&self.source[self.pos..]).unwrap_or(self.source.len() - self.pos);
+                            token_type = TokenType::StringLiteral;
+                            break;
+                        }
+                        else if self.source[self.pos..].starts_with("//") {
+                            // The newline ending the comment is lexed as its own token
+                            counter += self.source[self.pos..].find(['\r', '\n']).unwrap_or(self.source.len() - self.pos);
+                            let line_start = self.source[..self.pos].rfind(['\r', '\n']).map_or(0, |i| i + 1);
+                            let on_own_line = self.source[line_start..self.pos].trim().is_empty();
+                            token_type = if on_own_line && self.source[self.pos..].starts_with("///") {
+                                TokenType::DocComment
+                            }
+                            else {
+                                TokenType::Comment
+                            };
+                            break;
+                        }
+                        else if let Some((literal, lit_type)) = Self::starts_with_literal(&self.source[self.pos..]) {
+                            counter += literal;
+                            token_type = lit_type;
+                            break;
+                        }
+                        else if let Some((literal, lit_type)) = Self::starts_with_dots(&self.source[self.pos..]) {
+                            counter += literal;
+                            token_type = lit_type;
+                            break;
                         }
+                        else if let Some(keyword_len) = Self::starts_with_kw(&self.source[self.pos..]) {
+                            token_type = TokenType::Keyword;
+                            counter += keyword_len;
+                            break;
+                        }
+                        else if let Some(dt_len) = Self::starts_with_dt(&self.source[self.pos..]) {
+                            token_type = TokenType::DataType;
+                            counter += dt_len;
+                            break;
+                        }
+                        else if let Some(op_len) = Self::starts_with_cmp_op(&self.source[self.pos..]) {
+                            token_type = TokenType::ComparisonOperator;
+                            counter += op_len;
+                            break;
+                        }
+                        else if let Some(op_len) = Self::starts_with_assign_op(&self.source[self.pos..]) {
+                            token_type = TokenType::AssignmentOperator;
+                            counter += op_len;
+                            break;
+                        }
+                        else if let Some(object_name_len) = Self::starts_with_object_name(&self.source[self.pos..]) {
+                            counter += object_name_len;
+                            let object_name = &self.source[self.pos..counter];
 
-                        if let Some(last_token) = self.tokens.last() {
-                            match last_token.token_type {
-                                TokenType::Keyword => {
-                                    match last_token.value {
-                                        "fn" => {
-                                            token_type = TokenType::Object;
-                                            self.functions.insert(object_name);
-
-                                        }
-                                        "for" => {
-                                            token_type = TokenType::Object;     
-                                            self.variables.insert(object_name);                                   
-                                        }
-                                        _ => {
-                                            if self.variables.contains(object_name) {
-                                                token_type = TokenType::Object;
-                                            }
-                                            else if self.functions.contains(object_name) {
-                                                token_type = TokenType::Object;
-                                            }
-                                            else {
-                                                panic!("Uninitialized object: `{}`", object_name);
-                                            }
-                                        }
+                            // Reserved words used as names are reported by `validate_syntax`, and
+                            // names that aren't declared by the parser
+                            token_type = TokenType::Object;
+                            let is_reserved = KEYWORDS.contains(&object_name) || DATA_TYPES.contains(&object_name);
+                            if let Some(last_token) = self.tokens.last().filter(|_| !is_reserved) {
+                                match (last_token.token_type, last_token.keyword()) {
+                                    (TokenType::Keyword, Some(Keyword::Fn)) => {
+                                        self.functions.insert(object_name);
                                     }
-                                }
-                                TokenType::DataType => {
-                                    token_type = TokenType::Object;
-                                    let func_name = &self.source[self.pos..counter];
-                                    self.variables.insert(func_name);
-                                }                                
-                                _ => {
-                                    if self.variables.contains(object_name) {
-                                        token_type = TokenType::Object;
+                                    // Module aliases are recorded with the variables, as the parser
+                                    // tells them apart by the `.` after them
+                                    (TokenType::Keyword, Some(Keyword::For | Keyword::As)) | (TokenType::DataType, _) => {
+                                        self.variables.insert(object_name);
                                     }
-                                    else if self.functions.contains(object_name) {
-                                        token_type = TokenType::Object;
-                                    }
-                                    else {
-                                        panic!("Uninitialized object: `{}`", object_name);
+                                    _ => {}
+                                }
+                            }
+                            break;
+                        }
+                        else if "[{()}],\r\n;?:".contains(curr_char) {
+                            counter += 1;
+                            match curr_char {
+                                '{' => token_type = TokenType::OpenCurlyBrace,
+                                '}' => token_type = TokenType::CloseCurlyBrace,
+                                '(' => token_type = TokenType::OpenParen,
+                                ')' => token_type = TokenType::CloseParen,
+                                '[' => token_type = TokenType::OpenSquareBracket,
+                                ']' => token_type = TokenType::CloseSquareBracket,
+                                ',' => token_type = TokenType::Comma,
+                                '\n' => token_type = TokenType::NewLine,
+                                // A `\r\n` line ending is a single token
+                                '\r' => {
+                                    if source_bytes.get(counter) == Some(&b'\n') {
+                                        counter += 1;
                                     }
-                                },
+                                    token_type = TokenType::NewLine;
+                                }
+                                ';' => token_type = TokenType::SemiColon,
+                                '?' => token_type = TokenType::QuestionMark,
+                                ':' => token_type = TokenType::Colon,
+                                _ => panic!("This should never run"),
                             }
+                            break;
                         }
-                        break;
-                    }
-                    else if "[{()}],\n;".contains(curr_char) {
-                        counter += 1;
-                        match curr_char {
-                            '{' => token_type = TokenType::OpenCurlyBrace,
-                            '}' => token_type = TokenType::CloseCurlyBrace,
-                            '(' => token_type = TokenType::OpenParen,
-                            ')' => token_type = TokenType::CloseParen,
-                            '[' => token_type = TokenType::OpenSquareBracket,
-                            ']' => token_type = TokenType::CloseSquareBracket,
-                            ',' => token_type = TokenType::Comma,
-                            '\n' => token_type = TokenType::NewLine,
-                            ';' => token_type = TokenType::SemiColon,
-                            _ => panic!("This should never run"),
+                        else if let Some(op) = ARITHMETIC_OPERATORS.iter().find(|op| self.source[self.pos..].starts_with(**op)) {
+                            counter += op.len();
+                            token_type = TokenType::ArithmeticOperator;
+                            break;
+                        }
+                        // After comparisons, so that `!=` is a single token
+                        else if "!~".contains(curr_char) {
+                            counter += 1;
+                            token_type = TokenType::UnaryOperator;
+                            break;
+                        }
+                        else if "\t ".contains(curr_char) {
+                            counter += 1;
+                            self.advance_location(&self.source[self.pos..counter]);
+                            self.pos += 1;
+                            assert_eq!(self.pos, counter);
+                            continue;
+                        }
+                        else {
+                            panic!("No condition parsing met at {}:{}!\nString: `{}`\nFaulty Index: `{}`", self.line, self.col, &self.source[self.pos..], counter-self.pos);
                         }
-                        break;
-                    }
-                    else if "+-*/%".contains(curr_char) {
-                        counter += 1;
-                        token_type = TokenType::ArithmeticOperator;
-                        break;
-                    }
-                    else if "\t ".contains(curr_char) {
-                        counter += 1;
-                        self.pos += 1;
-                        assert_eq!(self.pos, counter);
-                        continue;
-                    }
-                    else {
-                        panic!("No condition parsing met!\nString: `{}`\nFaulty Index: `{}`", &self.source[self.pos..], counter-self.pos);
                     }
+                    _ => panic!("Not implemented"),
                 }
-                _ => panic!("Not implemented"),
             }
-        }
+            // Only whitespace was left
+            if token_type == TokenType::Unknown {
+                continue;
+            }
 
-        let res = &self.source[self.pos..counter];
-        self.pos = counter;
+            let res = &self.source[self.pos..counter];
+            let span = Span {
+                start: self.pos,
+                end: counter,
+                line: self.line,
+                col: self.col,
+            };
+            self.advance_location(res);
+            self.pos = counter;
 
-        let token = Token { token_type: token_type, value: res };
+            let suppressed = match token_type {
+                TokenType::NewLine => !self.options.emit_newlines,
+                TokenType::Comment => !self.options.emit_comments,
+                _ => false,
+            };
+            if suppressed {
+                continue;
+            }
 
-        self.tokens.push(token.clone());
-        Some(token)
+            let normalized = normalize_number(res, token_type);
+            let token = Token { token_type, value: res, span, normalized };
+
+            self.tokens.push(token.clone());
+            return Some(token);
+        }
     }
 
     fn starts_with_dt(s: &str) -> Option<usize> {
@@ -203,12 +550,20 @@ impl<'a> Lexer<'a> {
         for &dt in DATA_TYPES.iter() {
             if s.starts_with(dt) {
                 let mut dt_len = dt.len();
-                if !(char::is_whitespace(s_bytes[dt.len()] as char) || "[>".contains(s_bytes[dt.len()] as char)) {
+                // Types in a tuple type such as `(int, bool)` are followed by `,` or `)`, and the
+                // type of a cast such as `1e3 as int` may end a statement or block
+                let ends_type = s_bytes.get(dt.len()).is_none_or(|&b| char::is_whitespace(b as char) || "[>,);]}".contains(b as char));
+                if !ends_type {
                     continue;
                 }
 
-                while dt_len+1 < s.len() && s_bytes[dt_len] as char == '[' && s_bytes[dt_len+1] as char == ']' {
-                    dt_len += 2;
+                // `[]` for a vector, or `[len]` for a fixed-size array
+                while s_bytes.get(dt_len) == Some(&b'[') {
+                    let len_digits = s_bytes[dt_len+1..].iter().take_while(|b| b.is_ascii_digit() || **b == b'_').count();
+                    if s_bytes.get(dt_len + 1 + len_digits) != Some(&b']') {
+                        break;
+                    }
+                    dt_len += len_digits + 2;
                 }
                 
                 return Some(dt_len);
@@ -222,7 +577,7 @@ impl<'a> Lexer<'a> {
         let s_bytes = s.as_bytes();
         for &kw in KEYWORDS.iter() {
             if s.starts_with(kw) {
-                if !char::is_whitespace(s_bytes[kw.len()] as char) {
+                if s_bytes.get(kw.len()).is_some_and(|&b| !char::is_whitespace(b as char)) {
                     continue;
                 }
                 return Some(kw.len());
@@ -232,20 +587,44 @@ impl<'a> Lexer<'a> {
         None
     }
 
+    /// Returns the length of the triple-quoted string at the start of `s`, including both sets of
+    /// quotes. The string may span several lines, and its newlines are kept in the token's value.
+    fn triple_quoted_len(s: &str) -> Option<usize> {
+        let body = &s[TRIPLE_QUOTE.len()..];
+        body.find(TRIPLE_QUOTE).map(|end| end + 2 * TRIPLE_QUOTE.len())
+    }
+
+    // Each pattern is compiled once, on first use, as compiling one costs far more than matching it
     fn starts_with_literal(s: &str) -> Option<(usize, TokenType)> {
-        
-        let re_int = Regex::new(r"^(([0-9])([0-9]|_)*)|(-([0-9]|_)+)").unwrap();
-        if let Some(mat) = re_int.find(s) {
+        // Hex floats such as `0x1.8p3`. A missing exponent is reported by `validate_syntax`.
+        static RE_HEX_FP: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"^0[xX][0-9a-fA-F_]*(\.[0-9a-fA-F_]*([pP][+-]?[0-9_]*)?|[pP][+-]?[0-9_]*)").unwrap());
+        if let Some(mat) = RE_HEX_FP.find(s) {
+            return Some((mat.as_str().len(), TokenType::FloatLiteral));
+        }
+
+        static RE_HEX_INT: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"^0[xX][0-9a-fA-F][0-9a-fA-F_]*").unwrap());
+        if let Some(mat) = RE_HEX_INT.find(s) {
+            return Some((mat.as_str().len(), TokenType::IntegerLiteral));
+        }
+
+        // Scientific notation such as `1e-5` or `2.5E3`, which needs no decimal point
+        static RE_EXP: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"^([0-9][0-9_]*(\.[0-9_]*)?|\.[0-9][0-9_]*)[eE][+-]?[0-9][0-9_]*").unwrap());
+        if let Some(mat) = RE_EXP.find(s) {
+            return Some((mat.as_str().len(), TokenType::FloatLiteral));
+        }
+
+        static RE_INT: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"^[0-9]([0-9]|_)*").unwrap());
+        if let Some(mat) = RE_INT.find(s) {
             let l = mat.as_str().len();
             let s_bytes = s.as_bytes();
-            if s_bytes[l] as char != '.' {
+            if s_bytes.get(l) != Some(&b'.') {
                 return Some((l, TokenType::IntegerLiteral));
             }
         }
 
         if !s.starts_with("..") {
-            let re_fp = Regex::new(r"^([0-9]|-|\.)([0-9]|\.|_)*").unwrap();
-            if let Some(mat) = re_fp.find(s) {
+            static RE_FP: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"^([0-9]|\.)([0-9]|\.|_)*").unwrap());
+            if let Some(mat) = RE_FP.find(s).filter(|m| m.as_str().chars().any(|c| c.is_ascii_digit())) {
                 if mat.as_str().chars().map(|c| if c == '.' {1} else {0}).sum::<i32>() > 1 {
                     let num = mat.as_str().split_once(".").unwrap().0;
                     return Some((num.len(), TokenType::IntegerLiteral));
@@ -254,13 +633,25 @@ impl<'a> Lexer<'a> {
             }
         }
 
-        let re_str = Regex::new(r#"^"[^\n]*""#).unwrap();
-        if let Some(mat) = re_str.find(s) {
+        // Like strings, the contents of byte strings are checked by `validate_syntax`
+        static RE_BYTES: LazyLock<Regex> = LazyLock::new(|| Regex::new(r#"^(b"(\\.|[^"\\\r\n])*"|hex"[^"\r\n]*")"#).unwrap());
+        if let Some(mat) = RE_BYTES.find(s) {
+            return Some((mat.as_str().len(), TokenType::ByteStringLiteral));
+        }
+
+        // Escapes are only matched here so that `\"` doesn't end the literal, and are checked by `validate_syntax`
+        static RE_STR: LazyLock<Regex> = LazyLock::new(|| Regex::new(r#"^"(\\.|[^"\\\r\n])*""#).unwrap());
+        if let Some(mat) = RE_STR.find(s) {
             return Some((mat.as_str().len(), TokenType::StringLiteral));
         }
 
-        let re_bool = Regex::new(r"^(true|false)").unwrap();
-        if let Some(mat) = re_bool.find(s) {
+        static RE_CHAR: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"^'(\\.|[^'\\\r\n])*'").unwrap());
+        if let Some(mat) = RE_CHAR.find(s) {
+            return Some((mat.as_str().len(), TokenType::CharLiteral));
+        }
+
+        static RE_BOOL: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"^(true|false)").unwrap());
+        if let Some(mat) = RE_BOOL.find(s) {
             return Some((mat.as_str().len(), TokenType::BooleanLiteral));
         }
 
@@ -268,7 +659,7 @@ impl<'a> Lexer<'a> {
     }
 
     fn starts_with_cmp_op(s: &str) -> Option<usize> {
-        let operators = vec![
+        let operators = [
             "<=",
             ">=",
             "==",
@@ -287,24 +678,24 @@ impl<'a> Lexer<'a> {
     }
 
     fn starts_with_assign_op(s: &str) -> Option<usize> {
-        let assign_ops = [":=", "="];
-        for &op in assign_ops.iter() {
-            if s.starts_with(op) {
-                return Some(op.len());
-            }
+        // Every arithmetic operator has a compound form such as `+=`
+        let compound = ARITHMETIC_OPERATORS.iter()
+            .find(|op| s.strip_prefix(**op).is_some_and(|rest| rest.starts_with('=')));
+        if let Some(op) = compound {
+            return Some(op.len() + 1);
         }
 
-        None
+        [":=", "="].iter().find(|op| s.starts_with(**op)).map(|op| op.len())
     }
 
     fn starts_with_dots(s: &str) -> Option<(usize, TokenType)> {
-        let re_period = Regex::new(r"^\.[a-zA-Z_]").unwrap();
-        if let Some(mat) = re_period.find(s) {
+        static RE_PERIOD: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"^\.[a-zA-Z_]").unwrap());
+        if let Some(mat) = RE_PERIOD.find(s) {
             return Some((1, TokenType::Period));
         }
 
-        let re_range = Regex::new(r"^\.\.=?").unwrap();
-        if let Some(mat) = re_range.find(s) {
+        static RE_RANGE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"^\.\.=?").unwrap());
+        if let Some(mat) = RE_RANGE.find(s) {
             return Some((mat.as_str().len(), TokenType::RangeDescriptor));
         }
 
@@ -312,8 +703,8 @@ impl<'a> Lexer<'a> {
     }
 
     fn starts_with_object_name(s: &str) -> Option<usize> {
-        let re = Regex::new(r"^[a-zA-Z_](\w|_)*").unwrap();
-        let mat = re.find(s);
+        static RE_NAME: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"^[a-zA-Z_](\w|_)*").unwrap());
+        let mat = RE_NAME.find(s);
 
         if let Some(mat) = mat {
             let r: std::ops::Range<usize> = mat.range();
@@ -325,7 +716,9 @@ impl<'a> Lexer<'a> {
         None
     }
 
-    pub fn validate_syntax(&self) -> Vec<String> {
+    /// Checks the lexed tokens for problems the lexer doesn't stop at. Only diagnostics with
+    /// `Severity::Error` make a program invalid.
+    pub fn validate_syntax(&self) -> Vec<Diagnostic> {
         let mut errors = vec![];
 
         let mut num_paren = 0;
@@ -333,6 +726,63 @@ impl<'a> Lexer<'a> {
         let mut num_brack = 0;
 
         for (i, tok) in self.tokens.iter().enumerate() {
+            // Names are declared after a data type or `fn`, and a reserved word there was lexed as
+            // a keyword or data type rather than an object
+            let declares_name = i > 0 && (
+                self.tokens[i-1].token_type == TokenType::DataType || 
+                self.tokens[i-1].keyword() == Some(Keyword::Fn)
+            );
+            let is_reserved = KEYWORDS.contains(&tok.value) || DATA_TYPES.contains(&tok.value);
+            let declares_variable = i > 0 && self.tokens[i-1].token_type == TokenType::DataType;
+            if is_reserved && declares_variable && [TokenType::Keyword, TokenType::DataType].contains(&tok.token_type) {
+                let kind = if tok.token_type == TokenType::DataType { "type" } else { "keyword" };
+                errors.push(Diagnostic::error(format!("expected variable name, found {} `{}`", kind, tok.value), tok.span));
+            }
+            else if is_reserved && (tok.token_type == TokenType::Object || (declares_name && [TokenType::Keyword, TokenType::DataType].contains(&tok.token_type))) {
+                errors.push(Diagnostic::error(format!("reserved word `{}` cannot be used as a name", tok.value), tok.span));
+            }
+
+            if let Some(max_len) = self.max_identifier_len {
+                if tok.token_type == TokenType::Object && tok.value.len() > max_len {
+                    errors.push(Diagnostic::warning(format!("identifier is longer than {} characters", max_len), tok.span));
+                }
+            }
+
+            // The closing quotes can't overlap the opening ones, as in `""""`
+            let is_triple_quoted = tok.token_type == TokenType::StringLiteral && tok.value.starts_with(TRIPLE_QUOTE);
+            if is_triple_quoted && (tok.value.len() < 2 * TRIPLE_QUOTE.len() || !tok.value.ends_with(TRIPLE_QUOTE)) {
+                errors.push(Diagnostic::error(format!("unterminated `{}` string", TRIPLE_QUOTE), tok.span));
+            }
+
+            let is_quoted = tok.token_type == TokenType::CharLiteral ||
+                (tok.token_type == TokenType::StringLiteral && !tok.value.starts_with(TRIPLE_QUOTE));
+            if is_quoted {
+                if let Err(e) = unescape(&tok.value[1..tok.value.len()-1]) {
+                    errors.push(Diagnostic::error(e.to_string(), tok.span));
+                }
+            }
+
+            if tok.token_type == TokenType::ByteStringLiteral {
+                if let Err(e) = parse_byte_string(tok.value) {
+                    errors.push(Diagnostic::error(e.to_string(), tok.span));
+                }
+            }
+
+            if tok.token_type == TokenType::DataType {
+                let lens = tok.value.split('[').skip(1).map(|len| len.trim_end_matches(']')).filter(|len| !len.is_empty());
+                for len in lens {
+                    if let Err(e) = parse_array_len(len) {
+                        errors.push(Diagnostic::error(e.to_string(), tok.span));
+                    }
+                }
+            }
+
+            if tok.token_type == TokenType::FloatLiteral && (tok.value.starts_with("0x") || tok.value.starts_with("0X")) {
+                if let Err(e) = parse_hex_float(tok.value) {
+                    errors.push(Diagnostic::error(e.to_string(), tok.span));
+                }
+            }
+
             match tok.token_type {
                 TokenType::OpenParen => num_paren += 1,                
                 TokenType::OpenCurlyBrace => num_brace += 1,
@@ -340,24 +790,24 @@ impl<'a> Lexer<'a> {
                 TokenType::CloseParen => {
                     num_paren -= 1;
                     if num_paren < 0 {
-                        errors.push(format!("[Token {}] Error: too many close parenthesis", i));
+                        errors.push(Diagnostic::error("too many close parenthesis".to_string(), tok.span));
                     }
                 },                
                 TokenType::CloseCurlyBrace => {
                     num_brace -= 1;
                     if num_brace < 0 {
-                        errors.push(format!("[Token {}] Error: too many close curly braces", i));
+                        errors.push(Diagnostic::error("too many close curly braces".to_string(), tok.span));
                     }
                 },             
                 TokenType::CloseSquareBracket => {
                     num_brack -= 1;
                     if num_brack < 0 {
-                        errors.push(format!("[Token {}] Error: too many close square brackets", i));
+                        errors.push(Diagnostic::error("too many close square brackets".to_string(), tok.span));
                     }
                 },
                 TokenType::RangeDescriptor => {
                     if i == 0 || i >= self.tokens.len()-1 {
-                        errors.push(format!("[Token {}] Error: Invalid range descriptor", i));
+                        errors.push(Diagnostic::error("Invalid range descriptor".to_string(), tok.span));
                         continue;
                     }
 
@@ -365,7 +815,7 @@ impl<'a> Lexer<'a> {
                         ![TokenType::IntegerLiteral, TokenType::Object].contains(&self.tokens[i-1].token_type) || 
                         ![TokenType::IntegerLiteral, TokenType::Object].contains(&self.tokens[i+1].token_type) 
                     {
-                        errors.push(format!("[Token {}] Error: Invalid range descriptor", i));
+                        errors.push(Diagnostic::error("Invalid range descriptor".to_string(), tok.span));
                     }
                 }          
 
@@ -378,8 +828,12 @@ impl<'a> Lexer<'a> {
 
 }
 
-pub const DATA_TYPES: [&str; 6] = [
+/// Operators lexed as `ArithmeticOperator`. Longer operators must come before their prefixes.
+pub const ARITHMETIC_OPERATORS: [&str; 5] = ["+", "-", "*", "/", "%"];
+
+pub const DATA_TYPES: [&str; 7] = [
     "int",
+    "uint",
     "float",
     "bool",
     "char",
@@ -387,11 +841,653 @@ pub const DATA_TYPES: [&str; 6] = [
     "string",
 ];
 
-pub const KEYWORDS: [&str; 6] = [
-    "fn",
-    "if",
-    "for",
-    "while",
-    "return",
-    "in",
+/// Renders `tokens` back into source that lexes to the same tokens, though not at the same
+/// positions: tokens are separated by a space except after a `.`, which must touch the field
+/// name after it. An `Eof` token renders as nothing.
+pub fn tokens_to_source(tokens: &[Token]) -> String {
+    let mut source = String::new();
+    for (i, tok) in tokens.iter().enumerate() {
+        if i > 0 && tokens[i-1].token_type != TokenType::Period && tok.token_type != TokenType::Eof {
+            source.push(' ');
+        }
+        source.push_str(tok.value);
+    }
+    source
+}
+
+/// Returns the byte offset and length of each line break in `source`: a `\r\n`, `\n` or lone `\r`
+fn line_breaks(source: &str) -> impl Iterator<Item = (usize, usize)> + '_ {
+    let bytes = source.as_bytes();
+    let mut i = 0;
+    std::iter::from_fn(move || {
+        while i < bytes.len() {
+            let start = i;
+            i += 1;
+            match bytes[start] {
+                b'\r' if bytes.get(i) == Some(&b'\n') => {
+                    i += 1;
+                    return Some((start, 2));
+                }
+                b'\r' | b'\n' => return Some((start, 1)),
+                _ => {}
+            }
+        }
+        None
+    })
+}
+
+/// Returns the byte offset where line `line` (1-based) of `source` starts
+fn line_start(source: &str, line: usize) -> Option<usize> {
+    if line == 1 {
+        return Some(0);
+    }
+    line_breaks(source).nth(line.checked_sub(2)?).map(|(i, len)| i + len)
+}
+
+/// Returns the variables and functions that `tokens` declare, the same way the lexer records
+/// them while scanning: a name after a data type, `for` or `as` is a variable, and one after
+/// `fn` a function
+fn declared_names<'a>(tokens: &[Token<'a>]) -> (HashSet<&'a str>, HashSet<&'a str>) {
+    let (mut variables, mut functions) = (HashSet::new(), HashSet::new());
+    for pair in tokens.windows(2) {
+        if pair[1].token_type != TokenType::Object {
+            continue;
+        }
+        match (pair[0].token_type, pair[0].keyword()) {
+            (TokenType::DataType, _) | (_, Some(Keyword::For | Keyword::As)) => { variables.insert(pair[1].value); }
+            (_, Some(Keyword::Fn)) => { functions.insert(pair[1].value); }
+            _ => {}
+        }
+    }
+    (variables, functions)
+}
+
+/// Decodes `bytes` as UTF-8, reporting where the first invalid byte is
+pub fn decode_utf8(bytes: &[u8]) -> Result<&str> {
+    std::str::from_utf8(bytes).map_err(|e| {
+        let offset = e.valid_up_to();
+        // Everything before `offset` is valid, so the line/column can be counted over it
+        let valid = std::str::from_utf8(&bytes[..offset]).unwrap();
+        let line = line_breaks(valid).count() + 1;
+        let line_start = line_breaks(valid).last().map_or(0, |(i, len)| i + len);
+        let col = valid[line_start..].chars().count() + 1;
+        anyhow!("invalid UTF-8 byte `0x{:02X}` at offset {} ({}:{})", bytes[offset], offset, line, col)
+    })
+}
+
+/// Replaces the escape sequences in the body of a char or string literal with the characters
+/// they stand for. Besides `\n`, `\r`, `\t`, `\0`, `\\`, `\'` and `\"`, this accepts `\xHH` for
+/// an ASCII character and `\u{...}` (1-6 hex digits) for any Unicode scalar value.
+pub fn unescape(body: &str) -> Result<String> {
+    let mut res = String::with_capacity(body.len());
+    let mut chars = body.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            res.push(c);
+            continue;
+        }
+
+        let escaped = match chars.next() {
+            Some('n') => '\n',
+            Some('r') => '\r',
+            Some('t') => '\t',
+            Some('0') => '\0',
+            Some(c @ ('\\' | '\'' | '"')) => c,
+            Some('x') => {
+                let digits = chars.by_ref().take(2).collect::<String>();
+                match u8::from_str_radix(&digits, 16) {
+                    Ok(b) if digits.len() == 2 && b.is_ascii() => b as char,
+                    Ok(_) if digits.len() == 2 => return Err(anyhow!("`\\x{}` is not an ASCII character; use `\\u{{...}}` instead", digits)),
+                    _ => return Err(anyhow!("`\\x` must be followed by two hex digits, found `{}`", digits)),
+                }
+            }
+            Some('u') => {
+                let rest = chars.as_str();
+                let digits = rest.strip_prefix('{')
+                    .and_then(|r| r.split_once('}'))
+                    .map(|(digits, _)| digits)
+                    .filter(|d| (1..=6).contains(&d.len()) && d.chars().all(|c| c.is_ascii_hexdigit()))
+                    .ok_or_else(|| anyhow!("`\\u` must be followed by 1-6 hex digits in braces"))?;
+                chars = rest[digits.len() + 2..].chars();
+
+                let code_point = u32::from_str_radix(digits, 16).unwrap();
+                char::from_u32(code_point)
+                    .ok_or_else(|| anyhow!("`\\u{{{}}}` is not a valid unicode code point", digits))?
+            }
+            Some(c) => return Err(anyhow!("unknown escape sequence `\\{}`", c)),
+            None => return Err(anyhow!("unterminated escape sequence")),
+        };
+        res.push(escaped);
+    }
+
+    Ok(res)
+}
+
+/// Decodes a byte string literal. `hex"00FF"` is pairs of hex digits, optionally separated by
+/// `_` or spaces. `b"\x00\xFF"` holds ASCII characters and the escapes of strings, except that
+/// `\xHH` may be any byte and there is no `\u{...}`.
+pub fn parse_byte_string(text: &str) -> Result<Vec<u8>> {
+    if let Some(digits) = text.strip_prefix("hex\"").and_then(|t| t.strip_suffix('"')) {
+        let digits = digits.chars().filter(|c| *c != '_' && *c != ' ').collect::<Vec<char>>();
+        if let Some(c) = digits.iter().find(|c| !c.is_ascii_hexdigit()) {
+            return Err(anyhow!("invalid hex digit `{}` in `{}`", c, text));
+        }
+        if digits.len() % 2 != 0 {
+            return Err(anyhow!("`{}` has an odd number of hex digits", text));
+        }
+        let bytes = digits.chunks(2)
+            .map(|pair| u8::from_str_radix(&pair.iter().collect::<String>(), 16).unwrap())
+            .collect();
+        return Ok(bytes);
+    }
+
+    let body = text.strip_prefix("b\"").and_then(|t| t.strip_suffix('"'))
+        .ok_or_else(|| anyhow!("malformed byte string literal: `{}`", text))?;
+    let mut res = Vec::with_capacity(body.len());
+    let mut chars = body.chars();
+    while let Some(c) = chars.next() {
+        if !c.is_ascii() {
+            return Err(anyhow!("byte strings can only contain ASCII characters, found `{}`; use `\\xHH` escapes", c));
+        }
+        if c != '\\' {
+            res.push(c as u8);
+            continue;
+        }
+
+        let escaped = match chars.next() {
+            Some('n') => b'\n',
+            Some('r') => b'\r',
+            Some('t') => b'\t',
+            Some('0') => b'\0',
+            Some(c @ ('\\' | '\'' | '"')) => c as u8,
+            Some('x') => {
+                let digits = chars.by_ref().take(2).collect::<String>();
+                match u8::from_str_radix(&digits, 16) {
+                    Ok(b) if digits.len() == 2 => b,
+                    _ => return Err(anyhow!("`\\x` must be followed by two hex digits, found `{}`", digits)),
+                }
+            }
+            Some(c) => return Err(anyhow!("unknown escape sequence `\\{}` in byte string", c)),
+            None => return Err(anyhow!("unterminated escape sequence")),
+        };
+        res.push(escaped);
+    }
+    Ok(res)
+}
+
+/// Parses the length between the brackets of a fixed-size array type such as `int[4]`, which
+/// must be a positive integer
+pub fn parse_array_len(text: &str) -> Result<usize> {
+    match text.replace('_', "").parse::<usize>() {
+        Ok(0) => Err(anyhow!("array length must be positive, found `{}`", text)),
+        Ok(len) => Ok(len),
+        Err(_) => Err(anyhow!("array length `{}` is not a valid integer", text)),
+    }
+}
+
+/// Decodes a hex float literal such as `0x1.8p3`: a hex mantissa, optionally with a fractional
+/// part, scaled by the power of two after `p`. The exponent is required.
+pub fn parse_hex_float(text: &str) -> Result<f64> {
+    let digits = text.replace('_', "");
+    let body = digits.strip_prefix("0x").or_else(|| digits.strip_prefix("0X"))
+        .ok_or_else(|| anyhow!("malformed hex float literal `{}`", text))?;
+    let (mantissa, exponent) = body.split_once(['p', 'P'])
+        .ok_or_else(|| anyhow!("hex float literal `{}` is missing a `p` exponent", text))?;
+    let (int_part, frac_part) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+    if int_part.is_empty() && frac_part.is_empty() {
+        return Err(anyhow!("hex float literal `{}` has no digits", text));
+    }
+    let exponent = exponent.parse::<i32>()
+        .map_err(|_| anyhow!("hex float literal `{}` has a malformed exponent", text))?;
+
+    let mut value = 0.0;
+    for c in int_part.chars().chain(frac_part.chars()) {
+        let digit = c.to_digit(16).ok_or_else(|| anyhow!("malformed hex float literal `{}`", text))?;
+        value = value * 16.0 + digit as f64;
+    }
+    Ok(value * 2f64.powi(exponent - 4 * frac_part.len() as i32))
+}
+
+/// Returns the plain spelling of an integer literal such as `-1_000` or `0xFF`, without `_`
+/// separators and in decimal, or `None` if it's already spelled that way. Hex literals too
+/// large for any integer type are left as they are, for the parser to reject.
+pub fn normalize_int_literal(text: &str) -> Option<String> {
+    let (sign, magnitude) = text.strip_prefix('-').map_or(("", text), |m| ("-", m));
+    let digits = magnitude.replace('_', "");
+    let normalized = match digits.strip_prefix("0x").or_else(|| digits.strip_prefix("0X")) {
+        Some(hex) => format!("{}{}", sign, u128::from_str_radix(hex, 16).ok()?),
+        None => format!("{}{}", sign, digits),
+    };
+    Some(normalized).filter(|n| n != text)
+}
+
+/// The `Token::normalized` spelling of a token's text: number literals lose their `_`
+/// separators, and hex integers are written in decimal. Hex floats keep their spelling, as
+/// it's exact where a decimal one may not be.
+fn normalize_number(text: &str, token_type: TokenType) -> Option<String> {
+    match token_type {
+        TokenType::IntegerLiteral => normalize_int_literal(text),
+        TokenType::FloatLiteral => Some(text.replace('_', "")).filter(|n| n != text),
+        _ => None,
+    }
+}
+
+/// Functions that are always available without being declared
+pub const BUILTINS: [&str; 2] = [
+    "print",
+    "len",
 ];
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum Keyword {
+    Fn,
+    If,
+    Else,
+    For,
+    While,
+    Return,
+    In,
+    Const,
+    Pub,
+    Break,
+    Continue,
+    Import,
+    As,
+}
+
+impl Keyword {
+    pub const ALL: [Keyword; 13] = [
+        Keyword::Fn,
+        Keyword::If,
+        Keyword::Else,
+        Keyword::For,
+        Keyword::While,
+        Keyword::Return,
+        Keyword::In,
+        Keyword::Const,
+        Keyword::Pub,
+        Keyword::Break,
+        Keyword::Continue,
+        Keyword::Import,
+        Keyword::As,
+    ];
+
+    pub fn new(s: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|kw| kw.as_str() == s)
+    }
+
+    pub const fn as_str(&self) -> &'static str {
+        match self {
+            Keyword::Fn => "fn",
+            Keyword::If => "if",
+            Keyword::Else => "else",
+            Keyword::For => "for",
+            Keyword::While => "while",
+            Keyword::Return => "return",
+            Keyword::In => "in",
+            Keyword::Const => "const",
+            Keyword::Pub => "pub",
+            Keyword::Break => "break",
+            Keyword::Continue => "continue",
+            Keyword::Import => "import",
+            Keyword::As => "as",
+        }
+    }
+}
+
+pub const KEYWORDS: [&str; Keyword::ALL.len()] = {
+    let mut keywords = [""; Keyword::ALL.len()];
+    let mut i = 0;
+    while i < Keyword::ALL.len() {
+        keywords[i] = Keyword::ALL[i].as_str();
+        i += 1;
+    }
+    keywords
+};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use TokenType::*;
+
+    fn lex(src: &str) -> Lexer<'_> {
+        let mut lexer = Lexer::new(src);
+        while lexer.next().is_some() {}
+        lexer
+    }
+
+    /// Lexes `src` and asserts that its tokens, apart from the final `Eof`, are `expected`. On a
+    /// mismatch both sequences are printed side by side, with the first difference marked.
+    fn assert_tokens(src: &str, expected: &[(TokenType, &str)]) {
+        let lexer = lex(src);
+        let actual = lexer.tokens.iter()
+            .filter(|t| t.token_type != TokenType::Eof)
+            .map(|t| (t.token_type, t.value))
+            .collect::<Vec<_>>();
+        if actual == expected {
+            return;
+        }
+
+        let first = actual.iter().zip(expected).position(|(a, e)| a != e).unwrap_or(actual.len().min(expected.len()));
+        let show = |t: Option<&(TokenType, &str)>| t.map_or("-".to_string(), |(tt, v)| format!("({:?}, {:?})", tt, v));
+        let mut diff = format!("  {:<40} actual\n", "expected");
+        for i in 0..actual.len().max(expected.len()) {
+            let marker = if i == first { '>' } else { ' ' };
+            diff += &format!("{} {:<40} {}\n", marker, show(expected.get(i)), show(actual.get(i)));
+        }
+        panic!("tokens of {:?} differ at index {}:\n{}", src, first, diff);
+    }
+
+    #[test]
+    fn numbers() {
+        assert_tokens("1 1_000 0xFF 2.5 .5 1e-5 2.5E3 0x1.8p3", &[
+            (IntegerLiteral, "1"),
+            (IntegerLiteral, "1_000"),
+            (IntegerLiteral, "0xFF"),
+            (FloatLiteral, "2.5"),
+            (FloatLiteral, ".5"),
+            (FloatLiteral, "1e-5"),
+            (FloatLiteral, "2.5E3"),
+            (FloatLiteral, "0x1.8p3"),
+        ]);
+    }
+
+    #[test]
+    fn ranges_are_not_floats() {
+        assert_tokens("0..10 1..=2", &[
+            (IntegerLiteral, "0"),
+            (RangeDescriptor, ".."),
+            (IntegerLiteral, "10"),
+            (IntegerLiteral, "1"),
+            (RangeDescriptor, "..="),
+            (IntegerLiteral, "2"),
+        ]);
+    }
+
+    #[test]
+    fn strings() {
+        assert_tokens(r#""hi" "a\"b" 'c' '\n' b"\x00" hex"00FF""#, &[
+            (StringLiteral, r#""hi""#),
+            (StringLiteral, r#""a\"b""#),
+            (CharLiteral, "'c'"),
+            (CharLiteral, r"'\n'"),
+            (ByteStringLiteral, r#"b"\x00""#),
+            (ByteStringLiteral, r#"hex"00FF""#),
+        ]);
+    }
+
+    #[test]
+    fn operators() {
+        assert_tokens("+ - * / % = += := == != <= >= < > ! ~", &[
+            (ArithmeticOperator, "+"),
+            (ArithmeticOperator, "-"),
+            (ArithmeticOperator, "*"),
+            (ArithmeticOperator, "/"),
+            (ArithmeticOperator, "%"),
+            (AssignmentOperator, "="),
+            (AssignmentOperator, "+="),
+            (AssignmentOperator, ":="),
+            (ComparisonOperator, "=="),
+            (ComparisonOperator, "!="),
+            (ComparisonOperator, "<="),
+            (ComparisonOperator, ">="),
+            (ComparisonOperator, "<"),
+            (ComparisonOperator, ">"),
+            (UnaryOperator, "!"),
+            (UnaryOperator, "~"),
+        ]);
+    }
+
+    #[test]
+    fn keywords_and_declarations() {
+        assert_tokens("pub fn f(int x) int {\n    if x > 0 {\n        return x\n    }\n    return 0\n}\n", &[
+            (Keyword, "pub"),
+            (Keyword, "fn"),
+            (Object, "f"),
+            (OpenParen, "("),
+            (DataType, "int"),
+            (Object, "x"),
+            (CloseParen, ")"),
+            (DataType, "int"),
+            (OpenCurlyBrace, "{"),
+            (NewLine, "\n"),
+            (Keyword, "if"),
+            (Object, "x"),
+            (ComparisonOperator, ">"),
+            (IntegerLiteral, "0"),
+            (OpenCurlyBrace, "{"),
+            (NewLine, "\n"),
+            (Keyword, "return"),
+            (Object, "x"),
+            (NewLine, "\n"),
+            (CloseCurlyBrace, "}"),
+            (NewLine, "\n"),
+            (Keyword, "return"),
+            (IntegerLiteral, "0"),
+            (NewLine, "\n"),
+            (CloseCurlyBrace, "}"),
+            (NewLine, "\n"),
+        ]);
+    }
+
+    #[test]
+    fn keyword_prefixes_are_not_keywords() {
+        assert_tokens("int index = 1\nint iffy = index", &[
+            (DataType, "int"),
+            (Object, "index"),
+            (AssignmentOperator, "="),
+            (IntegerLiteral, "1"),
+            (NewLine, "\n"),
+            (DataType, "int"),
+            (Object, "iffy"),
+            (AssignmentOperator, "="),
+            (Object, "index"),
+        ]);
+    }
+
+    #[test]
+    fn trailing_whitespace_adds_no_token() {
+        assert_tokens("1 \t ", &[(IntegerLiteral, "1")]);
+    }
+
+    #[test]
+    #[should_panic(expected = "differ at index 1")]
+    fn assert_tokens_reports_the_first_difference() {
+        assert_tokens("1 + 2", &[(IntegerLiteral, "1"), (ArithmeticOperator, "-"), (IntegerLiteral, "2")]);
+    }
+
+    #[test]
+    fn rewinding_lexes_the_same_tokens_again() {
+        let mut lexer = lex("fn f(int x) int {\n    return x\n}\n");
+        let first = lexer.tokens.clone();
+        lexer.rewind();
+        assert_eq!(lexer.token_count(), 0);
+        assert_eq!((lexer.position(), lexer.location()), (0, (1, 1)));
+        while lexer.next().is_some() {}
+        assert_eq!(lexer.tokens, first);
+    }
+
+    #[test]
+    fn tabs_advance_columns_to_the_next_tab_stop() {
+        let src = "\tint x = 1\n  \tx\t= 2\n\tstring s = \"a\tb\"\n";
+        fn columns<'a>(lexer: &Lexer<'a>) -> Vec<(&'a str, usize, usize)> {
+            lexer.tokens.iter()
+                .filter(|t| ![NewLine, Eof].contains(&t.token_type))
+                .map(|t| (t.value, t.span.line, t.span.col))
+                .collect()
+        }
+        assert_eq!(columns(&lex(src)), [
+            ("int", 1, 5), ("x", 1, 9), ("=", 1, 11), ("1", 1, 13),
+            ("x", 2, 5), ("=", 2, 9), ("2", 2, 11),
+            ("string", 3, 5), ("s", 3, 12), ("=", 3, 14), ("\"a\tb\"", 3, 16),
+        ]);
+
+        let mut lexer = Lexer::new(src).with_tab_width(8);
+        while lexer.next().is_some() {}
+        assert_eq!(columns(&lexer)[..3], [("int", 1, 9), ("x", 1, 13), ("=", 1, 15)]);
+    }
+
+    #[test]
+    fn reserved_words_and_long_names_are_reported() {
+        let messages = |lexer: &Lexer| lexer.validate_syntax().iter().map(|d| d.to_string()).collect::<Vec<_>>();
+        assert_eq!(messages(&lex("int if = 5\n")), ["Error: expected variable name, found keyword `if` (1:5)"]);
+        assert!(messages(&lex("int count = 5\n")).is_empty());
+
+        let mut lexer = Lexer::new("int count = 5\nint n = count\n").with_max_identifier_len(3);
+        while lexer.next().is_some() {}
+        assert_eq!(messages(&lexer), [
+            "Warning: identifier is longer than 3 characters (1:5)",
+            "Warning: identifier is longer than 3 characters (2:9)",
+        ]);
+    }
+
+    #[test]
+    fn comments_are_skipped() {
+        assert_tokens("1 // one\n/// doc\n2", &[
+            (IntegerLiteral, "1"),
+            (NewLine, "\n"),
+            (DocComment, "/// doc"),
+            (NewLine, "\n"),
+            (IntegerLiteral, "2"),
+        ]);
+    }
+
+    #[test]
+    fn triple_quoted_strings_span_lines() {
+        let src = "string s = \"\"\"first\nsecond\"\"\"\n";
+        assert_tokens(src, &[
+            (DataType, "string"),
+            (Object, "s"),
+            (AssignmentOperator, "="),
+            (StringLiteral, "\"\"\"first\nsecond\"\"\""),
+            (NewLine, "\n"),
+        ]);
+        // The newline in the string counts towards the lines of the tokens after it
+        assert_eq!(lex(src).tokens[4].span.line, 2);
+        assert!(lex(src).validate_syntax().is_empty());
+    }
+
+    #[test]
+    fn unterminated_triple_quoted_strings_are_errors() {
+        for src in ["string s = \"\"\"first\nsecond\n", "string s = \"\"\"\"\n"] {
+            let diagnostics = lex(src).validate_syntax();
+            assert_eq!(diagnostics.len(), 1, "{:?}", src);
+            assert_eq!(diagnostics[0].to_string(), "Error: unterminated `\"\"\"` string (1:12)");
+        }
+    }
+
+    #[test]
+    fn keywords_map_to_their_enum() {
+        for kw in super::Keyword::ALL {
+            assert_eq!(super::Keyword::new(kw.as_str()), Some(kw));
+            let src = format!("{} ", kw.as_str());
+            let lexer = lex(&src);
+            assert_eq!((lexer.tokens[0].token_type, lexer.tokens[0].keyword()), (Keyword, Some(kw)));
+        }
+        for word in ["Fn", "iff", "int", "main", ""] {
+            assert_eq!(super::Keyword::new(word), None, "{:?}", word);
+        }
+    }
+
+    #[test]
+    fn escapes_decode_to_their_character() {
+        assert_eq!(unescape(r"\x41\u{e9}\u{1F600}\t").unwrap(), "A\u{e9}\u{1F600}\t");
+        assert_eq!(unescape(r"\u{110000}").unwrap_err().to_string(), r"`\u{110000}` is not a valid unicode code point");
+        assert_eq!(unescape(r"\xFF").unwrap_err().to_string(), r"`\xFF` is not an ASCII character; use `\u{...}` instead");
+        assert_eq!(unescape(r"\u{}").unwrap_err().to_string(), r"`\u` must be followed by 1-6 hex digits in braces");
+
+        let diagnostics = lex("char c = '\\u{110000}'\n").validate_syntax();
+        assert_eq!(diagnostics[0].to_string(), r"Error: `\u{110000}` is not a valid unicode code point (1:10)");
+    }
+
+    #[test]
+    fn newlines_and_comments_can_be_emitted_or_skipped() {
+        let src = "1 // one\n2\n";
+        assert_tokens(src, &[(IntegerLiteral, "1"), (NewLine, "\n"), (IntegerLiteral, "2"), (NewLine, "\n")]);
+
+        let options = LexerOptions { emit_newlines: false, emit_comments: true };
+        let mut lexer = Lexer::new(src).with_options(options);
+        while lexer.next().is_some() {}
+        let tokens = lexer.tokens.iter().map(|t| (t.token_type, t.value)).collect::<Vec<_>>();
+        assert_eq!(tokens, [(IntegerLiteral, "1"), (Comment, "// one"), (IntegerLiteral, "2"), (Eof, "")]);
+        // Lines are still counted without `NewLine` tokens
+        assert_eq!(lexer.tokens[2].span.line, 2);
+    }
+
+    #[test]
+    fn hex_floats_decode_with_a_binary_exponent() {
+        assert_eq!(parse_hex_float("0x1.8p3").unwrap(), 12.0);
+        assert_eq!(parse_hex_float("0xAp-1").unwrap(), 5.0);
+
+        let diagnostics = lex("float f = 0x1.8\n").validate_syntax();
+        assert_eq!(diagnostics[0].to_string(), "Error: hex float literal `0x1.8` is missing a `p` exponent (1:11)");
+    }
+
+    #[test]
+    fn tokens_recover_their_source_text() {
+        let src = "fn f(string s) {\n    print(\"a  b\") // hi\n}\n";
+        let lexer = lex(src);
+        let string = lexer.tokens.iter().find(|t| t.token_type == StringLiteral).unwrap();
+        assert_eq!(string.span_text(src), "\"a  b\"");
+        for t in &lexer.tokens {
+            assert_eq!(t.span_text(src), t.value, "{:?}", t);
+        }
+    }
+
+    #[test]
+    fn relexing_an_edited_line_matches_a_full_lex() {
+        let tokens = |lexer: &Lexer| lexer.tokens.iter().map(|t| (t.token_type, t.value.to_string(), t.span)).collect::<Vec<_>>();
+        let old = lex("fn f(int x) int {\n    int y = x + 1\n    return x\n}\n");
+        // Changing an expression, the declared name, and the number of lines
+        for new in [
+            "fn f(int x) int {\n    int y = x * 20\n    return x\n}\n",
+            "fn f(int x) int {\n    int z = x\n    return x\n}\n",
+            "fn f(int x) int {\n    int y = x\n\n    return x\n}\n",
+        ] {
+            assert_eq!(tokens(&old.relex_line(new, 2)), tokens(&lex(new)), "{:?}", new);
+        }
+    }
+
+    #[test]
+    fn crlf_and_lone_cr_end_lines() {
+        let src = "fn f(int x) {\r\n    int y = x\r    print(\"a\\r\")\r\n}\r\n";
+        let lexer = lex(src);
+        for t in &lexer.tokens {
+            assert!(t.token_type == NewLine || !t.value.contains('\r'), "{:?}", t);
+        }
+        let lines = lexer.tokens.iter()
+            .filter(|t| t.token_type != NewLine)
+            .map(|t| (t.value, t.span.line, t.span.col))
+            .collect::<Vec<_>>();
+        assert_eq!(lines[7..10], [("int", 2, 5), ("y", 2, 9), ("=", 2, 11)]);
+        assert_eq!(lines[11..], [("print", 3, 5), ("(", 3, 10), ("\"a\\r\"", 3, 11), (")", 3, 16), ("}", 4, 1), ("", 5, 1)]);
+    }
+
+    #[test]
+    fn tokens_compare_with_or_without_their_spans() {
+        let src = "fn f(int x) {\n    print(x)\n}\n";
+        assert_eq!(lex(src).tokens, lex(src).tokens);
+
+        let indented = lex("fn f(int x) {\n        print(x)\n}\n");
+        assert_ne!(lex(src).tokens, indented.tokens);
+        assert!(lex(src).tokens.iter().zip(&indented.tokens).all(|(a, b)| a.eq_ignore_span(b)));
+
+        // The two `x` tokens differ in their spans
+        let unique = lex("int x = x").tokens.into_iter().collect::<HashSet<_>>();
+        assert_eq!(unique.len(), 5);
+    }
+
+    #[test]
+    fn skipped_tokens_do_not_grow_the_stack() {
+        let src = format!("1{}2{}", "// skipped\n".repeat(100_000), " ".repeat(100_000));
+        let options = LexerOptions { emit_newlines: false, emit_comments: false };
+        let mut lexer = Lexer::new(&src).with_options(options);
+        while lexer.next().is_some() {}
+        let tokens = lexer.tokens.iter().map(|t| (t.token_type, t.value)).collect::<Vec<_>>();
+        assert_eq!(tokens, [(IntegerLiteral, "1"), (IntegerLiteral, "2"), (Eof, "")]);
+
+        // A name can start the source
+        assert_tokens("print(1)", &[(Object, "print"), (OpenParen, "("), (IntegerLiteral, "1"), (CloseParen, ")")]);
+    }
+}
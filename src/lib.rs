@@ -0,0 +1,6 @@
+#![allow(unused)]
+pub mod imports;
+pub mod interpreter;
+pub mod lexer;
+pub mod parser;
+pub mod utils;
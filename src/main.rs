@@ -1,41 +1,233 @@
 #![allow(unused)]
-mod lexer;
-mod parser;
-mod utils;
-
-use std::{collections::HashSet, default, fs, io::Stdout};
-use parser::AstNode;
+use std::{collections::HashSet, default, env, fs::{self, File}, io::{self, BufWriter, Stdout, Write}, panic, path::Path, process, thread, time::{Duration, Instant}};
+use compiler_pilot::{
+    imports::{analyze_lexed_with_imports, compile_file, resolve_imports, Imports},
+    interpreter::{run_main, RuntimeValue},
+    lexer::{decode_utf8, Diagnostic, Lexer},
+    parser::{lex_to_end, lint, ParserOptions, DEFAULT_MAX_ERRORS},
+};
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
 use regex::Regex;
-use lexer::Lexer;
-use utils::{FnLst, VarLst};
 
+/// Usage: `compiler-pilot [--check] [--time] [--dump-scopes] [--max-errors N] [--output FILE] [path]`
+/// (the path defaults to `truffle/main.tr`)
+///
+/// The tokens, the AST, diagnostics and warnings are written to stdout, or to `FILE` with
+/// `--output`.
+///
+/// Parsing continues past errors where it can, so that several can be reported at once. After
+/// `--max-errors` of them (100 by default) it stops at the next one, and only the number of
+/// errors beyond the limit is printed.
+///
+/// With `--check`, only diagnostics are printed and the exit status reports the result:
+/// - `0`: no lexer or parser errors were found, though there may have been warnings
+/// - `1`: at least one error was found
+/// - `101`: the compiler panicked on input it can't handle yet
+///
+/// With `--time`, how long lexing, parsing and linting took is printed to stderr.
+///
+/// With `--dump-scopes`, the variables in scope before each statement are printed to stderr as
+/// the program is parsed.
+///
+/// `compiler-pilot run <path> [-- args...]` instead interprets the program's `main` function,
+/// passing it the arguments after `--`, and prints what it returns. Calls nested more than
+/// `MAX_CALL_DEPTH` deep stop the program with an error.
 fn main() {
-    let code = fs::read_to_string("truffle/main.tr")
-        .unwrap()
-        .split("\n")
-        .filter(|&line| !line.trim().starts_with("//"))
-        .map(|s| s.to_string())
-        .collect::<Vec<String>>()
-        .join("\n")
-        .replace("  ", " ")
-        .replace("\n\n", "\n");
+    let args = env::args().skip(1).collect::<Vec<String>>();
+    if args.first().is_some_and(|a| a == "run") {
+        process::exit(run(&args[1..]));
+    }
+
+    let check_mode = args.iter().any(|a| a == "--check");
+    let time_mode = args.iter().any(|a| a == "--time");
+    let max_errors = match args.iter().position(|a| a == "--max-errors") {
+        Some(i) => match args.get(i + 1).and_then(|n| n.parse::<usize>().ok()).filter(|n| *n > 0) {
+            Some(n) => n,
+            None => {
+                println!("Error: `--max-errors` must be followed by a positive number");
+                process::exit(1);
+            }
+        },
+        None => DEFAULT_MAX_ERRORS,
+    };
+    let output = match args.iter().position(|a| a == "--output") {
+        Some(i) => match args.get(i + 1).filter(|p| !p.starts_with("--")) {
+            Some(p) => Some(p.as_str()),
+            None => {
+                println!("Error: `--output` must be followed by a file path");
+                process::exit(1);
+            }
+        },
+        None => None,
+    };
+    let options = ParserOptions::default()
+        .with_dump_scopes(args.iter().any(|a| a == "--dump-scopes"))
+        .with_max_errors(max_errors);
+    let path = args.iter().enumerate()
+        .find(|(i, a)| !a.starts_with("--") && (*i == 0 || !["--max-errors", "--output"].contains(&args[i - 1].as_str())))
+        .map(|(_, a)| a.as_str())
+        .unwrap_or("truffle/main.tr");
+
+    let mut out: Box<dyn Write> = match output {
+        Some(output) => match File::create(output) {
+            Ok(file) => Box::new(BufWriter::new(file)),
+            Err(e) => {
+                println!("Error: cannot create `{}`: {}", output, e);
+                process::exit(1);
+            }
+        },
+        None => Box::new(io::stdout()),
+    };
+    let status = compile(&mut out, path, options, max_errors, check_mode, time_mode).and_then(|status| {
+        out.flush()?;
+        Ok(status)
+    });
+    match status {
+        Ok(status) => process::exit(status),
+        Err(e) => {
+            println!("Error: cannot write output: {}", e);
+            process::exit(1);
+        }
+    }
+}
 
+/// Compiles the program at `path`, writing what's reported about it to `out`. Returns the exit
+/// status, which is only nonzero in check mode if there were errors, or if an import failed.
+fn compile(out: &mut impl Write, path: &str, options: ParserOptions, max_errors: usize, check_mode: bool, time_mode: bool) -> io::Result<i32> {
+    let code = read_source(path);
 
+    // Imported files are compiled first, since the parser needs the signatures of their functions
+    let start = Instant::now();
+    let imports = match resolve_imports(Path::new(path), &code, &options) {
+        Ok(imports) => imports,
+        Err(e) => {
+            writeln!(out, "Error: {}", e)?;
+            return Ok(1);
+        }
+    };
+    let import_time = start.elapsed();
 
+    let start = Instant::now();
     let mut lexer = Lexer::new(&code);
+    if let Err(e) = lex_to_end(&mut lexer) {
+        writeln!(out, "Error: {}", e)?;
+        return Ok(1);
+    }
+    let lex_time = start.elapsed();
+
+    if !check_mode {
+        for token in lexer.tokens.iter() {
+            writeln!(out, "{:?}", token)?;
+        }
+        writeln!(out, "\n\n\n\n")?;
+    }
+
+    // Lexer diagnostics are among those returned, as they decide whether parsing can start
+    let start = Instant::now();
+    let (funcs, diagnostics) = analyze_lexed_with_imports(&lexer, options, imports);
+    let parse_time = import_time + start.elapsed();
+    if time_mode {
+        report_time("lex", lex_time);
+        report_time("parse", parse_time);
+    }
 
-    while let Some(token) = lexer.next() {
-        println!("{:?}", token);
+    let has_errors = diagnostics.iter().any(|d| d.is_error());
+    report_diagnostics(out, &diagnostics, max_errors)?;
+    if let Some(funcs) = funcs.filter(|_| !has_errors) {
+        if !check_mode {
+            writeln!(out, "{:#?}", funcs)?;
+        }
+        let start = Instant::now();
+        let warnings = lint(&funcs);
+        if time_mode {
+            report_time("lint", start.elapsed());
+        }
+        for warning in warnings {
+            writeln!(out, "{}", warning)?;
+        }
     }
+    Ok(if has_errors && check_mode { 1 } else { 0 })
+}
 
-    let errors = lexer.validate_syntax();
-    println!("\nLexer Errors: {:#?}\n\n\n\n\n", errors);
+/// Prints the diagnostics in order, leaving out the errors after the first `max_errors` and
+/// saying how many were left out. The parser stops at the first error past the limit, but the
+/// lexer doesn't, so there may be more than one of them.
+fn report_diagnostics(out: &mut impl Write, diagnostics: &[Diagnostic], max_errors: usize) -> io::Result<()> {
+    let mut errors = 0;
+    for diagnostic in diagnostics {
+        if diagnostic.is_error() {
+            errors += 1;
+            if errors > max_errors {
+                continue;
+            }
+        }
+        writeln!(out, "{}", diagnostic)?;
+    }
+    if errors > max_errors {
+        writeln!(out, "... and {} more errors", errors - max_errors)?;
+    }
+    Ok(())
+}
 
-    let mut var_lst = VarLst::new();
-    let mut fn_list = FnLst::new();
+/// Prints how long a compilation phase took, for `--time`
+fn report_time(phase: &str, duration: Duration) {
+    eprintln!("{:>5}: {:.3?}", phase, duration);
+}
 
-    let s = AstNode::generate_function(&lexer.tokens, &mut var_lst, &mut fn_list);
-    println!("{:#?}", s);
+/// Reads a source file, exiting if it can't be read or isn't valid UTF-8. The source is passed to
+/// the lexer as is, which skips comments itself, so string literals keep exactly what's in the file.
+fn read_source(path: &str) -> String {
+    let bytes = match fs::read(path) {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            println!("Error: cannot read `{}`: {}", path, e);
+            process::exit(1);
+        }
+    };
+    match decode_utf8(&bytes) {
+        Ok(source) => source.to_string(),
+        Err(e) => {
+            println!("Error: {}", e);
+            process::exit(1);
+        }
+    }
+}
+
+/// The stack size of the thread programs run on
+const RUN_STACK_SIZE: usize = 256 * 1024 * 1024;
+
+/// Handles `run <path> [-- args...]`, returning the exit status
+fn run(args: &[String]) -> i32 {
+    let (path, program_args) = match args.iter().position(|a| a == "--") {
+        Some(i) => (args[..i].first(), &args[i+1..]),
+        None => (args.first(), &[][..]),
+    };
+    let path = path.map(|p| p.as_str()).unwrap_or("truffle/main.tr");
+
+    // Programs run on a thread with room for `MAX_CALL_DEPTH` nested calls, even in a debug build
+    let res = thread::scope(|scope| {
+        thread::Builder::new()
+            .stack_size(RUN_STACK_SIZE)
+            .spawn_scoped(scope, || {
+                compile_file(Path::new(path))
+                    .and_then(|funcs| run_main(&funcs, program_args))
+                    .map(|value| (!matches!(value, RuntimeValue::Unit)).then(|| value.to_string()))
+                    .map_err(|e| e.to_string())
+            })
+            .expect("failed to start the interpreter thread")
+            .join()
+            .unwrap_or_else(|payload| panic::resume_unwind(payload))
+    });
+    match res {
+        Ok(None) => 0,
+        Ok(Some(value)) => {
+            println!("{}", value);
+            0
+        }
+        Err(e) => {
+            println!("Error: {}", e);
+            1
+        }
+    }
 }
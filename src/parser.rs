@@ -1,11 +1,11 @@
-use std::{collections::{HashMap, HashSet}, default, fmt::Debug};
+use std::{cell::{Cell, RefCell}, collections::{BTreeSet, HashMap, HashSet}, default, fmt::{Debug, Display}, num::IntErrorKind, panic::{self, AssertUnwindSafe}, rc::Rc, sync::Once};
 
 use anyhow::{Result, anyhow};
 use serde::{Deserialize, Serialize};
 
-use crate::{lexer::{Token, TokenType}, utils::{FnLst, VarLst}};
+use crate::{lexer::{parse_array_len, parse_byte_string, Diagnostic, Keyword, Lexer, Span, Token, TokenType, BUILTINS}, utils::{clear_interned, format_signature, intern, FnLst, FnSignature, VarLst}, interpreter::{eval_constant, Evaluate}};
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum DataType {
     I64,
     U64,
@@ -15,6 +15,12 @@ pub enum DataType {
     Char,
     String,
     Vec { inner: Box<DataType> },
+    /// A fixed number of values of the same type, such as `int[4]`
+    Array { inner: Box<DataType>, len: usize },
+    /// A fixed number of values of possibly different types, such as `(int, bool)`
+    Tuple(Vec<DataType>),
+    /// The result of calls that don't produce a value, such as `print`
+    Unit,
 }
 
 impl DataType {
@@ -34,14 +40,97 @@ impl DataType {
             },
         }
 
+        // Each `[]` or `[len]` wraps the type before it, so `int[3][]` is a vector of `int[3]`
         let (ty, brackets) = dt.split_once("[").unwrap();
         let mut final_dt = Self::new(ty);
-        for i in 0..=(brackets.len()/2) {
-            final_dt = DataType::Vec { inner: Box::new(final_dt) };
+        for len in brackets.split('[') {
+            let len = len.trim_end_matches(']');
+            final_dt = match len {
+                "" => DataType::Vec { inner: Box::new(final_dt) },
+                _ => DataType::Array {
+                    inner: Box::new(final_dt),
+                    len: parse_array_len(len).expect("array lengths are checked by `validate_syntax`"),
+                },
+            };
         }
         final_dt
     }
 
+    /// Returns the type of the elements of a vector or fixed-size array
+    fn element_type(&self) -> Option<&DataType> {
+        match self {
+            DataType::Vec { inner } | DataType::Array { inner, .. } => Some(inner),
+            _ => None,
+        }
+    }
+
+    /// Parses a tuple type such as `(int, bool)` starting at the `(` in `s[0]`, returning the type
+    /// and the number of tokens it spans
+    fn parse_tuple(s: &[Token]) -> Result<(Self, usize)> {
+        let mut elements = vec![];
+        let mut i = 1;
+        loop {
+            match s.get(i) {
+                Some(t) if t.token_type == TokenType::DataType => elements.push(Self::new(t.value)),
+                Some(t) => return Err(anyhow!("expected a type in tuple type, found `{}`", t.value)),
+                None => return Err(anyhow!("unclosed tuple type")),
+            }
+            match s.get(i + 1) {
+                Some(t) if t.token_type == TokenType::Comma => i += 2,
+                Some(t) if t.token_type == TokenType::CloseParen => break,
+                _ => return Err(anyhow!("expected `,` or `)` in tuple type")),
+            }
+        }
+
+        if elements.len() < 2 {
+            return Err(anyhow!("a tuple type needs at least two elements"));
+        }
+        Ok((DataType::Tuple(elements), i + 2))
+    }
+
+    /// Returns the type both `self` and `other` can be used as, if there is one. Integers unify
+    /// with floats (as in arithmetic), and string literals (`Vec<U8>`) with `String`.
+    pub fn unify(&self, other: &DataType) -> Option<DataType> {
+        let string_literal = DataType::Vec { inner: Box::new(DataType::U8) };
+        match (self, other) {
+            _ if self == other => Some(self.clone()),
+            (DataType::F64, dt) | (dt, DataType::F64) if dt.is_numeric() => Some(DataType::F64),
+            (DataType::String, dt) | (dt, DataType::String) if *dt == string_literal => Some(DataType::String),
+            (DataType::Tuple(a), DataType::Tuple(b)) if a.len() == b.len() => {
+                a.iter().zip(b.iter()).map(|(a, b)| a.unify(b)).collect::<Option<Vec<DataType>>>().map(DataType::Tuple)
+            }
+            _ => None,
+        }
+    }
+
+    /// Returns true if a value of type `src` can be stored somewhere typed `self`: the types are
+    /// the same, `src` widens losslessly into `self` (an integer into `float`, a `byte` into
+    /// `int` or `uint`), or `src` is a string literal stored as a `string`
+    pub fn is_assignable_from(&self, src: &DataType) -> bool {
+        let widens_byte = *src == DataType::U8 && [DataType::I64, DataType::U64].contains(self);
+        widens_byte || src.unify(self).as_ref() == Some(self)
+    }
+
+    /// Returns true if values of this type are copied on assignment rather than moved: scalars,
+    /// and fixed-size arrays and tuples of them. Strings and vectors are heap-backed.
+    pub fn is_copy(&self) -> bool {
+        match self {
+            DataType::I64 | DataType::U64 | DataType::F64 | DataType::U8 | DataType::Bool | DataType::Char | DataType::Unit => true,
+            DataType::String | DataType::Vec { .. } => false,
+            DataType::Array { inner, .. } => inner.is_copy(),
+            DataType::Tuple(elements) => elements.iter().all(|e| e.is_copy()),
+        }
+    }
+
+    /// Returns true for `String` and for `byte[]`, the type of string literals
+    pub fn is_string(&self) -> bool {
+        match self {
+            DataType::String => true,
+            DataType::Vec { inner } => **inner == DataType::U8,
+            _ => false,
+        }
+    }
+
     fn is_numeric(&self) -> bool {
         let num_types = [
             Self::I64,
@@ -49,7 +138,29 @@ impl DataType {
             Self::F64,
             Self::U8,
         ];
-        num_types.contains(&self)
+        num_types.contains(self)
+    }
+}
+
+/// Uses the names types are written with in source, e.g. `int` or `float[]`
+impl Display for DataType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DataType::I64 => write!(f, "int"),
+            DataType::U64 => write!(f, "uint"),
+            DataType::F64 => write!(f, "float"),
+            DataType::U8 => write!(f, "byte"),
+            DataType::Bool => write!(f, "bool"),
+            DataType::Char => write!(f, "char"),
+            DataType::String => write!(f, "string"),
+            DataType::Vec { inner } => write!(f, "{}[]", inner),
+            DataType::Array { inner, len } => write!(f, "{}[{}]", inner, len),
+            DataType::Tuple(elements) => {
+                let elements = elements.iter().map(|e| e.to_string()).collect::<Vec<String>>();
+                write!(f, "({})", elements.join(", "))
+            }
+            DataType::Unit => write!(f, "()"),
+        }
     }
 }
 
@@ -66,9 +177,25 @@ pub enum OperationType {
     LessThanOrEq,
     Eq,
     NotEq,
+    In,
 }
 
 impl OperationType {
+    pub const ALL: [OperationType; 12] = [
+        OperationType::Add,
+        OperationType::Subtract,
+        OperationType::Div,
+        OperationType::Mult,
+        OperationType::Mod,
+        OperationType::GreaterThan,
+        OperationType::LessThan,
+        OperationType::GreaterThanOrEq,
+        OperationType::LessThanOrEq,
+        OperationType::Eq,
+        OperationType::NotEq,
+        OperationType::In,
+    ];
+
     fn new(token: &Token) -> Result<Self> {
         match token.token_type {
             TokenType::ArithmeticOperator => {
@@ -80,7 +207,7 @@ impl OperationType {
                     "%" => Self::Mod,
                     _ => return Err(anyhow!("faulty arithmetic operator: `{}`", token.value)),
                 };
-                return Ok(op);
+                Ok(op)
             }
             TokenType::ComparisonOperator => {
                 let op = match token.value {
@@ -92,21 +219,26 @@ impl OperationType {
                     "!=" => Self::NotEq,
                     _ => return Err(anyhow!("faulty comparison operator: `{}`", token.value)),
                 };
-                return Ok(op);
+                Ok(op)
             }
-            _ => return Err(anyhow!("Incorrect token passed to [fn OperationType::new]")),
+            TokenType::Keyword if token.keyword() == Some(Keyword::In) => Ok(Self::In),
+            _ => Err(anyhow!("Incorrect token passed to [fn OperationType::new]")),
         }
     }
 
+    /// Returns the operator that a compound assignment such as `+=` applies. Every operator whose
+    /// result has the type of its operands gets an `op=` form.
+    fn from_compound_assignment(symbol: &str) -> Option<Self> {
+        let base = symbol.strip_suffix('=')?;
+        Self::ALL.into_iter().find(|op| {
+            let info = describe_operator(*op);
+            info.symbol == base && info.result == ResultType::Promoted
+        })
+    }
+
     /// 255 is highest priority, 1 is the lowest
     fn get_priority(&self) -> usize {
-        if [Self::Mult, Self::Div, Self::Mod].contains(&self) {
-            return 10;
-        }
-        else if [Self::Add, Self::Subtract].contains(&self) {
-            return 9;
-        }
-        8
+        describe_operator(*self).precedence
     }
 
     /// Returns true if the operation is a arithmetic operator
@@ -118,11 +250,11 @@ impl OperationType {
             Self::Mult,
             Self::Mod,
         ];
-        arith.contains(&self)
+        arith.contains(self)
     }
 
     /// Returns true if the operation is a comparison operator
-    fn is_comparison(&self) -> bool {
+    pub(crate) fn is_comparison(&self) -> bool {
         let comp = [    
             Self::GreaterThan,
             Self::LessThan,
@@ -131,29 +263,127 @@ impl OperationType {
             Self::Eq,
             Self::NotEq
         ];
-        comp.contains(&self)
+        comp.contains(self)
     }
 
-    fn as_str(&self) -> &'static str {
-        match &self {
-            Self::Add => return "+",
-            Self::Subtract => return "-",
-            Self::Div => return "/",
-            Self::Mult => return "*",
-            Self::Mod => return "%",
-            Self::GreaterThan => return ">",
-            Self::LessThan => return "<",
-            Self::GreaterThanOrEq => return ">=",
-            Self::LessThanOrEq => return "<=",
-            Self::Eq => return "==",
-            Self::NotEq => return "!=",
-        }
+    pub(crate) fn as_str(&self) -> &'static str {
+        describe_operator(*self).symbol
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Associativity {
+    Left,
+    Right,
+}
+
+/// The types of operands a binary operator accepts
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum OperandTypes {
+    /// Any two numeric types, as long as they're the same unless one of them is `float` (or a
+    /// `byte`, which widens into the other integer types)
+    Numeric,
+    /// Two numeric operands as for `Numeric`, or two `char`s. Strings and bools aren't ordered.
+    Ordered,
+    /// Two operands of a common type (see `DataType::unify`), or numeric operands as for `Numeric`
+    Equatable,
+    /// A value on the left that can be stored as an element of the vector on the right
+    Membership,
+}
+
+/// How the type of a binary operation is derived from its operands
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ResultType {
+    /// Always `bool`
+    Bool,
+    /// `float` if either operand is a `float`, otherwise the type both operands share
+    Promoted,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct OperatorInfo {
+    pub symbol: &'static str,
+    /// Operators with a higher precedence bind tighter
+    pub precedence: usize,
+    pub associativity: Associativity,
+    pub operands: OperandTypes,
+    pub result: ResultType,
+}
+
+/// Returns the symbol, precedence and typing rules of a binary operator
+pub fn describe_operator(op: OperationType) -> OperatorInfo {
+    let (symbol, precedence) = match op {
+        OperationType::Add => ("+", 9),
+        OperationType::Subtract => ("-", 9),
+        OperationType::Div => ("/", 10),
+        OperationType::Mult => ("*", 10),
+        OperationType::Mod => ("%", 10),
+        OperationType::GreaterThan => (">", 8),
+        OperationType::LessThan => ("<", 8),
+        OperationType::GreaterThanOrEq => (">=", 8),
+        OperationType::LessThanOrEq => ("<=", 8),
+        OperationType::Eq => ("==", 8),
+        OperationType::NotEq => ("!=", 8),
+        OperationType::In => ("in", 8),
+    };
+
+    let (operands, result) = match op {
+        OperationType::Eq | OperationType::NotEq => (OperandTypes::Equatable, ResultType::Bool),
+        OperationType::In => (OperandTypes::Membership, ResultType::Bool),
+        _ if op.is_comparison() => (OperandTypes::Ordered, ResultType::Bool),
+        _ => (OperandTypes::Numeric, ResultType::Promoted),
+    };
+
+    OperatorInfo {
+        symbol,
+        precedence,
+        associativity: Associativity::Left,
+        operands,
+        result,
     }
 }
 
-pub trait Value {
+pub trait Value: Evaluate {
     fn dtype(&self) -> DataType;
     fn value(&self) -> String;
+
+    /// Returns the value of a `Bool` that's known at compile time
+    fn as_bool_constant(&self) -> Option<bool> {
+        None
+    }
+
+    /// Returns the values this one is computed from, e.g. the operands of an operation
+    fn children(&self) -> Vec<&dyn Value> {
+        vec![]
+    }
+
+    fn as_operation(&self) -> Option<&Operation> {
+        None
+    }
+
+    fn as_variable(&self) -> Option<&Variable> {
+        None
+    }
+
+    fn as_literal(&self) -> Option<&Literal> {
+        None
+    }
+
+    fn as_call(&self) -> Option<&Call> {
+        None
+    }
+
+    fn as_array_literal(&self) -> Option<&ArrayLiteral> {
+        None
+    }
+
+    fn as_block_expression(&self) -> Option<&BlockExpression> {
+        None
+    }
+
+    /// Replaces each of the values this one is computed from with `f(child)`. Used by
+    /// `Transformer`, which needs to own a node to replace it.
+    fn map_children(self: Box<Self>, f: &mut dyn FnMut(Box<dyn Value>) -> Box<dyn Value>) -> Box<dyn Value>;
 }
 
 impl Value for Literal {
@@ -162,7 +392,23 @@ impl Value for Literal {
     }
 
     fn value(&self) -> String {
-        self.value.clone()
+        self.value.to_string()
+    }
+
+    fn as_bool_constant(&self) -> Option<bool> {
+        match (&self.dtype, &*self.value) {
+            (DataType::Bool, "true") => Some(true),
+            (DataType::Bool, "false") => Some(false),
+            _ => None,
+        }
+    }
+
+    fn as_literal(&self) -> Option<&Literal> {
+        Some(self)
+    }
+
+    fn map_children(self: Box<Self>, _f: &mut dyn FnMut(Box<dyn Value>) -> Box<dyn Value>) -> Box<dyn Value> {
+        self
     }
 }
 impl Value for Variable{
@@ -171,7 +417,15 @@ impl Value for Variable{
     }
 
     fn value(&self) -> String {
-        self.name.clone()
+        self.name.to_string()
+    }
+
+    fn as_variable(&self) -> Option<&Variable> {
+        Some(self)
+    }
+
+    fn map_children(self: Box<Self>, _f: &mut dyn FnMut(Box<dyn Value>) -> Box<dyn Value>) -> Box<dyn Value> {
+        self
     }
 }
 impl Value for Operation{
@@ -182,393 +436,4009 @@ impl Value for Operation{
     fn value(&self) -> String {
         format!("({} {} {})", self.opd_1.value(), self.op.as_str(), self.opd_2.value())
     }
-}
 
-impl Debug for dyn Value {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self.value())
+    fn children(&self) -> Vec<&dyn Value> {
+        vec![self.opd_1.as_ref(), self.opd_2.as_ref()]
     }
-}
 
+    fn as_operation(&self) -> Option<&Operation> {
+        Some(self)
+    }
 
-#[derive(Debug, Clone)]
-pub struct Literal {
-    value: String,
-    dtype: DataType,
+    fn map_children(self: Box<Self>, f: &mut dyn FnMut(Box<dyn Value>) -> Box<dyn Value>) -> Box<dyn Value> {
+        let Operation { opd_1, opd_2, op, ret_type } = *self;
+        Box::new(Operation { opd_1: f(opd_1), opd_2: f(opd_2), op, ret_type })
+    }
 }
 
-#[derive(Debug, Clone)]
-pub struct Variable {
-    name: String,
-    dtype: DataType,
-}
+impl Value for Index {
+    fn dtype(&self) -> DataType {
+        self.dtype.clone()
+    }
 
-#[derive(Debug)]
-pub struct Function {
-    name: String,
-    parameters: Vec<Variable>,
-    body: CodeBlock,
-}
+    fn value(&self) -> String {
+        format!("{}[{}]", self.base.value(), self.index.value())
+    }
 
-#[derive(Debug)]
-pub struct Loop {
-    condition: Box<dyn Value>,
-    body: CodeBlock,
+    fn children(&self) -> Vec<&dyn Value> {
+        vec![self.base.as_ref(), self.index.as_ref()]
+    }
+
+    fn map_children(self: Box<Self>, f: &mut dyn FnMut(Box<dyn Value>) -> Box<dyn Value>) -> Box<dyn Value> {
+        let Index { base, index, dtype } = *self;
+        Box::new(Index { base: f(base), index: f(index), dtype })
+    }
 }
 
+impl Value for Cast {
+    fn dtype(&self) -> DataType {
+        self.dtype.clone()
+    }
 
-#[derive(Debug)]
-pub struct CodeBlock {
-    statements: Vec<AstNode>,
-}
+    fn value(&self) -> String {
+        format!("({} as {})", self.value.value(), self.dtype)
+    }
 
-#[derive(Debug)]
-pub struct AssignmentStatement {
-    dst: Variable,
-    src: Box<dyn Value>,
-}
+    fn children(&self) -> Vec<&dyn Value> {
+        vec![self.value.as_ref()]
+    }
 
-#[derive(Debug)]
-pub struct Operation {
-    opd_1: Box<dyn Value>,
-    opd_2: Box<dyn Value>,
-    op: OperationType,
-    ret_type: DataType,
+    fn map_children(self: Box<Self>, f: &mut dyn FnMut(Box<dyn Value>) -> Box<dyn Value>) -> Box<dyn Value> {
+        let Cast { value, dtype } = *self;
+        Box::new(Cast { value: f(value), dtype })
+    }
 }
 
-impl Operation {
-    /// Modifies the return type of the Operation object based on the types of the operands and operator
-    fn gen_return_t(&mut self) {
-        if self.op.is_comparison() {
-            self.ret_type = DataType::Bool;
-        }
-        else if self.opd_1.dtype() == DataType::F64 || self.opd_2.dtype() == DataType::F64 {
-            assert!(self.opd_1.dtype().is_numeric());
-            assert!(self.opd_2.dtype().is_numeric());
-            self.ret_type = DataType::F64;
-        }
-        else {
-            assert_eq!(self.opd_1.dtype(), self.opd_2.dtype());
-            self.ret_type = self.opd_1.dtype();
-        }
+impl Value for UnaryOperation {
+    fn dtype(&self) -> DataType {
+        self.ret_type.clone()
     }
 
-    fn exists_inline(tokens: &[Token]) -> bool {
-        let operators = [
-            TokenType::ArithmeticOperator,
-            TokenType::ComparisonOperator,
-        ];
-
-        let end_tokens = [
-            TokenType::NewLine,
-            TokenType::OpenCurlyBrace,
-            TokenType::CloseCurlyBrace,
-            TokenType::SemiColon,
-            TokenType::Comma
-        ];
+    fn value(&self) -> String {
+        format!("({}{})", self.op.as_str(), self.operand.value())
+    }
 
-        for t in tokens.iter() {
-            if operators.contains(&t.token_type) {
-                return true;
-            } 
-            else if end_tokens.contains(&t.token_type) {
-                return false;
-            }
-        }
-        false
+    fn children(&self) -> Vec<&dyn Value> {
+        vec![self.operand.as_ref()]
     }
 
-    fn extract_operation(tokens: &[Token], variable_lst: &VarLst, fn_lst: &FnLst) -> (Box<dyn Value>, usize) {
-        let mut op = Operation {
-            opd_1: Box::new(Literal{value:"1".to_string(), dtype: DataType::I64}),
-            opd_2: Box::new(Literal{value:"1".to_string(), dtype: DataType::I64}),
-            op: OperationType::GreaterThan,
-            ret_type: DataType::Bool,
-        };
+    fn map_children(self: Box<Self>, f: &mut dyn FnMut(Box<dyn Value>) -> Box<dyn Value>) -> Box<dyn Value> {
+        let UnaryOperation { operand, op, ret_type } = *self;
+        Box::new(UnaryOperation { operand: f(operand), op, ret_type })
+    }
+}
 
-        let end_tokens = [
-            TokenType::NewLine,
-            TokenType::OpenCurlyBrace,
-            TokenType::CloseCurlyBrace,
-            TokenType::SemiColon,
-            TokenType::Comma
-        ];
-        let mut length = 0;
-        for t in tokens.iter() {
-            if end_tokens.contains(&t.token_type) {
-                break;
-            }
-            length += 1;
-        }
+impl Value for ArrayLiteral {
+    fn dtype(&self) -> DataType {
+        self.dtype.clone()
+    }
 
-        (Self::extract_operation_h(&tokens[..length], variable_lst, fn_lst), length)
+    fn value(&self) -> String {
+        let elements = self.elements.iter().map(|e| e.value()).collect::<Vec<String>>();
+        format!("[{}]", elements.join(", "))
     }
 
-    /// Preconditions:
-    /// - The tokens passed to it have no addition tokens past the end of the operations
-    /// - There are no parenthesis in the tokens (if there are, you need to call this recursively)
-    fn extract_operation_h(tokens: &[Token], variable_lst: &VarLst, fn_lst: &FnLst) -> Box<dyn Value> {
-        let value_tokens = [
-            TokenType::FloatLiteral,
-            TokenType::StringLiteral,
-            TokenType::BooleanLiteral,
-            TokenType::IntegerLiteral,
-            TokenType::Object,
-        ];
+    fn children(&self) -> Vec<&dyn Value> {
+        self.elements.iter().map(|e| e.as_ref()).collect()
+    }
 
-        if tokens.len() == 1 {
-            if value_tokens.contains(&tokens[0].token_type) {
-                let (val, _) = AstNode::generate_expression(&tokens[0..1], variable_lst, fn_lst);
-                return val;
-            }
-            else {
-                panic!("Last token left `{:?}` not a value in [fn Operation::extract_operation_h]", tokens[0]);
-            }
-        }
+    fn as_array_literal(&self) -> Option<&ArrayLiteral> {
+        Some(self)
+    }
 
-        let mut op_idx = 0;
-        let mut op_priority = 0;
+    fn map_children(self: Box<Self>, f: &mut dyn FnMut(Box<dyn Value>) -> Box<dyn Value>) -> Box<dyn Value> {
+        let ArrayLiteral { elements, dtype } = *self;
+        Box::new(ArrayLiteral { elements: elements.into_iter().map(f).collect(), dtype })
+    }
+}
 
-        for (i, t) in tokens.iter().enumerate() {
-            if let Ok(op) = OperationType::new(t) {
-                let p = op.get_priority();
-                if p > op_priority {
-                    op_idx = i;
-                    op_priority = p;
-                }
-            }
-        }
+impl Value for TupleLiteral {
+    fn dtype(&self) -> DataType {
+        self.dtype.clone()
+    }
 
-        if op_idx == 0 {
-            panic!("[fn Operations::extract_operation_h] no operation found in `{:?}`", tokens);
-        }
+    fn value(&self) -> String {
+        let elements = self.elements.iter().map(|e| e.value()).collect::<Vec<String>>();
+        format!("({})", elements.join(", "))
+    }
 
-        let mut op = Operation {
-            opd_1: Self::extract_operation_h(&tokens[..op_idx], variable_lst, fn_lst),
-            opd_2: Self::extract_operation_h(&tokens[(op_idx+1)..], variable_lst, fn_lst),
-            op: OperationType::new(&tokens[op_idx]).unwrap(),
-            ret_type: DataType::Bool,
-        };
-        op.gen_return_t();
+    fn children(&self) -> Vec<&dyn Value> {
+        self.elements.iter().map(|e| e.as_ref()).collect()
+    }
 
-        Box::new(op)
+    fn map_children(self: Box<Self>, f: &mut dyn FnMut(Box<dyn Value>) -> Box<dyn Value>) -> Box<dyn Value> {
+        let TupleLiteral { elements, dtype } = *self;
+        Box::new(TupleLiteral { elements: elements.into_iter().map(f).collect(), dtype })
     }
 }
 
+impl Value for Conditional {
+    fn dtype(&self) -> DataType {
+        self.dtype.clone()
+    }
+
+    fn value(&self) -> String {
+        format!("({} ? {} : {})", self.condition.value(), self.then_value.value(), self.else_value.value())
+    }
 
-#[derive(Debug)]
-pub enum AstNode {
-    Variable(Variable),  
-    Function(Function),  
-    CodeBlock(CodeBlock),
-    AssignmentStatement(AssignmentStatement),
-    Operation(Operation),
-    Loop(Loop),
-}
+    fn children(&self) -> Vec<&dyn Value> {
+        vec![self.condition.as_ref(), self.then_value.as_ref(), self.else_value.as_ref()]
+    }
 
+    fn map_children(self: Box<Self>, f: &mut dyn FnMut(Box<dyn Value>) -> Box<dyn Value>) -> Box<dyn Value> {
+        let Conditional { condition, then_value, else_value, dtype } = *self;
+        Box::new(Conditional { condition: f(condition), then_value: f(then_value), else_value: f(else_value), dtype })
+    }
+}
 
-impl AstNode {
-    pub fn generate_function(s: &[Token], var_lst: &mut VarLst, fn_lst: &mut FnLst) -> Function {
-        var_lst.push_scope();
+impl Value for BlockExpression {
+    fn dtype(&self) -> DataType {
+        self.dtype.clone()
+    }
 
-        if !(s[0].token_type == TokenType::Keyword && s[0].value == "fn") {
-            panic!("Error, token list does not start with");
+    fn value(&self) -> String {
+        match &self.value {
+            Some(v) if self.block.statements.is_empty() => format!("{{ {} }}", v.value()),
+            Some(v) => format!("{{ ...; {} }}", v.value()),
+            None => "{ ... }".to_string(),
         }
+    }
 
-        assert_eq!(s[1].token_type, TokenType::Object);
-        let mut func = Function{
-            name: s[1].value.to_string(),
-            parameters: vec![],
-            body: CodeBlock{statements: vec![]}
-        };
+    /// Only the trailing expression, since the values in the block's statements aren't operands
+    fn children(&self) -> Vec<&dyn Value> {
+        self.value.iter().map(|v| v.as_ref()).collect()
+    }
 
-        assert_eq!(s[2].token_type, TokenType::OpenParen);
+    fn as_block_expression(&self) -> Option<&BlockExpression> {
+        Some(self)
+    }
 
-        let mut i = 3;
-        while s[i].token_type != TokenType::CloseParen {
-            if s[i].token_type == TokenType::Comma {
-                i += 1;
-                continue;
-            }
+    /// Maps the values in the block's statements too, though they aren't children
+    fn map_children(self: Box<Self>, f: &mut dyn FnMut(Box<dyn Value>) -> Box<dyn Value>) -> Box<dyn Value> {
+        let BlockExpression { block, value, dtype } = *self;
+        let block = MapValues(&mut *f).transform_block(block);
+        Box::new(BlockExpression { block, value: value.map(f), dtype })
+    }
+}
 
-            assert_eq!(s[i].token_type, TokenType::DataType);
-            let var_type = DataType::new(s[i].value);
+impl Value for Call {
+    fn dtype(&self) -> DataType {
+        self.dtype.clone()
+    }
 
-            assert_eq!(s[i+1].token_type, TokenType::Object);
-            let var_name = s[i+1].value.to_string();
+    fn value(&self) -> String {
+        let args = self.args.iter().map(|a| a.value()).collect::<Vec<String>>();
+        format!("{}({})", self.name, args.join(", "))
+    }
 
-            var_lst.insert(var_name.clone(), var_type.clone());
-            func.parameters.push(Variable {
-                name: var_name,
-                dtype: var_type,
-            });
-            i += 2;
-        }
+    fn children(&self) -> Vec<&dyn Value> {
+        self.args.iter().map(|a| a.as_ref()).collect()
+    }
 
-        assert_eq!(s[i+1].token_type, TokenType::OpenCurlyBrace);
-        (func.body, _) = Self::generate_code_block(&s[(i+1)..], var_lst, fn_lst);
+    fn as_call(&self) -> Option<&Call> {
+        Some(self)
+    }
 
-        var_lst.pop_scope();
-        func
+    fn map_children(self: Box<Self>, f: &mut dyn FnMut(Box<dyn Value>) -> Box<dyn Value>) -> Box<dyn Value> {
+        let Call { name, args, dtype, module } = *self;
+        Box::new(Call { name, args: args.into_iter().map(f).collect(), dtype, module })
     }
+}
 
-    fn generate_code_block(s: &[Token], var_lst: &mut VarLst, fn_lst: &mut FnLst) -> (CodeBlock, usize) {
-        assert_eq!(s[0].token_type, TokenType::OpenCurlyBrace);
-        var_lst.push_scope();
+impl Debug for dyn Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.value())
+    }
+}
 
-        let mut block = CodeBlock {statements: vec![]};
 
-        let mut i = 1;
-        loop {
-            match s[i].token_type {
-                TokenType::NewLine => {
-                    i += 1;
-                    continue;
-                }
-                TokenType::DataType => {
-                    if s[i+1].token_type == TokenType::Object && s[i+2].token_type == TokenType::AssignmentOperator {
-                        let var_type = DataType::new(s[i].value);
-                        let var_name = s[i+1].value.to_string();
-                        let var = Variable {
-                            name: var_name.clone(),
-                            dtype: var_type.clone(),
-                        };
-                        
-                        var_lst.insert(var_name, var_type);
-                        
+/// Renders a float the same way wherever it appears, so equal values always compare equal as
+/// text: the shortest representation that parses back to the same value, with a `.0` on whole
+/// numbers (`10.0`, `0.1`, `1e20`)
+pub fn format_float(f: f64) -> String {
+    format!("{:?}", f)
+}
 
-                        let (val, num_tokens) = Self::generate_expression(&s[i+3..], var_lst, fn_lst);
+#[derive(Debug, Clone)]
+pub struct Literal {
+    pub(crate) value: Rc<str>,
+    pub(crate) dtype: DataType,
+}
 
-                        let assignment = AssignmentStatement {
-                            dst: var,
-                            src: val,
-                        };
-                        block.statements.push(AstNode::AssignmentStatement(assignment));
-                        i += 3 + num_tokens;
+thread_local! {
+    /// Literals parsed so far, by their source text and type. The same few literals such as `0`
+    /// and `true` make up most of a program's, so each is only validated once per compilation,
+    /// see `CompilationScope`.
+    static LITERALS: RefCell<HashMap<(Rc<str>, DataType), Literal>> = RefCell::new(HashMap::new());
+}
 
+impl Literal {
+    /// Builds a literal of type `dtype` from its source text, validating that the text
+    /// actually represents a value of that type and normalizing it.
+    pub fn parse_typed(text: &str, dtype: DataType) -> Result<Literal> {
+        let key = (intern(text), dtype);
+        if let Some(lit) = LITERALS.with(|cache| cache.borrow().get(&key).cloned()) {
+            return Ok(lit);
+        }
+        let lit = Self::parse_uncached(text, key.1.clone())?;
+        LITERALS.with(|cache| cache.borrow_mut().insert(key, lit.clone()));
+        Ok(lit)
+    }
+
+    fn parse_uncached(text: &str, dtype: DataType) -> Result<Literal> {
+        let value = match &dtype {
+            DataType::I64 | DataType::U64 | DataType::U8 => {
+                let digits = crate::lexer::normalize_int_literal(text).unwrap_or_else(|| text.to_string());
+                let parsed = match dtype {
+                    DataType::I64 => digits.parse::<i64>().map(|n| n.to_string()),
+                    DataType::U64 => digits.parse::<u64>().map(|n| n.to_string()),
+                    _ => digits.parse::<u8>().map(|n| n.to_string()),
+                };
+                match parsed {
+                    Ok(n) => n,
+                    Err(e) if matches!(e.kind(), IntErrorKind::PosOverflow) => {
+                        return Err(anyhow!("integer literal `{}` is too large for `{}`", text, dtype));
                     }
-                    else {
-                        panic!("This probably shouldn't happen");
+                    Err(e) if matches!(e.kind(), IntErrorKind::NegOverflow) => {
+                        return Err(anyhow!("integer literal `{}` is too small for `{}`", text, dtype));
                     }
+                    Err(_) => return Err(anyhow!("integer literal `{}` is not a valid `{}`", text, dtype)),
                 }
-                TokenType::Keyword => {
-                    if s[i].value == "while" {
-                        let (loop_obj, l) = Self::parse_loop(&s[i..], var_lst, fn_lst);
-                        block.statements.push(AstNode::Loop(loop_obj));
-                        i += l;
-                    }
-                    else {
-                        panic!("Unsupported keyword");
-                    }
+            }
+            DataType::F64 => {
+                let parsed = if text.starts_with("0x") || text.starts_with("0X") {
+                    crate::lexer::parse_hex_float(text).ok()
+                } else {
+                    text.replace('_', "").parse::<f64>().ok()
+                };
+                match parsed {
+                    Some(f) if f.is_finite() => format_float(f),
+                    _ => return Err(anyhow!("malformed float literal: `{}`", text)),
+                }
+            }
+            DataType::Bool => {
+                if text != "true" && text != "false" {
+                    return Err(anyhow!("malformed boolean literal: `{}`", text));
+                }
+                text.to_string()
+            }
+            DataType::Char => {
+                let inner = text.strip_prefix('\'').and_then(|t| t.strip_suffix('\''));
+                match inner.map(crate::lexer::unescape) {
+                    Some(Ok(c)) if c.chars().count() == 1 => text.to_string(),
+                    _ => return Err(anyhow!("malformed char literal: `{}`", text)),
+                }
+            }
+            DataType::String | DataType::Vec { .. } => {
+                if !(text.len() >= 2 && text.starts_with('"') && text.ends_with('"')) {
+                    return Err(anyhow!("malformed string literal: `{}`", text));
                 }
-                TokenType::Object => {
-                    if let Some(dt) = var_lst.get(&s[i].value.to_string()) {
-                        let (op, l) = Operation::extract_operation(&s[(i+2)..], var_lst, fn_lst);
+                text.to_string()
+            }
+            DataType::Array { .. } | DataType::Tuple(_) | DataType::Unit => {
+                return Err(anyhow!("there are no literals of type `{}`", dtype));
+            }
+        };
 
-                        let mut assignment = AssignmentStatement {
-                            dst: Variable {
-                                name: s[i].value.to_string(),
-                                dtype: dt
-                            },
-                            src: op,
-                        };
+        Ok(Literal { value: intern(&value), dtype })
+    }
+}
 
-                        block.statements.push(AstNode::AssignmentStatement(assignment));
-                        i += 2 + l;
-                    }
-                    else {
-                        panic!("unknown object");
+#[derive(Debug, Clone)]
+pub struct Variable {
+    pub(crate) name: Rc<str>,
+    pub(crate) dtype: DataType,
+    /// True if the variable is one of the enclosing function's parameters rather than a local
+    is_parameter: bool,
+}
+
+#[derive(Debug)]
+pub struct Function {
+    pub(crate) name: String,
+    is_public: bool,
+    /// The text of the `///` comments directly above the function
+    doc: Option<String>,
+    pub(crate) parameters: Vec<Variable>,
+    /// The default value of each parameter, if it has one. Only trailing parameters have defaults.
+    pub(crate) defaults: Vec<Option<Box<dyn Value>>>,
+    /// `Unit` for functions declared without a return type
+    return_type: DataType,
+    pub(crate) body: CodeBlock,
+    /// Every local declared in the body with its type and where it was declared
+    locals: Vec<(String, DataType, Span)>,
+    /// Where the function's name is defined
+    pub(crate) span: Span,
+    /// The file the function is defined in: 0 for the file being compiled, and a number of its
+    /// own for each imported file, so that functions in different files may share a name
+    pub(crate) module: usize,
+}
+
+impl Function {
+    /// Returns the locals declared in the function's body, in declaration order. Parameters
+    /// aren't included.
+    pub fn locals(&self) -> &[(String, DataType, Span)] {
+        &self.locals
+    }
+
+    pub fn return_type(&self) -> &DataType {
+        &self.return_type
+    }
+
+    pub fn doc(&self) -> Option<&str> {
+        self.doc.as_deref()
+    }
+
+    pub fn is_public(&self) -> bool {
+        self.is_public
+    }
+
+    /// Returns the signature that calls to the function are checked against
+    pub(crate) fn fn_signature(&self) -> FnSignature {
+        FnSignature {
+            params: self.parameters.iter().map(|p| (p.name.to_string(), p.dtype.clone())).collect(),
+            required: self.defaults.iter().filter(|d| d.is_none()).count(),
+            ret_type: self.return_type.clone(),
+            span: self.span,
+            module: None,
+        }
+    }
+
+    /// Renders the function's signature as in `add(int, int) -> int`
+    pub fn signature_string(&self) -> String {
+        format_signature(&self.name, self.parameters.iter().map(|p| &p.dtype), &self.return_type)
+    }
+
+    /// Renders the function back to source, one statement per line with four-space indents.
+    /// Expressions are written as their `Value::value`, so operations come out fully parenthesised.
+    pub fn to_source(&self) -> String {
+        let mut out = String::new();
+        for line in self.doc.iter().flat_map(|doc| doc.lines()) {
+            out += &format!("/// {}\n", line);
+        }
+        let params = self.parameters.iter().zip(&self.defaults).map(|(p, default)| match default {
+            Some(default) => format!("{} {} = {}", p.dtype, p.name, default.value()),
+            None => format!("{} {}", p.dtype, p.name),
+        }).collect::<Vec<String>>();
+        let ret_type = match self.return_type {
+            DataType::Unit => String::new(),
+            ref dt => format!(" {}", dt),
+        };
+        let visibility = if self.is_public { "pub " } else { "" };
+        out += &format!("{}fn {}({}){} {{\n", visibility, self.name, params.join(", "), ret_type);
+        write_block(&self.body, 1, &mut out);
+        out += "}\n";
+        out
+    }
+}
+
+/// Writes each statement of `block` on its own line, `depth` levels deep
+fn write_block(block: &CodeBlock, depth: usize, out: &mut String) {
+    let indent = "    ".repeat(depth);
+    for node in &block.statements {
+        let line = match node {
+            AstNode::AssignmentStatement(a) if a.is_declaration => format!("{} {} = {}", a.dst.dtype, a.dst.name, a.src.value()),
+            AstNode::AssignmentStatement(a) => format!("{} = {}", a.dst.name, a.src.value()),
+            AstNode::IndexAssignment(a) => format!("{}[{}] = {}", a.dst.name, a.index.value(), a.src.value()),
+            AstNode::ReturnStatement(ReturnStatement { value: Some(v) }) => format!("return {}", v.value()),
+            AstNode::ReturnStatement(ReturnStatement { value: None }) => "return".to_string(),
+            AstNode::ExpressionStatement(v) => v.value(),
+            AstNode::Operation(op) => op.value(),
+            AstNode::Variable(v) => v.value(),
+            AstNode::Break => "break".to_string(),
+            AstNode::Continue => "continue".to_string(),
+            AstNode::Loop(l) => {
+                *out += &format!("{}while {} {{\n", indent, l.condition.value());
+                write_block(&l.body, depth + 1, out);
+                "}".to_string()
+            }
+            AstNode::If(if_obj) => {
+                *out += &indent;
+                if_obj.write_chain(depth, out);
+                continue;
+            }
+            AstNode::CodeBlock(inner) => {
+                *out += &format!("{}{{\n", indent);
+                write_block(inner, depth + 1, out);
+                "}".to_string()
+            }
+            AstNode::Function(func) => func.to_source().trim_end().replace('\n', &format!("\n{}", indent)),
+        };
+        *out += &format!("{}{}\n", indent, line);
+    }
+}
+
+#[derive(Debug)]
+pub struct Loop {
+    pub(crate) condition: Box<dyn Value>,
+    pub(crate) body: CodeBlock,
+}
+
+#[derive(Debug)]
+pub struct If {
+    pub(crate) condition: Box<dyn Value>,
+    pub(crate) body: CodeBlock,
+    pub(crate) else_branch: Option<ElseBranch>,
+}
+
+/// What runs when an `If`'s condition is false
+#[derive(Debug)]
+pub enum ElseBranch {
+    Block(CodeBlock),
+    /// An `else if`, held directly rather than in a block of its own so that a long chain stays flat
+    If(Box<If>),
+}
+
+impl If {
+    /// Returns the arms of the `if`/`else if` chain starting at this one, in order
+    pub(crate) fn chain(&self) -> impl Iterator<Item = &If> {
+        std::iter::successors(Some(self), |arm| match &arm.else_branch {
+            Some(ElseBranch::If(next)) => Some(next.as_ref()),
+            _ => None,
+        })
+    }
+
+    /// Returns the `else` block that ends the chain, if there is one
+    pub(crate) fn final_else(&self) -> Option<&CodeBlock> {
+        match &self.chain().last()?.else_branch {
+            Some(ElseBranch::Block(block)) => Some(block),
+            _ => None,
+        }
+    }
+
+    /// Returns the body of every arm of the chain, followed by the final `else` block
+    pub(crate) fn blocks(&self) -> impl Iterator<Item = &CodeBlock> {
+        self.chain().map(|arm| &arm.body).chain(self.final_else())
+    }
+
+    /// Writes the chain starting after the indent of its first line, with each `else if` arm on
+    /// the line that closes the arm before it
+    fn write_chain(&self, depth: usize, out: &mut String) {
+        let indent = "    ".repeat(depth);
+        for (i, arm) in self.chain().enumerate() {
+            let keyword = if i == 0 { "if" } else { " else if" };
+            *out += &format!("{} {} {{\n", keyword, arm.condition.value());
+            write_block(&arm.body, depth + 1, out);
+            *out += &format!("{}}}", indent);
+        }
+        if let Some(block) = self.final_else() {
+            *out += " else {\n";
+            write_block(block, depth + 1, out);
+            *out += &format!("{}}}", indent);
+        }
+        *out += "\n";
+    }
+}
+
+#[derive(Debug)]
+pub struct ReturnStatement {
+    pub(crate) value: Option<Box<dyn Value>>,
+}
+
+
+#[derive(Debug)]
+pub struct CodeBlock {
+    pub(crate) statements: Vec<AstNode>,
+}
+
+impl CodeBlock {
+    /// Builds a block from already parsed statements, e.g. for generated code
+    pub fn new(statements: Vec<AstNode>) -> Self {
+        CodeBlock { statements }
+    }
+
+    pub fn statements(&self) -> &[AstNode] {
+        &self.statements
+    }
+
+    /// Appends `node` as the block's last statement
+    pub fn push(&mut self, node: AstNode) {
+        self.statements.push(node);
+    }
+}
+
+#[derive(Debug)]
+pub struct AssignmentStatement {
+    pub(crate) dst: Variable,
+    pub(crate) src: Box<dyn Value>,
+    /// True for `int x = ...`, false when reassigning an existing variable
+    pub(crate) is_declaration: bool,
+}
+
+/// An assignment to a vector element such as `xs[0] = 5`
+#[derive(Debug)]
+pub struct IndexAssignment {
+    pub(crate) dst: Variable,
+    pub(crate) index: Box<dyn Value>,
+    pub(crate) src: Box<dyn Value>,
+}
+
+/// An element access such as `xs[0]`
+#[derive(Debug)]
+pub struct Index {
+    pub(crate) base: Box<dyn Value>,
+    pub(crate) index: Box<dyn Value>,
+    dtype: DataType,
+}
+
+impl Index {
+    fn new(base: Box<dyn Value>, index: Box<dyn Value>) -> Result<Self> {
+        let base_type = base.dtype();
+        let dtype = match base_type.element_type() {
+            Some(inner) => inner.clone(),
+            // Strings are indexed by byte, like the `byte[]` of a string literal
+            None if base_type == DataType::String => DataType::U8,
+            None => return Err(anyhow!("cannot index into `{}` of type `{}`", base.value(), base_type)),
+        };
+        if ![DataType::I64, DataType::U64, DataType::U8].contains(&index.dtype()) {
+            return Err(anyhow!("index `{}` must be an integer, found `{}`", index.value(), index.dtype()));
+        }
+        check_constant_index(&base.value(), &base_type, index.as_ref())?;
+        Ok(Index { base, index, dtype })
+    }
+}
+
+/// Rejects a literal index that's out of bounds for a fixed-size array. Other indices are
+/// checked when the program runs.
+fn check_constant_index(base: &str, base_type: &DataType, index: &dyn Value) -> Result<()> {
+    let (DataType::Array { len, .. }, Some(lit)) = (base_type, index.as_literal()) else {
+        return Ok(());
+    };
+    let in_bounds = lit.value.replace('_', "").parse::<usize>().is_ok_and(|i| i < *len);
+    if !in_bounds {
+        return Err(anyhow!("index {} is out of bounds for `{}` of type `{}`", lit.value, base, base_type));
+    }
+    Ok(())
+}
+
+/// A conversion between numeric types such as `1e3 as int`
+#[derive(Debug)]
+pub struct Cast {
+    pub(crate) value: Box<dyn Value>,
+    dtype: DataType,
+}
+
+impl Cast {
+    fn new(value: Box<dyn Value>, dtype: DataType) -> Result<Self> {
+        let from = value.dtype();
+        if !from.is_numeric() || !dtype.is_numeric() {
+            return Err(anyhow!("cannot cast `{}` of type `{}` to `{}`", value.value(), from, dtype));
+        }
+        Ok(Cast { value, dtype })
+    }
+}
+
+/// A call to a function or builtin such as `add(1, 2)` or `print(x)`
+#[derive(Debug)]
+pub struct Call {
+    pub(crate) name: Rc<str>,
+    pub(crate) args: Vec<Box<dyn Value>>,
+    dtype: DataType,
+    /// The `Function::module` of the called function if it's imported, or `None` if it's in the
+    /// same file as the call
+    pub(crate) module: Option<usize>,
+}
+
+impl Call {
+    /// Parses the arguments in `arg_tokens` (everything between the parentheses) and checks them
+    /// against the signature of `name`
+    fn parse(name: &str, arg_tokens: &[Token], variable_lst: &VarLst, fn_lst: &FnLst) -> Result<Self> {
+        let arg_slices = Self::split_arguments(arg_tokens)?;
+
+        if BUILTINS.contains(&name) {
+            let args = arg_slices.iter()
+                .map(|a| Operation::extract_operation_h(a, variable_lst, fn_lst, None))
+                .collect::<Result<Vec<Box<dyn Value>>>>()?;
+            if args.len() != 1 {
+                return Err(anyhow!("`{}` takes 1 argument but {} were given", name, args.len()));
+            }
+            let dtype = match name {
+                // The length of a string is its number of bytes
+                "len" => {
+                    let arg_type = args[0].dtype();
+                    if !arg_type.is_string() && arg_type.element_type().is_none() {
+                        return Err(anyhow!("`len` requires a string or vector, found `{}` of type `{}`", args[0].value(), arg_type));
                     }
+                    DataType::I64
                 }
-                TokenType::CloseCurlyBrace => break,
-                _ => {
-                    panic!("Unsupported syntax at {:?}", &s[i..(i+5)]);
-                }
+                _ => DataType::Unit,
+            };
+            return Ok(Call { name: intern(name), args, dtype, module: None });
+        }
+
+        let Some(signature) = fn_lst.get(name) else {
+            if let Some(qualified) = fn_lst.qualified_name(name) {
+                return Err(anyhow!("`{}` was imported with an alias and must be called as `{}`", name, qualified));
             }
+            return Err(anyhow!("call to undefined function `{}`", name));
+        };
+        let (params, ret_type) = (&signature.params, signature.ret_type.clone());
+        if signature.required == params.len() && arg_slices.len() != params.len() {
+            return Err(anyhow!("`{}` takes {} arguments but {} were given", name, params.len(), arg_slices.len()));
+        }
+        if arg_slices.len() < signature.required || arg_slices.len() > params.len() {
+            return Err(anyhow!(
+                "`{}` takes {} to {} arguments but {} were given", name, signature.required, params.len(), arg_slices.len()
+            ));
         }
 
-        var_lst.pop_scope();
-        (block, i)
+        // Omitted arguments are filled in from the parameters' defaults when the call is evaluated
+        let args = arg_slices.iter().zip(params.iter())
+            .map(|(arg, (_, param_type))| Operation::extract_operation_h(arg, variable_lst, fn_lst, Some(param_type)))
+            .collect::<Result<Vec<Box<dyn Value>>>>()?;
+        let mismatch = args.iter().zip(params.iter())
+            .find(|(arg, (_, param_type))| !param_type.is_assignable_from(&arg.dtype()));
+        if let Some((arg, (param, param_type))) = mismatch {
+            let arg_types = args.iter().map(|a| a.dtype()).collect::<Vec<_>>();
+            return Err(anyhow!(
+                "argument `{}` for parameter `{}` has type `{}`, expected `{}`: expected `{}`, got `{}`",
+                arg.value(), param, arg.dtype(), param_type,
+                signature.signature_string(name), format_signature(name, arg_types.iter(), &DataType::Unit)
+            ));
+        }
+        Ok(Call { name: intern(name), args, dtype: ret_type, module: signature.module })
     }
 
-    fn parse_loop(s: &[Token], var_lst: &mut VarLst, fn_lst: &mut FnLst) -> (Loop, usize) {
-        assert_eq!(s[0].token_type, TokenType::Keyword);
-        assert_eq!(s[0].value, "while");
+    /// Returns the name of the called function without any module alias, so `sqrt` for
+    /// `m.sqrt(x)`. Along with `module`, this is how the function is looked up.
+    pub(crate) fn function_name(&self) -> &str {
+        self.name.rsplit('.').next().unwrap_or(&self.name)
+    }
 
-        var_lst.push_scope();
+    /// Splits call arguments at the commas outside of any brackets. A single trailing comma is
+    /// allowed, as in `f(1, 2,)`, but not an empty argument such as in `f(1,,2)` or `f(,)`.
+    fn split_arguments<'t, 'a>(tokens: &'t [Token<'a>]) -> Result<Vec<&'t [Token<'a>]>> {
+        if tokens.is_empty() {
+            return Ok(vec![]);
+        }
 
-        let (condition, i) = Operation::extract_operation(&s[1..], var_lst, fn_lst);
+        let mut args = vec![];
+        let mut start = 0;
+        let mut depth = 0;
+        for (i, t) in tokens.iter().enumerate() {
+            match t.token_type {
+                TokenType::OpenParen | TokenType::OpenSquareBracket => depth += 1,
+                TokenType::CloseParen | TokenType::CloseSquareBracket => depth -= 1,
+                TokenType::Comma if depth == 0 => {
+                    args.push(&tokens[start..i]);
+                    start = i + 1;
+                }
+                _ => {}
+            }
+        }
+        if start < tokens.len() {
+            args.push(&tokens[start..]);
+        }
 
-        let mut idx = i + 1;
+        // Each empty argument is followed by a comma, since a trailing empty one was left out
+        if let Some(arg) = args.iter().position(|a| a.is_empty()) {
+            let comma = args[..arg].iter().map(|a| a.len() + 1).sum::<usize>();
+            let span = tokens[comma].span;
+            return Err(error_at(span, "expected an expression before `,`"));
+        }
+        Ok(args)
+    }
+}
+
+/// An array literal such as `[1, 2, 3]`
+#[derive(Debug)]
+pub struct ArrayLiteral {
+    pub(crate) elements: Vec<Box<dyn Value>>,
+    pub(crate) dtype: DataType,
+}
 
-        assert_eq!(s[idx].token_type, TokenType::OpenCurlyBrace);
+impl ArrayLiteral {
+    /// Parses the elements in `tokens` (everything between the brackets). Elements must share a
+    /// common type, and an empty array takes its type from `expected`, e.g. in `int[] xs = []`.
+    fn parse(tokens: &[Token], variable_lst: &VarLst, fn_lst: &FnLst, expected: Option<&DataType>) -> Result<Self> {
+        let expected_inner = expected.and_then(|dt| dt.element_type());
 
-        let (code_block, i) = Self::generate_code_block(&s[idx..], var_lst, fn_lst);
-        idx += i;
+        let elements = Call::split_arguments(tokens)?.iter()
+            .map(|e| Operation::extract_operation_h(e, variable_lst, fn_lst, expected_inner))
+            .collect::<Result<Vec<Box<dyn Value>>>>()?;
 
-        let loop_var = Loop {
-            condition: condition,
-            body: code_block,
+        let mut inner = match (elements.first(), expected_inner) {
+            (_, Some(dt)) => dt.clone(),
+            (Some(first), None) => first.dtype(),
+            (None, None) => return Err(anyhow!("cannot infer element type of empty array")),
         };
+        for element in elements.iter() {
+            inner = inner.unify(&element.dtype()).ok_or_else(|| anyhow!(
+                "array element `{}` has type `{}`, expected `{}`", element.value(), element.dtype(), inner
+            ))?;
+        }
 
-        var_lst.pop_scope();
-        (loop_var, idx)        
+        // A literal assigned to a fixed-size array takes its type, if it has the right length
+        if let Some(DataType::Array { len, .. }) = expected {
+            if elements.len() != *len {
+                return Err(anyhow!(
+                    "array literal has {} elements, but `{}` holds {}", elements.len(), expected.unwrap(), len
+                ));
+            }
+            return Ok(ArrayLiteral { elements, dtype: DataType::Array { inner: Box::new(inner), len: *len } });
+        }
+        Ok(ArrayLiteral { elements, dtype: DataType::Vec { inner: Box::new(inner) } })
     }
 
-    fn generate_expression(s: &[Token], var_lst: &VarLst, fn_lst: &FnLst) -> (Box<dyn Value>, usize) {
-        if !Operation::exists_inline(s) {
-            match s[0].token_type {
-                TokenType::FloatLiteral => {
-                    let res = Literal {
-                        value: s[0].value.to_string(),
-                        dtype: DataType::F64,
-                    };
-                    return (Box::new(res), 1);
+    /// Returns the length along each dimension of a nested literal, e.g. `[2, 3]` for
+    /// `[[1, 2, 3], [4, 5, 6]]`, or `None` if its rows differ in length somewhere or a row isn't a
+    /// literal, so its length isn't known
+    pub fn shape(&self) -> Option<Vec<usize>> {
+        let mut row_shape = None;
+        for element in self.elements.iter() {
+            let shape = match element.as_array_literal() {
+                Some(row) => row.shape()?,
+                None if element.dtype().element_type().is_some() => return None,
+                None => vec![],
+            };
+            if row_shape.as_ref().is_some_and(|s| *s != shape) {
+                return None;
+            }
+            row_shape = Some(shape);
+        }
+        Some([vec![self.elements.len()], row_shape.unwrap_or_default()].concat())
+    }
+
+    /// Returns true if the rows of this literal are literals with a consistent shape of their
+    /// own, but not the same one, as in `[[1, 2], [3]]`
+    pub fn is_jagged(&self) -> bool {
+        let shapes = self.elements.iter()
+            .map(|e| e.as_array_literal().and_then(|row| row.shape()))
+            .collect::<Option<Vec<Vec<usize>>>>();
+        shapes.is_some_and(|shapes| shapes.windows(2).any(|w| w[0] != w[1]))
+    }
+}
+
+/// A tuple literal such as `(1, true)`. Parentheses only make a tuple when they contain a comma;
+/// `(a)` is just `a`.
+#[derive(Debug)]
+pub struct TupleLiteral {
+    pub(crate) elements: Vec<Box<dyn Value>>,
+    dtype: DataType,
+}
+
+impl TupleLiteral {
+    fn new(elements: Vec<Box<dyn Value>>) -> Self {
+        let dtype = DataType::Tuple(elements.iter().map(|e| e.dtype()).collect());
+        TupleLiteral { elements, dtype }
+    }
+}
+
+/// A block used as a value, such as `{ int a = 1; a + 2 }`. It evaluates to its trailing
+/// expression, or to `()` if it ends with a statement.
+#[derive(Debug)]
+pub struct BlockExpression {
+    pub(crate) block: CodeBlock,
+    pub(crate) value: Option<Box<dyn Value>>,
+    pub(crate) dtype: DataType,
+}
+
+/// A conditional expression such as `cond ? a : b`
+#[derive(Debug)]
+pub struct Conditional {
+    pub(crate) condition: Box<dyn Value>,
+    pub(crate) then_value: Box<dyn Value>,
+    pub(crate) else_value: Box<dyn Value>,
+    pub(crate) dtype: DataType,
+}
+
+impl Conditional {
+    fn new(condition: Box<dyn Value>, then_value: Box<dyn Value>, else_value: Box<dyn Value>) -> Result<Self> {
+        if condition.dtype() != DataType::Bool {
+            return Err(anyhow!("condition `{}` must be a `bool`, found `{}`", condition.value(), condition.dtype()));
+        }
+        let dtype = then_value.dtype().unify(&else_value.dtype()).ok_or_else(|| anyhow!(
+            "branches `{}` and `{}` have incompatible types `{}` and `{}`",
+            then_value.value(), else_value.value(), then_value.dtype(), else_value.dtype()
+        ))?;
+        Ok(Conditional { condition, then_value, else_value, dtype })
+    }
+
+    /// Returns the indices of the `?` and its matching `:` if `tokens` is a conditional expression.
+    /// Nested conditionals are right associative, so `a ? b : c ? d : e` splits at the first `?`.
+    fn split(tokens: &[Token]) -> Result<Option<(usize, usize)>> {
+        let mut depth = 0;
+        let mut question_idx = None;
+        let mut pending = 0;
+
+        for (i, t) in tokens.iter().enumerate() {
+            match t.token_type {
+                TokenType::OpenParen | TokenType::OpenSquareBracket => depth += 1,
+                TokenType::CloseParen | TokenType::CloseSquareBracket => depth -= 1,
+                TokenType::QuestionMark if depth == 0 => {
+                    question_idx.get_or_insert(i);
+                    pending += 1;
                 }
-                TokenType::IntegerLiteral => {
-                    let res = Literal {
-                        value: s[0].value.to_string(),
-                        dtype: DataType::I64,
-                    };
-                    return (Box::new(res), 1);
+                TokenType::Colon if depth == 0 => {
+                    if pending == 0 {
+                        return Err(anyhow!("`:` without a matching `?`"));
+                    }
+                    pending -= 1;
+                    if pending == 0 {
+                        return Ok(question_idx.map(|q| (q, i)));
+                    }
                 }
-                TokenType::BooleanLiteral => {
-                    let res = Literal {
-                        value: s[0].value.to_string(),
-                        dtype: DataType::Bool,
-                    };
-                    return (Box::new(res), 1);
+                _ => {}
+            }
+        }
+
+        match question_idx {
+            Some(_) => Err(anyhow!("expected `:` after `?` in conditional expression")),
+            None => Ok(None),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Operation {
+    pub(crate) opd_1: Box<dyn Value>,
+    pub(crate) opd_2: Box<dyn Value>,
+    pub(crate) op: OperationType,
+    ret_type: DataType,
+}
+
+impl Operation {
+    pub fn op(&self) -> OperationType {
+        self.op
+    }
+
+    /// The left operand
+    pub fn lhs(&self) -> &dyn Value {
+        self.opd_1.as_ref()
+    }
+
+    /// The right operand
+    pub fn rhs(&self) -> &dyn Value {
+        self.opd_2.as_ref()
+    }
+
+    /// The type of the operation's result, e.g. `bool` for comparisons
+    pub fn return_type(&self) -> &DataType {
+        &self.ret_type
+    }
+
+    /// Modifies the return type of the Operation object based on the types of the operands and operator
+    fn gen_return_t(&mut self) -> Result<()> {
+        let info = describe_operator(self.op);
+        let (dt_1, dt_2) = (self.opd_1.dtype(), self.opd_2.dtype());
+
+        for opd in [&self.opd_1, &self.opd_2] {
+            if opd.dtype() == DataType::Unit {
+                return Err(anyhow!("`{}` has no value and can't be used with `{}`", opd.value(), info.symbol));
+            }
+        }
+
+        // A constant zero divisor is always a mistake, so it's rejected instead of leaving float
+        // division to produce an infinity or NaN. `0.0 * -1.0` still folds to `-0.0`.
+        let zero_divisor = self.opd_2.as_literal().is_some_and(|lit| lit.value().parse::<f64>() == Ok(0.0));
+        if zero_divisor && [OperationType::Div, OperationType::Mod].contains(&self.op) {
+            let kind = if self.op == OperationType::Div { "division" } else { "modulo" };
+            return Err(anyhow!("{} by zero in `{}`", kind, self.value()));
+        }
+
+        let both_numeric = dt_1.is_numeric() && dt_2.is_numeric();
+        // A `byte` widens losslessly into either of the wider integer types
+        let widens = dt_1 == DataType::U8 || dt_2 == DataType::U8;
+        // Integers compare by value whatever their signedness, and `lint` warns about `int` vs `uint`
+        let numeric_mismatch = dt_1 != dt_2 && dt_1 != DataType::F64 && dt_2 != DataType::F64 && !widens &&
+            !self.op.is_comparison();
+
+        // `+` concatenates strings, including string literals
+        if self.op == OperationType::Add && dt_1.is_string() && dt_2.is_string() {
+            self.ret_type = DataType::String;
+            return Ok(());
+        }
+
+        match info.operands {
+            // This includes `%` on floats, which is typed `F64` and behaves like C's `fmod`: the
+            // result has the sign of the left operand, so `-5.5 % 2` is `-1.5`
+            OperandTypes::Numeric | OperandTypes::Ordered if both_numeric => {
+                if numeric_mismatch {
+                    return Err(anyhow!(
+                        "mismatched operand types `{}` and `{}` in `{}`", dt_1, dt_2, self.value()
+                    ));
                 }
-                TokenType::StringLiteral => {
-                    let res = Literal {
-                        value: s[0].value.to_string(),
-                        dtype: DataType::Vec { inner: Box::new(DataType::U8) },
-                    };
-                    return (Box::new(res), 1);
+            }
+            OperandTypes::Numeric => {
+                return Err(anyhow!(
+                    "`{}` requires numeric operands, found `{}` and `{}` in `{}`", info.symbol, dt_1, dt_2, self.value()
+                ));
+            }
+            OperandTypes::Ordered => {
+                if !(dt_1 == DataType::Char && dt_2 == DataType::Char) {
+                    return Err(anyhow!(
+                        "`{}` requires numeric or `char` operands, found `{}` and `{}` in `{}`", info.symbol, dt_1, dt_2, self.value()
+                    ));
+                }
+            }
+            OperandTypes::Equatable => {
+                if (both_numeric && numeric_mismatch) || (!both_numeric && dt_1.unify(&dt_2).is_none()) {
+                    return Err(anyhow!(
+                        "cannot compare `{}` and `{}` with `{}` in `{}`", dt_1, dt_2, info.symbol, self.value()
+                    ));
+                }
+            }
+            OperandTypes::Membership => {
+                let inner = match dt_2.element_type() {
+                    Some(inner) => inner,
+                    None => return Err(anyhow!(
+                        "`in` requires a vector on the right, found `{}` of type `{}`", self.opd_2.value(), dt_2
+                    )),
+                };
+                if !inner.is_assignable_from(&dt_1) && !dt_1.is_assignable_from(inner) {
+                    return Err(anyhow!(
+                        "cannot look for `{}` of type `{}` in `{}` of type `{}`", self.opd_1.value(), dt_1, self.opd_2.value(), dt_2
+                    ));
                 }
-                TokenType::Object => {
-                    let var_name = s[0].value.to_string();
+            }
+        }
 
-                    let var_type = match var_lst.get(&var_name) {
-                        Some(s) => s,
-                        None => panic!("Undefined variable: `{}`", var_name),
-                    };
-                    let var_type = var_type.clone();
+        self.ret_type = match info.result {
+            ResultType::Bool => DataType::Bool,
+            ResultType::Promoted if dt_1 == DataType::F64 || dt_2 == DataType::F64 => DataType::F64,
+            ResultType::Promoted if dt_1 == DataType::U8 => dt_2,
+            ResultType::Promoted => dt_1,
+        };
+        Ok(())
+    }
 
-                    let res = Variable {
-                        name: var_name,
-                        dtype: var_type,
-                    };
-                    return (Box::new(res), 1);
+    /// Returns the number of tokens in the expression at the start of `tokens`. The expression
+    /// ends at the first end token or unmatched closing bracket outside of any brackets.
+    fn expression_length(tokens: &[Token]) -> usize {
+        let end_tokens = [
+            TokenType::NewLine,
+            TokenType::OpenCurlyBrace,
+            TokenType::CloseCurlyBrace,
+            TokenType::SemiColon,
+            TokenType::Comma,
+            TokenType::Eof,
+        ];
+
+        let mut depth = 0;
+        for (i, t) in tokens.iter().enumerate() {
+            match t.token_type {
+                TokenType::OpenParen | TokenType::OpenSquareBracket => depth += 1,
+                TokenType::CloseParen | TokenType::CloseSquareBracket => {
+                    if depth == 0 {
+                        return i;
+                    }
+                    depth -= 1;
                 }
-                _ => panic!("Syntax error in value")
+                _ if depth == 0 && end_tokens.contains(&t.token_type) => return i,
+                _ => {}
             }
         }
+        tokens.len()
+    }
 
-        Operation::extract_operation(s, var_lst, fn_lst)
+    /// Returns the index of the token closing the bracket opened at `tokens[0]`
+    fn matching_bracket(tokens: &[Token]) -> Result<usize> {
+        let mut depth = 0;
+        for (i, t) in tokens.iter().enumerate() {
+            match t.token_type {
+                TokenType::OpenParen | TokenType::OpenSquareBracket => depth += 1,
+                TokenType::CloseParen | TokenType::CloseSquareBracket => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return Ok(i);
+                    }
+                }
+                _ => {}
+            }
+        }
+        Err(anyhow!("unclosed `{}` in expression", tokens[0].value))
     }
-}
 
+    /// `expected` is the type the expression should have if known from context (e.g. a declaration),
+    /// which literals without an explicit type adopt when compatible.
+    fn extract_operation(tokens: &[Token], variable_lst: &VarLst, fn_lst: &FnLst, expected: Option<&DataType>) -> Result<(Box<dyn Value>, usize)> {
+        let length = Self::expression_length(tokens);
+        if length == 0 {
+            AstNode::check_not_eof(tokens, 0)?;
+        }
+        Ok((Self::extract_operation_h(&tokens[..length], variable_lst, fn_lst, expected)?, length))
+    }
 
+    /// Preconditions:
+    /// - The tokens passed to it have no addition tokens past the end of the operations
+    fn extract_operation_h(tokens: &[Token], variable_lst: &VarLst, fn_lst: &FnLst, expected: Option<&DataType>) -> Result<Box<dyn Value>> {
+        if tokens.is_empty() {
+            return Err(anyhow!("expected an expression"));
+        }
+        if let Some(t) = tokens.iter().find(|t| t.token_type == TokenType::AssignmentOperator) {
+            return Err(error_at(t.span, "assignment is not an expression; did you mean `==`?"));
+        }
+
+        // Conditionals bind looser than any binary operator
+        if let Some((question_idx, colon_idx)) = Conditional::split(tokens)? {
+            let conditional = Conditional::new(
+                Self::extract_operation_h(&tokens[..question_idx], variable_lst, fn_lst, Some(&DataType::Bool))?,
+                Self::extract_operation_h(&tokens[(question_idx+1)..colon_idx], variable_lst, fn_lst, expected)?,
+                Self::extract_operation_h(&tokens[(colon_idx+1)..], variable_lst, fn_lst, expected)?,
+            )?;
+            return Ok(Box::new(conditional));
+        }
+
+        // The operation is split at every operator of the lowest priority outside of any
+        // brackets. The operands between them are folded from the left, which makes operators of
+        // equal priority left associative without recursing once per operator in `a + b + c...`.
+        let mut op_idxs = vec![];
+        let mut op_priority = usize::MAX;
+        let mut depth = 0;
+
+        for (i, t) in tokens.iter().enumerate() {
+            match t.token_type {
+                TokenType::OpenParen | TokenType::OpenSquareBracket => depth += 1,
+                TokenType::CloseParen | TokenType::CloseSquareBracket => depth -= 1,
+                _ => {}
+            }
+            // Operators at the start or directly after another operator are unary
+            let follows_operator = i > 0 && (OperationType::new(&tokens[i-1]).is_ok() || tokens[i-1].token_type == TokenType::UnaryOperator);
+            if depth != 0 || i == 0 || follows_operator {
+                continue;
+            }
+            if let Ok(op) = OperationType::new(t) {
+                let p = op.get_priority();
+                if p < op_priority {
+                    op_idxs.clear();
+                    op_priority = p;
+                }
+                if p == op_priority {
+                    op_idxs.push(i);
+                }
+            }
+        }
+
+        if op_idxs.is_empty() {
+            return match tokens[0].token_type {
+                TokenType::ArithmeticOperator | TokenType::UnaryOperator => UnaryOperation::extract(tokens, variable_lst, fn_lst, expected),
+                TokenType::ComparisonOperator => Err(anyhow!("expected an expression before `{}`", tokens[0].value)),
+                _ => AstNode::generate_postfix(tokens, variable_lst, fn_lst, expected),
+            };
+        }
+
+        // Operators of the same priority are either all arithmetic or all comparisons
+        let is_arithmetic = OperationType::new(&tokens[op_idxs[0]]).unwrap().is_arithmetic();
+        let opd_expected = if is_arithmetic { expected } else { None };
+
+        // Passes over the AST recurse once per level of nesting, so a chain as long as the stack
+        // allows is cut off here. Its operands are themselves at most this deep.
+        let mut acc = Self::extract_operation_h(&tokens[..op_idxs[0]], variable_lst, fn_lst, opd_expected)?;
+        let mut nesting = expression_depth(acc.as_ref());
+        for (k, &op_idx) in op_idxs.iter().enumerate() {
+            let end = op_idxs.get(k + 1).copied().unwrap_or(tokens.len());
+            let opd_2 = Self::extract_operation_h(&tokens[(op_idx+1)..end], variable_lst, fn_lst, opd_expected)?;
+            nesting = 1 + nesting.max(expression_depth(opd_2.as_ref()));
+            if nesting > MAX_EXPRESSION_DEPTH {
+                let t = &tokens[op_idx];
+                return Err(error_at(t.span, format!("expression is nested more than {} deep", MAX_EXPRESSION_DEPTH)));
+            }
+            let mut op = Operation {
+                opd_1: acc,
+                opd_2,
+                op: OperationType::new(&tokens[op_idx]).unwrap(),
+                ret_type: DataType::Bool,
+            };
+            op.gen_return_t()?;
+            acc = Box::new(op);
+        }
+
+        Ok(acc)
+    }
+}
+
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum UnaryOperationType {
+    Negate,
+    /// Unary `+`, which leaves a number as is. It's dropped while parsing, so `+x` is just `x`.
+    Plus,
+    /// Logical not, `!`, on a `Bool`
+    Not,
+    /// Bitwise not, `~`, which flips every bit of an integer
+    BitNot,
+}
+
+impl UnaryOperationType {
+    fn new(token: &Token) -> Result<Self> {
+        match token.value {
+            "-" => Ok(Self::Negate),
+            "+" => Ok(Self::Plus),
+            "!" => Ok(Self::Not),
+            "~" => Ok(Self::BitNot),
+            _ => Err(anyhow!("`{}` is not a unary operator", token.value)),
+        }
+    }
+
+    pub(crate) fn as_str(&self) -> &'static str {
+        match &self {
+            Self::Negate => "-",
+            Self::Plus => "+",
+            Self::Not => "!",
+            Self::BitNot => "~",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct UnaryOperation {
+    pub(crate) operand: Box<dyn Value>,
+    pub(crate) op: UnaryOperationType,
+    ret_type: DataType,
+}
+
+impl UnaryOperation {
+    fn new(op: UnaryOperationType, operand: Box<dyn Value>) -> Result<Self> {
+        let ret_type = Self::gen_return_t(op, &operand.value(), operand.dtype())?;
+        Ok(UnaryOperation { operand, op, ret_type })
+    }
+
+    /// Returns the type of applying `op` to `operand` of type `dtype`, or an error if it isn't allowed
+    fn gen_return_t(op: UnaryOperationType, operand: &str, dtype: DataType) -> Result<DataType> {
+        match op {
+            UnaryOperationType::Negate => {
+                if [DataType::U64, DataType::U8].contains(&dtype) {
+                    return Err(anyhow!("cannot negate unsigned value `{}` of type `{}`", operand, dtype));
+                }
+                if !dtype.is_numeric() {
+                    return Err(anyhow!("cannot negate `{}` of type `{}`", operand, dtype));
+                }
+            }
+            UnaryOperationType::Plus => {
+                if !dtype.is_numeric() {
+                    return Err(anyhow!("cannot apply unary `+` to `{}` of type `{}`", operand, dtype));
+                }
+            }
+            UnaryOperationType::Not => {
+                if dtype != DataType::Bool {
+                    return Err(anyhow!("`!` requires a `bool` operand, found `{}` of type `{}`", operand, dtype));
+                }
+            }
+            UnaryOperationType::BitNot => {
+                if ![DataType::I64, DataType::U64, DataType::U8].contains(&dtype) {
+                    return Err(anyhow!("`~` requires an integer operand, found `{}` of type `{}`", operand, dtype));
+                }
+            }
+        }
+        Ok(dtype)
+    }
+
+    /// Parses a unary operator applied to the rest of `tokens`. Negated number literals are folded
+    /// into a single literal, so that integers have their range checked with the sign applied.
+    fn extract(tokens: &[Token], variable_lst: &VarLst, fn_lst: &FnLst, expected: Option<&DataType>) -> Result<Box<dyn Value>> {
+        let op = UnaryOperationType::new(&tokens[0])?;
+        if tokens.len() == 1 {
+            return Err(anyhow!("expected an expression after `{}`", tokens[0].value));
+        }
+
+        let is_number = [TokenType::IntegerLiteral, TokenType::FloatLiteral].contains(&tokens[1].token_type);
+        if op == UnaryOperationType::Negate && tokens.len() == 2 && is_number {
+            let dtype = match tokens[1].token_type {
+                TokenType::IntegerLiteral => AstNode::int_literal_type(expected, variable_lst),
+                _ => variable_lst.options.default_float.clone(),
+            };
+            let dtype = Self::gen_return_t(op, tokens[1].value, dtype)?;
+            let text = format!("-{}", tokens[1].value);
+            let lit = Literal::parse_typed(&text, dtype)
+                .map_err(|e| error_at(tokens[0].span, e))?;
+            return Ok(Box::new(lit));
+        }
+
+        let operand = Operation::extract_operation_h(&tokens[1..], variable_lst, fn_lst, expected)?;
+        if op == UnaryOperationType::Plus {
+            Self::gen_return_t(op, &operand.value(), operand.dtype())?;
+            return Ok(operand);
+        }
+        Ok(Box::new(UnaryOperation::new(op, operand)?))
+    }
+}
+
+
+#[derive(Debug)]
+pub enum AstNode {
+    Variable(Variable),  
+    Function(Function),  
+    CodeBlock(CodeBlock),
+    AssignmentStatement(AssignmentStatement),
+    Operation(Operation),
+    Loop(Loop),
+    If(If),
+    ReturnStatement(ReturnStatement),
+    IndexAssignment(IndexAssignment),
+    /// An expression evaluated for its side effects, such as `print(x)`, whose value is discarded
+    ExpressionStatement(Box<dyn Value>),
+    Break,
+    Continue,
+}
+
+
+impl AstNode {
+    /// Parses every function in the token stream
+    pub fn generate_program(s: &[Token], var_lst: &mut VarLst, fn_lst: &mut FnLst) -> Result<Vec<Function>> {
+        let mut funcs = vec![];
+
+        let mut i = 0;
+        while i < s.len() {
+            if s[i].token_type == TokenType::NewLine {
+                i += 1;
+                continue;
+            }
+            if s[i].token_type == TokenType::Eof {
+                break;
+            }
+            // Imports are resolved before the program is lexed, see `imports::resolve_imports`
+            if s[i].keyword() == Some(Keyword::Import) {
+                if !funcs.is_empty() {
+                    return Err(error_at(s[i].span, "imports must come before the first function"));
+                }
+                if s.get(i + 1).map(|t| t.token_type) != Some(TokenType::StringLiteral) {
+                    return Err(error_at(s[i].span, "expected a quoted path after `import`"));
+                }
+                i += 2;
+                if s.get(i).and_then(|t| t.keyword()) == Some(Keyword::As) {
+                    if s.get(i + 1).map(|t| t.token_type) != Some(TokenType::Object) {
+                        return Err(error_at(s[i].span, "expected a module name after `as`"));
+                    }
+                    i += 2;
+                }
+                continue;
+            }
+
+            let starts_function = s[i].token_type == TokenType::DocComment ||
+                [Some(Keyword::Fn), Some(Keyword::Pub)].contains(&s[i].keyword());
+            if !starts_function && !funcs.is_empty() {
+                return Err(error_at(s[i].span, format!(
+                    "unexpected tokens after function definition, starting with `{}`",
+                    s[i].value.escape_debug()
+                )));
+            }
+
+            if !var_lst.is_recovering() {
+                let (func, l) = Self::generate_function(&s[i..], var_lst, fn_lst)?;
+                funcs.push(func);
+                i += l;
+                continue;
+            }
+
+            // A function that fails to parse is skipped, so the ones after it are still checked
+            let depth = var_lst.scope_depth();
+            let res = catch_parse(|| Self::generate_function(&s[i..], var_lst, fn_lst))
+                .unwrap_or_else(|e| Err(anyhow!("{}", e.message)));
+            match res {
+                Ok((func, l)) => {
+                    funcs.push(func);
+                    i += l;
+                }
+                Err(e) => {
+                    // A statement past the error limit has recorded its error already
+                    if var_lst.has_too_many_errors() || !var_lst.record_error(Diagnostic::error(e.to_string(), Span::default())) {
+                        break;
+                    }
+                    var_lst.truncate_scopes(depth);
+                    var_lst.take_locals();
+                    i += Self::function_len(&s[i..]);
+                }
+            }
+        }
+
+        Ok(funcs)
+    }
+
+    /// Returns the number of tokens up to and including the brace closing the body of the
+    /// function starting at `s[0]`, or all of them if there's no body
+    fn function_len(s: &[Token]) -> usize {
+        let Some(open) = s.iter().position(|t| t.token_type == TokenType::OpenCurlyBrace) else {
+            return s.len();
+        };
+        let mut depth = 0;
+        for (i, t) in s.iter().enumerate().skip(open) {
+            match t.token_type {
+                TokenType::OpenCurlyBrace => depth += 1,
+                TokenType::CloseCurlyBrace => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return i + 1;
+                    }
+                }
+                _ => {}
+            }
+        }
+        s.len()
+    }
+
+    /// Returns the text of the `///` comments at the end of `tokens`, which precede a function.
+    /// A blank line between a comment and the function detaches it.
+    fn collect_doc(tokens: &[Token]) -> Option<String> {
+        let mut lines = vec![];
+        for (i, t) in tokens.iter().enumerate() {
+            match t.token_type {
+                TokenType::DocComment => {
+                    let text = &t.value["///".len()..];
+                    lines.push(text.strip_prefix(' ').unwrap_or(text));
+                }
+                _ if i > 0 && tokens[i-1].token_type == TokenType::NewLine => lines.clear(),
+                _ => {}
+            }
+        }
+        if lines.is_empty() { None } else { Some(lines.join("\n")) }
+    }
+
+    pub fn generate_function(s: &[Token], var_lst: &mut VarLst, fn_lst: &mut FnLst) -> Result<(Function, usize)> {
+        var_lst.push_scope();
+
+        let leading_newlines = s.iter()
+            .take_while(|t| [TokenType::NewLine, TokenType::DocComment].contains(&t.token_type))
+            .count();
+        let doc = Self::collect_doc(&s[..leading_newlines]);
+        let s = &s[leading_newlines..];
+        if s.first().is_none_or(|t| t.token_type == TokenType::Eof) {
+            return Err(anyhow!("expected a function definition"));
+        }
+        // The header is indexed freely below, so make sure it's all there
+        let header_len = s.iter()
+            .position(|t| [TokenType::OpenCurlyBrace, TokenType::Eof].contains(&t.token_type))
+            .unwrap_or(s.len());
+        Self::check_not_eof(s, header_len)?;
+
+        let is_public = s[0].keyword() == Some(Keyword::Pub);
+        let pub_span = s[0].span;
+        let s = if is_public { &s[1..] } else { s };
+
+        if is_public && s[0].keyword() != Some(Keyword::Fn) {
+            return Err(error_at(pub_span, "`pub` may only appear before `fn`"));
+        }
+        if s[0].keyword() != Some(Keyword::Fn) {
+            return Err(error_at(s[0].span, format!("expected `fn`, found `{}`", s[0].value.escape_debug())));
+        }
+
+        Self::expect_token(s, 1, TokenType::Object, "a function name")?;
+        let mut func = Function{
+            name: s[1].value.to_string(),
+            is_public,
+            doc,
+            parameters: vec![],
+            defaults: vec![],
+            return_type: DataType::Unit,
+            body: CodeBlock{statements: vec![]},
+            locals: vec![],
+            span: s[1].span,
+            module: 0,
+        };
+
+        Self::expect_token(s, 2, TokenType::OpenParen, "`(` after the function name")?;
+
+        let mut i = 3;
+        while s[i].token_type != TokenType::CloseParen {
+            if s[i].token_type == TokenType::Comma {
+                i += 1;
+                continue;
+            }
+
+            Self::expect_token(s, i, TokenType::DataType, "a parameter type")?;
+            let var_type = DataType::new(s[i].value);
+
+            Self::expect_token(s, i + 1, TokenType::Object, "a parameter name")?;
+            let var_name = s[i+1].value;
+
+            // Parsed before the parameter is in scope, so a default can't refer to it
+            let default = if s[i+2].value == "=" {
+                let (default, l) = Self::parse_default(&s[(i+3)..], var_name, &var_type, var_lst, fn_lst)?;
+                i += 1 + l;
+                Some(default)
+            }
+            else if func.defaults.iter().any(|d| d.is_some()) {
+                return Err(anyhow!("required parameter `{}` cannot follow a parameter with a default value", var_name));
+            }
+            else {
+                None
+            };
+
+            var_lst.insert_parameter(var_name.to_string(), var_type.clone());
+            func.parameters.push(Variable {
+                name: intern(var_name),
+                dtype: var_type,
+                is_parameter: true,
+            });
+            func.defaults.push(default);
+            i += 2;
+        }
+
+        if s[i+1].token_type == TokenType::DataType {
+            func.return_type = DataType::new(s[i+1].value);
+            i += 1;
+        }
+        else if s[i+1].token_type == TokenType::OpenParen {
+            let (dtype, l) = DataType::parse_tuple(&s[(i+1)..])?;
+            func.return_type = dtype;
+            i += l;
+        }
+
+        // Registered before the body is parsed so the function can call itself
+        fn_lst.insert(func.name.clone(), func.fn_signature())?;
+
+        if s[i+1].token_type != TokenType::OpenCurlyBrace {
+            return Err(error_at(func.span, format!("function `{}` is missing a body", func.name)));
+        }
+        let (body, l) = Self::generate_code_block(&s[(i+1)..], var_lst, fn_lst)?;
+        func.body = body;
+        func.locals = var_lst.take_locals();
+
+        var_lst.pop_scope();
+        Ok((func, leading_newlines + (is_public as usize) + i + l + 2))
+    }
+
+    /// Fails unless `s[i]` is a token of type `token_type`, described in the error as `expected`
+    fn expect_token(s: &[Token], i: usize, token_type: TokenType, expected: &str) -> Result<()> {
+        Self::check_not_eof(s, i)?;
+        if s[i].token_type != token_type {
+            return Err(error_at(s[i].span, format!("expected {}, found `{}`", expected, s[i].value.escape_debug())));
+        }
+        Ok(())
+    }
+
+    /// Fails with "unexpected end of input" if the token stream ends at `s[i]`
+    fn check_not_eof(s: &[Token], i: usize) -> Result<()> {
+        match s.get(i) {
+            Some(t) if t.token_type != TokenType::Eof => Ok(()),
+            Some(t) => Err(error_at(t.span, "unexpected end of input")),
+            None => Err(anyhow!("unexpected end of input")),
+        }
+    }
+
+    /// Parses the default value of parameter `name`, which must be a constant expression of its type
+    fn parse_default(s: &[Token], name: &str, dtype: &DataType, var_lst: &VarLst, fn_lst: &FnLst) -> Result<(Box<dyn Value>, usize)> {
+        let length = Operation::expression_length(s);
+        if let Some(t) = s[..length].iter().find(|t| t.token_type == TokenType::Object) {
+            return Err(error_at(t.span, format!(
+                "default value for parameter `{}` must be a constant expression, found `{}`", name, t.value
+            )));
+        }
+
+        let (default, l) = Self::generate_expression(s, var_lst, fn_lst, Some(dtype))?;
+        if !dtype.is_assignable_from(&default.dtype()) {
+            return Err(anyhow!(
+                "default value `{}` for parameter `{}` has type `{}`, expected `{}`", default.value(), name, default.dtype(), dtype
+            ));
+        }
+        Ok((default, l))
+    }
+
+    fn generate_code_block(s: &[Token], var_lst: &mut VarLst, fn_lst: &mut FnLst) -> Result<(CodeBlock, usize)> {
+        let (block, l) = Self::generate_block(s, var_lst, fn_lst, false)?;
+        Ok((block.block, l))
+    }
+
+    /// Prints the variables of each scope, outermost first, as of the statement starting at `token`
+    fn dump_scopes(token: &Token, var_lst: &VarLst) {
+        let scopes = var_lst.scope_names().iter()
+            .map(|names| format!("{{{}}}", names.join(", ")))
+            .collect::<Vec<String>>();
+        eprintln!(
+            "scopes at {}:{} `{}`: depth {}: {}",
+            token.span.line, token.span.col, token.value.escape_debug(), scopes.len(), scopes.join(" ")
+        );
+    }
+
+    /// Parses the block starting at `s[0]`, returning it with the index of its closing brace.
+    /// With `yields_value`, an expression at the end of the block is kept as its value.
+    fn generate_block(s: &[Token], var_lst: &mut VarLst, fn_lst: &mut FnLst, yields_value: bool) -> Result<(BlockExpression, usize)> {
+        Self::expect_token(s, 0, TokenType::OpenCurlyBrace, "`{`")?;
+        var_lst.push_scope();
+
+        let mut block = CodeBlock {statements: vec![]};
+        let mut value = None;
+
+        let mut i = 1;
+        loop {
+            Self::check_not_eof(s, i)?;
+            let skipped = [TokenType::NewLine, TokenType::DocComment, TokenType::SemiColon];
+            if var_lst.options.dump_scopes && !skipped.contains(&s[i].token_type) {
+                Self::dump_scopes(&s[i], var_lst);
+            }
+            if yields_value && Self::is_trailing_expression(&s[i..]) {
+                let (val, l) = Self::generate_expression(&s[i..], var_lst, fn_lst, None)?;
+                value = Some(val);
+                i += l;
+                continue;
+            }
+
+            match s[i].token_type {
+                TokenType::NewLine | TokenType::DocComment | TokenType::SemiColon => {
+                    i += 1;
+                    continue;
+                }
+                TokenType::CloseCurlyBrace => break,
+                _ => {}
+            }
+
+            if !var_lst.is_recovering() {
+                i += Self::parse_statement(&s[i..], var_lst, fn_lst, &mut block.statements)?;
+                continue;
+            }
+
+            // A statement that fails to parse is skipped up to the next statement, leaving out
+            // anything it added before failing
+            let (depth, len) = (var_lst.scope_depth(), block.statements.len());
+            let res = catch_parse(|| Self::parse_statement(&s[i..], var_lst, fn_lst, &mut block.statements))
+                .unwrap_or_else(|e| Err(e.into()));
+            match res {
+                Ok(l) => i += l,
+                Err(e) => {
+                    // Past the error limit, parsing stops at the function level, which sees
+                    // that this error was recorded
+                    if !var_lst.record_error(diagnostic_from(e, s[i].span)) {
+                        return Err(anyhow!("too many errors"));
+                    }
+                    var_lst.truncate_scopes(depth);
+                    block.statements.truncate(len);
+                    // A declaration that fails still declares its name, so that using it doesn't
+                    // cause more errors, e.g. a missing `return` after `return x` fails too
+                    if let Some((name, dtype, mutable)) = Self::declared_name(&s[i..]) {
+                        match mutable {
+                            true => var_lst.insert(name.to_string(), dtype),
+                            false => var_lst.insert_const(name.to_string(), dtype),
+                        }
+                    }
+                    i += Self::statement_len(&s[i..]);
+                }
+            }
+        }
+
+        var_lst.pop_scope();
+        let dtype = value.as_ref().map_or(DataType::Unit, |v| v.dtype());
+        Ok((BlockExpression { block, value, dtype }, i))
+    }
+
+    /// Returns the name, type and mutability of the variable declared by the statement starting at
+    /// `s[0]`, if it's a declaration such as `int x = ...` or `const int x = ...`
+    fn declared_name<'a>(s: &[Token<'a>]) -> Option<(&'a str, DataType, bool)> {
+        let (s, mutable) = match s.first()?.keyword() {
+            Some(Keyword::Const) => (&s[1..], false),
+            _ => (s, true),
+        };
+        match (s.first()?, s.get(1)?) {
+            (dt, name) if dt.token_type == TokenType::DataType && name.token_type == TokenType::Object => {
+                Some((name.value, DataType::new(dt.value), mutable))
+            }
+            _ => None,
+        }
+    }
+
+    /// Parses the statement starting at `s[0]` into `statements`, returning the number of tokens
+    /// it spans. Most statements are a single node, but a chained assignment is several.
+    fn parse_statement(s: &[Token], var_lst: &mut VarLst, fn_lst: &mut FnLst, statements: &mut Vec<AstNode>) -> Result<usize> {
+        let mut i = 0;
+        match s[i].token_type {
+            TokenType::DataType => {
+                let (assignment, l) = Self::parse_declaration(&s[i..], var_lst, fn_lst, true)?;
+                statements.push(AstNode::AssignmentStatement(assignment));
+                i += l;
+            }
+            TokenType::Keyword => {
+                let keyword = s[i].keyword();
+                if keyword == Some(Keyword::Pub) {
+                    return Err(error_at(s[i].span, "`pub` may only appear before `fn`"));
+                }
+                else if keyword == Some(Keyword::Const) {
+                    Self::expect_token(s, i + 1, TokenType::DataType, "a type after `const`")?;
+                    let (assignment, l) = Self::parse_declaration(&s[(i+1)..], var_lst, fn_lst, false)?;
+                    statements.push(AstNode::AssignmentStatement(assignment));
+                    i += 1 + l;
+                }
+                else if keyword == Some(Keyword::While) {
+                    let (loop_obj, l) = Self::parse_loop(&s[i..], var_lst, fn_lst)?;
+                    statements.push(AstNode::Loop(loop_obj));
+                    i += l;
+                }
+                else if keyword == Some(Keyword::If) {
+                    let (if_obj, l) = Self::parse_if(&s[i..], var_lst, fn_lst)?;
+                    statements.push(AstNode::If(if_obj));
+                    i += l;
+                }
+                else if keyword == Some(Keyword::Return) {
+                    let end_tokens = [TokenType::NewLine, TokenType::SemiColon, TokenType::CloseCurlyBrace, TokenType::Eof];
+                    let mut ret = ReturnStatement { value: None };
+                    i += 1;
+                    if !end_tokens.contains(&s[i].token_type) {
+                        let (val, l) = Self::generate_expression(&s[i..], var_lst, fn_lst, None)?;
+                        ret.value = Some(val);
+                        i += l;
+                    }
+                    statements.push(AstNode::ReturnStatement(ret));
+                }
+                else if keyword == Some(Keyword::Break) || keyword == Some(Keyword::Continue) {
+                    let end_tokens = [TokenType::NewLine, TokenType::SemiColon, TokenType::CloseCurlyBrace, TokenType::Eof];
+                    if s.get(i+1).is_some_and(|t| !end_tokens.contains(&t.token_type)) {
+                        return Err(anyhow!("expected the end of the statement after `{}`", s[i].value));
+                    }
+                    let node = if keyword == Some(Keyword::Break) { AstNode::Break } else { AstNode::Continue };
+                    statements.push(node);
+                    i += 1;
+                }
+                else {
+                    return Err(error_at(s[i].span, format!("unexpected keyword `{}`", s[i].value)));
+                }
+            }
+            TokenType::Object if !Self::is_assignment(&s[i..]) => {
+                let (val, l) = Self::generate_expression(&s[i..], var_lst, fn_lst, None)?;
+                statements.push(AstNode::ExpressionStatement(val));
+                i += l;
+            }
+            TokenType::Object => {
+                if let Some(dt) = var_lst.get(s[i].value) {
+                    if var_lst.is_mutable(s[i].value) == Some(false) {
+                        return Err(error_at(s[i].span, format!("cannot assign to constant `{}`", s[i].value)));
+                    }
+
+                    if s[i+1].token_type == TokenType::OpenSquareBracket {
+                        let (assignment, l) = Self::parse_index_assignment(&s[i..], var_lst, fn_lst)?;
+                        statements.push(AstNode::IndexAssignment(assignment));
+                        i += l;
+                        return Ok(i);
+                    }
+
+                    Self::expect_token(s, i + 1, TokenType::AssignmentOperator, &format!("an assignment to `{}`", s[i].value))?;
+                    let compound_op = match s[i+1].value {
+                        "=" | ":=" => None,
+                        op => match OperationType::from_compound_assignment(op) {
+                            Some(op) => Some(op),
+                            None => return Err(anyhow!("faulty assignment operator: `{}`", op)),
+                        },
+                    };
+
+                    let dst = Variable {
+                        name: intern(s[i].value),
+                        dtype: dt,
+                        is_parameter: var_lst.is_parameter(s[i].value) == Some(true),
+                    };
+
+                    // In `a = b = 5`, the targets after `a` are assigned first, right to left
+                    let mut chain = vec![(dst.clone(), s[i].span)];
+                    let mut j = i + 2;
+                    while s[j].token_type == TokenType::Object && s.get(j + 1).is_some_and(|t| t.token_type == TokenType::AssignmentOperator) {
+                        if compound_op.is_some() || s[j+1].value != "=" {
+                            return Err(error_at(s[j+1].span, "only `=` assignments can be chained"));
+                        }
+                        chain.push((Self::assignment_target(&s[j], var_lst)?, s[j].span));
+                        j += 2;
+                    }
+                    if chain.len() > 1 {
+                        let expected = chain.last().map(|(target, _)| target.dtype.clone());
+                        let (mut src, l) = Self::generate_assigned_value(&s[j..], var_lst, fn_lst, expected.as_ref())?;
+                        for (target, span) in chain.iter().rev() {
+                            check_assignment(src.as_ref(), target, *span)?;
+                            statements.push(AstNode::AssignmentStatement(AssignmentStatement {
+                                dst: target.clone(),
+                                src,
+                                is_declaration: false,
+                            }));
+                            src = Box::new(target.clone());
+                        }
+                        i = j + l;
+                        return Ok(i);
+                    }
+
+                    let (mut op, l) = Self::generate_assigned_value(&s[(i+2)..], var_lst, fn_lst, Some(&dst.dtype))?;
+
+                    // `x op= y` is desugared into `x = x op y`
+                    if let Some(compound_op) = compound_op {
+                        let mut operation = Operation {
+                            opd_1: Box::new(dst.clone()),
+                            opd_2: op,
+                            op: compound_op,
+                            ret_type: DataType::Bool,
+                        };
+                        operation.gen_return_t()?;
+                        op = Box::new(operation);
+                    }
+                    check_assignment(op.as_ref(), &dst, s[i].span)?;
+
+                    let mut assignment = AssignmentStatement {
+                        dst,
+                        src: op,
+                        is_declaration: false,
+                    };
+
+                    statements.push(AstNode::AssignmentStatement(assignment));
+                    i += 2 + l;
+                }
+                else {
+                    return Err(error_at(s[i].span, format!("undefined variable `{}`", s[i].value)));
+                }
+            }
+            TokenType::IntegerLiteral | TokenType::FloatLiteral | TokenType::StringLiteral | TokenType::ByteStringLiteral | TokenType::CharLiteral |
+            TokenType::BooleanLiteral | TokenType::OpenParen | TokenType::OpenSquareBracket | TokenType::ArithmeticOperator |
+            TokenType::UnaryOperator => {
+                let (val, l) = Self::generate_expression(&s[i..], var_lst, fn_lst, None)?;
+                statements.push(AstNode::ExpressionStatement(val));
+                i += l;
+            }
+            _ => {
+                Self::check_not_eof(s, i)?;
+                return Err(error_at(s[i].span, format!("unexpected `{}`", s[i].value.escape_debug())));
+            }
+        }
+        Ok(i)
+    }
+
+    /// Returns the number of tokens before the end of the statement starting at `s[0]`, which is
+    /// the first `NewLine` or `SemiColon` outside of brackets, or the `}` closing the block
+    fn statement_len(s: &[Token]) -> usize {
+        let mut depth = 0;
+        for (i, t) in s.iter().enumerate() {
+            match t.token_type {
+                TokenType::OpenParen | TokenType::OpenSquareBracket | TokenType::OpenCurlyBrace => depth += 1,
+                TokenType::CloseParen | TokenType::CloseSquareBracket => depth -= 1,
+                TokenType::CloseCurlyBrace if depth == 0 => return i,
+                TokenType::CloseCurlyBrace => depth -= 1,
+                TokenType::NewLine | TokenType::SemiColon | TokenType::Eof if depth == 0 => return i,
+                _ => {}
+            }
+        }
+        s.len()
+    }
+
+    /// Returns the variable named by `token` if it may be assigned to, as in a chained assignment
+    fn assignment_target(token: &Token, var_lst: &VarLst) -> Result<Variable> {
+        let dtype = var_lst.get(token.value)
+            .ok_or_else(|| error_at(token.span, format!("undefined variable `{}`", token.value)))?;
+        if var_lst.is_mutable(token.value) == Some(false) {
+            return Err(error_at(token.span, format!("cannot assign to constant `{}`", token.value)));
+        }
+        Ok(Variable {
+            name: intern(token.value),
+            dtype,
+            is_parameter: var_lst.is_parameter(token.value) == Some(true),
+        })
+    }
+
+    /// Returns true if the statement starting at `s[0]` assigns to something, as in `x = 1` or
+    /// `xs[0] += 2`, rather than being an expression
+    fn is_assignment(s: &[Token]) -> bool {
+        let length = Operation::expression_length(s);
+        s[..length].iter().any(|t| t.token_type == TokenType::AssignmentOperator)
+    }
+
+    /// Returns true if `s` starts with an expression that's the last thing in its block, such as
+    /// `a + 2` in `{ int a = 1; a + 2 }`
+    fn is_trailing_expression(s: &[Token]) -> bool {
+        let starts_statement = [
+            TokenType::NewLine, TokenType::DocComment, TokenType::SemiColon, TokenType::CloseCurlyBrace,
+            TokenType::DataType, TokenType::Keyword,
+        ];
+        if starts_statement.contains(&s[0].token_type) {
+            return false;
+        }
+
+        let length = Operation::expression_length(s);
+        let next = s[length..].iter().find(|t| t.token_type != TokenType::NewLine);
+        !Self::is_assignment(s) && next.is_some_and(|t| t.token_type == TokenType::CloseCurlyBrace)
+    }
+
+    /// Parses the value assigned in a declaration or assignment, which is either an expression, a
+    /// block expression such as `{ int a = 1; a + 2 }` or an if-expression
+    fn generate_assigned_value(s: &[Token], var_lst: &mut VarLst, fn_lst: &mut FnLst, expected: Option<&DataType>) -> Result<(Box<dyn Value>, usize)> {
+        if s[0].keyword() == Some(Keyword::If) {
+            return Self::generate_if_expression(s, var_lst, fn_lst);
+        }
+        if s[0].token_type != TokenType::OpenCurlyBrace {
+            return Self::generate_expression(s, var_lst, fn_lst, expected);
+        }
+
+        let (block_expr, close) = Self::generate_value_block(s, var_lst, fn_lst)?;
+        Ok((Box::new(block_expr), close + 1))
+    }
+
+    /// Parses a block expression starting at `s[0]`, returning it with the index of its closing
+    /// brace
+    fn generate_value_block(s: &[Token], var_lst: &mut VarLst, fn_lst: &mut FnLst) -> Result<(BlockExpression, usize)> {
+        let (block_expr, close) = Self::generate_block(s, var_lst, fn_lst, true)?;
+        check_loop_control(&block_expr.block, false)?;
+        if contains_return(&block_expr.block) {
+            return Err(error_at(s[0].span, "`return` can't be used in a block expression"));
+        }
+        Ok((block_expr, close))
+    }
+
+    /// Parses an if-expression such as `if a { 1 } else if b { 2 } else { 3 }` into nested
+    /// `Conditional`s whose branches are block expressions. Without a final `else` the expression
+    /// would have type `Unit`, which can't be assigned, so that's an error.
+    fn generate_if_expression(s: &[Token], var_lst: &mut VarLst, fn_lst: &mut FnLst) -> Result<(Box<dyn Value>, usize)> {
+        // As in `parse_if`, the arms are linked up from the last one so a long chain doesn't recurse
+        let mut arms = vec![];
+        let mut idx = 0;
+        let else_value = loop {
+            let (condition, i) = Operation::extract_operation(&s[idx+1..], var_lst, fn_lst, Some(&DataType::Bool))?;
+            idx += i + 1;
+            Self::check_not_eof(s, idx)?;
+            if s[idx].token_type != TokenType::OpenCurlyBrace {
+                return Err(error_at(s[idx].span, "expected `{` after the condition of `if`"));
+            }
+
+            let (then_value, close) = Self::generate_value_block(&s[idx..], var_lst, fn_lst)?;
+            idx += close + 1;
+            arms.push((condition, then_value));
+
+            if idx >= s.len() || s[idx].keyword() != Some(Keyword::Else) {
+                let span = s[0].span;
+                return Err(error_at(span, "an `if` used as a value needs an `else`, as its type is `()` without one"));
+            }
+            idx += 1;
+            Self::check_not_eof(s, idx)?;
+            if s[idx].keyword() == Some(Keyword::If) {
+                continue;
+            }
+            if s[idx].token_type != TokenType::OpenCurlyBrace {
+                return Err(error_at(s[idx].span, "expected `{` or `if` after `else`"));
+            }
+
+            let (else_value, close) = Self::generate_value_block(&s[idx..], var_lst, fn_lst)?;
+            idx += close + 1;
+            break else_value;
+        };
+
+        let mut value: Box<dyn Value> = Box::new(else_value);
+        while let Some((condition, then_value)) = arms.pop() {
+            value = Box::new(Conditional::new(condition, Box::new(then_value), value)?);
+        }
+        Ok((value, idx))
+    }
+
+    /// Parses a `<type> <name> = <expr>` declaration, registering the variable in `var_lst`
+    fn parse_declaration(s: &[Token], var_lst: &mut VarLst, fn_lst: &mut FnLst, mutable: bool) -> Result<(AssignmentStatement, usize)> {
+        let (name, span) = (s[1].value, s[1].span);
+        match s[1].token_type {
+            TokenType::Object => {}
+            TokenType::DataType => {
+                return Err(error_at(span, format!("expected variable name, found type `{}`", name)));
+            }
+            TokenType::Keyword => {
+                return Err(error_at(span, format!("expected variable name, found keyword `{}`", name)));
+            }
+            _ => return Err(error_at(span, format!("expected variable name, found `{}`", name.escape_debug()))),
+        }
+        if s[2].token_type != TokenType::AssignmentOperator {
+            return Err(error_at(span, format!("expected `=` after `{}` in its declaration", name)));
+        }
+        let chained = s.get(3).is_some_and(|t| t.token_type == TokenType::Object) &&
+            s.get(4).is_some_and(|t| t.token_type == TokenType::AssignmentOperator);
+        if chained {
+            return Err(error_at(span, format!(
+                "a declaration can't be part of a chained assignment; declare `{}` first, then assign `{} = {} = ...`",
+                name, name, s[3].value
+            )));
+        }
+
+        let var_type = DataType::new(s[0].value);
+        let var_name = s[1].value;
+        let var = Variable {
+            name: intern(var_name),
+            dtype: var_type.clone(),
+            is_parameter: false,
+        };
+
+        // The variable is only in scope after its initializer, so `int x = x` can only refer to
+        // an `x` from an enclosing scope
+        let init_tokens = &s[3..(3 + Operation::expression_length(&s[3..]))];
+        let self_reference = init_tokens.iter().enumerate().any(|(i, t)| {
+            t.token_type == TokenType::Object && t.value == var_name &&
+                (i == 0 || init_tokens[i-1].token_type != TokenType::Period) &&
+                init_tokens.get(i+1).is_none_or(|next| next.token_type != TokenType::OpenParen)
+        });
+        if self_reference && var_lst.get(var_name).is_none() {
+            return Err(error_at(s[1].span, format!("variable `{}` used in its own initializer", var_name)));
+        }
+
+        let (val, num_tokens) = Self::generate_assigned_value(&s[3..], var_lst, fn_lst, Some(&var.dtype))?;
+        let integer_types = [DataType::I64, DataType::U64, DataType::U8];
+        if num_tokens == 1 && s[3].token_type == TokenType::FloatLiteral && integer_types.contains(&var.dtype) {
+            return Err(float_initializer_error(&s[3], val.as_ref(), &var.dtype));
+        }
+        check_assignment(val.as_ref(), &var, s[1].span)?;
+
+        var_lst.record_local(var_name, var_type.clone(), s[1].span);
+        if mutable {
+            var_lst.insert(var_name.to_string(), var_type);
+        }
+        else {
+            var_lst.insert_const(var_name.to_string(), var_type);
+        }
+
+        let assignment = AssignmentStatement {
+            dst: var,
+            src: val,
+            is_declaration: true,
+        };
+        Ok((assignment, 3 + num_tokens))
+    }
+
+    /// Parses `<name>[<index>] = <expr>`, checking that `name` is a vector or array and that the
+    /// value matches its element type
+    fn parse_index_assignment(s: &[Token], var_lst: &VarLst, fn_lst: &FnLst) -> Result<(IndexAssignment, usize)> {
+        let dst = Variable {
+            name: intern(s[0].value),
+            dtype: var_lst.get(s[0].value).ok_or_else(|| anyhow!("undefined variable `{}`", s[0].value))?,
+            is_parameter: var_lst.is_parameter(s[0].value) == Some(true),
+        };
+        let inner = match dst.dtype.element_type() {
+            Some(inner) => inner.clone(),
+            None => return Err(anyhow!("cannot index into `{}` of type `{}`", dst.name, dst.dtype)),
+        };
+
+        let close = 1 + Operation::matching_bracket(&s[1..])?;
+        let index = Operation::extract_operation_h(&s[2..close], var_lst, fn_lst, None)?;
+        if ![DataType::I64, DataType::U64, DataType::U8].contains(&index.dtype()) {
+            return Err(anyhow!("index `{}` must be an integer, found `{}`", index.value(), index.dtype()));
+        }
+        check_constant_index(&dst.name, &dst.dtype, index.as_ref())?;
+
+        match s.get(close + 1) {
+            Some(t) if t.value == "=" => {}
+            Some(t) if t.token_type == TokenType::OpenSquareBracket => {
+                return Err(anyhow!("only a single index may be assigned to, in `{}[{}]`", dst.name, index.value()));
+            }
+            Some(t) => return Err(anyhow!("expected `=` after `{}[{}]`, found `{}`", dst.name, index.value(), t.value)),
+            None => return Err(anyhow!("expected `=` after `{}[{}]`", dst.name, index.value())),
+        }
+
+        let (src, l) = Self::generate_expression(&s[(close + 2)..], var_lst, fn_lst, Some(&inner))?;
+        if !inner.is_assignable_from(&src.dtype()) {
+            return Err(anyhow!(
+                "cannot assign `{}` of type `{}` to an element of `{}` of type `{}`", src.value(), src.dtype(), dst.name, dst.dtype
+            ));
+        }
+
+        Ok((IndexAssignment { dst, index, src }, close + 2 + l))
+    }
+
+    fn parse_loop(s: &[Token], var_lst: &mut VarLst, fn_lst: &mut FnLst) -> Result<(Loop, usize)> {
+        assert_eq!(s[0].keyword(), Some(Keyword::While));
+
+        var_lst.push_scope();
+
+        let (condition, i) = Operation::extract_operation(&s[1..], var_lst, fn_lst, Some(&DataType::Bool))?;
+
+        let mut idx = i + 1;
+
+        Self::expect_token(s, idx, TokenType::OpenCurlyBrace, "`{` after the condition of `while`")?;
+
+        let (code_block, i) = Self::generate_code_block(&s[idx..], var_lst, fn_lst)?;
+        idx += i + 1;
+
+        let loop_var = Loop {
+            condition,
+            body: code_block,
+        };
+
+        var_lst.pop_scope();
+        Ok((loop_var, idx))        
+    }
+
+    /// Parses an `if` along with any `else if` arms and `else` block that follow it. The arms are
+    /// parsed in a loop and linked up from the last one, so a long chain doesn't recurse.
+    fn parse_if(s: &[Token], var_lst: &mut VarLst, fn_lst: &mut FnLst) -> Result<(If, usize)> {
+        let mut arms = vec![];
+        let mut idx = 0;
+        let else_branch = loop {
+            assert_eq!(s[idx].keyword(), Some(Keyword::If));
+
+            let (condition, i) = Operation::extract_operation(&s[idx+1..], var_lst, fn_lst, Some(&DataType::Bool))?;
+            idx += i + 1;
+
+            Self::expect_token(s, idx, TokenType::OpenCurlyBrace, "`{` after the condition of `if`")?;
+
+            let (body, i) = Self::generate_code_block(&s[idx..], var_lst, fn_lst)?;
+            idx += i + 1;
+            arms.push((condition, body));
+
+            if idx >= s.len() || s[idx].keyword() != Some(Keyword::Else) {
+                break None;
+            }
+            idx += 1;
+            if s[idx].keyword() == Some(Keyword::If) {
+                continue;
+            }
+            Self::expect_token(s, idx, TokenType::OpenCurlyBrace, "`{` after `else`")?;
+
+            let (else_body, i) = Self::generate_code_block(&s[idx..], var_lst, fn_lst)?;
+            idx += i + 1;
+            break Some(ElseBranch::Block(else_body));
+        };
+
+        let (condition, body) = arms.pop().unwrap();
+        let mut if_obj = If { condition, body, else_branch };
+        while let Some((condition, body)) = arms.pop() {
+            if_obj = If { condition, body, else_branch: Some(ElseBranch::If(Box::new(if_obj))) };
+        }
+
+        Ok((if_obj, idx))
+    }
+
+    fn generate_expression(s: &[Token], var_lst: &VarLst, fn_lst: &FnLst, expected: Option<&DataType>) -> Result<(Box<dyn Value>, usize)> {
+        Operation::extract_operation(s, var_lst, fn_lst, expected)
+    }
+
+    /// Parses a primary value followed by any number of `[index]` and `as type` postfix operators.
+    /// The tokens passed to it must contain no operators outside of brackets.
+    fn generate_postfix(s: &[Token], var_lst: &VarLst, fn_lst: &FnLst, expected: Option<&DataType>) -> Result<Box<dyn Value>> {
+        let (mut val, mut i): (Box<dyn Value>, usize) = match s[0].token_type {
+            TokenType::OpenParen => {
+                let close = Operation::matching_bracket(s)?;
+                let elements = Call::split_arguments(&s[1..close])?;
+                if elements.len() > 1 {
+                    let expected_elements = match expected {
+                        Some(DataType::Tuple(dts)) if dts.len() == elements.len() => dts.iter().map(Some).collect(),
+                        _ => vec![None; elements.len()],
+                    };
+                    let elements = elements.iter().zip(expected_elements)
+                        .map(|(e, expected)| Operation::extract_operation_h(e, var_lst, fn_lst, expected))
+                        .collect::<Result<Vec<Box<dyn Value>>>>()?;
+                    (Box::new(TupleLiteral::new(elements)), close + 1)
+                }
+                else {
+                    (Operation::extract_operation_h(&s[1..close], var_lst, fn_lst, expected)?, close + 1)
+                }
+            }
+            TokenType::Object if s.get(1).is_some_and(|t| t.token_type == TokenType::OpenParen) => {
+                let close = 1 + Operation::matching_bracket(&s[1..])?;
+                (Box::new(Call::parse(s[0].value, &s[2..close], var_lst, fn_lst)?), close + 1)
+            }
+            // A call qualified by a module alias, as in `m.sqrt(x)`
+            TokenType::Object if s.len() > 3 && s[1].token_type == TokenType::Period &&
+                s[2].token_type == TokenType::Object && s[3].token_type == TokenType::OpenParen =>
+            {
+                let name = format!("{}.{}", s[0].value, s[2].value);
+                let close = 3 + Operation::matching_bracket(&s[3..])?;
+                (Box::new(Call::parse(&name, &s[4..close], var_lst, fn_lst)?), close + 1)
+            }
+            TokenType::OpenSquareBracket => {
+                let close = Operation::matching_bracket(s)?;
+                (Box::new(ArrayLiteral::parse(&s[1..close], var_lst, fn_lst, expected)?), close + 1)
+            }
+            _ => (Self::generate_value(&s[0], var_lst, expected)?, 1),
+        };
+
+        while i < s.len() {
+            match s[i].token_type {
+                TokenType::OpenSquareBracket => {
+                    let close = i + Operation::matching_bracket(&s[i..])?;
+                    let index = Operation::extract_operation_h(&s[(i+1)..close], var_lst, fn_lst, None)?;
+                    val = Box::new(Index::new(val, index)?);
+                    i = close + 1;
+                }
+                TokenType::Period => {
+                    if i + 1 >= s.len() || s[i+1].token_type != TokenType::Object {
+                        return Err(anyhow!("expected a field name after `.`"));
+                    }
+                    // There are no struct types yet, so no value has fields to access
+                    return Err(anyhow!("type `{}` has no field `{}`", val.dtype(), s[i+1].value));
+                }
+                TokenType::Keyword if s[i].keyword() == Some(Keyword::As) => {
+                    Self::expect_token(s, i + 1, TokenType::DataType, "a type after `as`")?;
+                    val = Box::new(Cast::new(val, DataType::new(s[i+1].value))?);
+                    i += 2;
+                }
+                _ => return Err(anyhow!("unexpected token `{}` in expression", s[i].value)),
+            }
+        }
+
+        Ok(val)
+    }
+
+    /// Integer literals take on the expected integer type if there is one, and the default integer
+    /// type from the parser's options otherwise
+    fn int_literal_type(expected: Option<&DataType>, var_lst: &VarLst) -> DataType {
+        match expected {
+            Some(dt) if [DataType::I64, DataType::U64, DataType::U8].contains(dt) => dt.clone(),
+            _ => var_lst.options.default_int.clone(),
+        }
+    }
+
+    /// Parses a single literal or variable token
+    fn generate_value(token: &Token, var_lst: &VarLst, expected: Option<&DataType>) -> Result<Box<dyn Value>> {
+        match token.token_type {
+            TokenType::FloatLiteral => {
+                let res = Literal::parse_typed(token.value, var_lst.options.default_float.clone())?;
+                Ok(Box::new(res))
+            }
+            TokenType::IntegerLiteral => {
+                let res = Literal::parse_typed(token.value, Self::int_literal_type(expected, var_lst))
+                    .map_err(|e| error_at(token.span, e))?;
+                Ok(Box::new(res))
+            }
+            TokenType::BooleanLiteral => {
+                let res = Literal::parse_typed(token.value, DataType::Bool)?;
+                Ok(Box::new(res))
+            }
+            TokenType::StringLiteral => {
+                let res = Literal::parse_typed(token.value, DataType::Vec { inner: Box::new(DataType::U8) })?;
+                Ok(Box::new(res))
+            }
+            TokenType::ByteStringLiteral => {
+                // A byte string is a vector of its bytes, unlike a string literal, which is text
+                let elements = parse_byte_string(token.value)
+                    .map_err(|e| error_at(token.span, e))?
+                    .into_iter()
+                    .map(|b| Box::new(Literal { value: intern(&b.to_string()), dtype: DataType::U8 }) as Box<dyn Value>)
+                    .collect();
+                Ok(Box::new(ArrayLiteral { elements, dtype: DataType::Vec { inner: Box::new(DataType::U8) } }))
+            }
+            TokenType::CharLiteral => {
+                let res = Literal::parse_typed(token.value, DataType::Char)
+                    .map_err(|e| error_at(token.span, e))?;
+                Ok(Box::new(res))
+            }
+            TokenType::Object => {
+                let var_name = token.value;
+
+                let var_type = var_lst.get(var_name)
+                    .ok_or_else(|| error_at(token.span, format!("undefined variable `{}`", var_name)))?;
+
+                let res = Variable {
+                    is_parameter: var_lst.is_parameter(var_name) == Some(true),
+                    name: intern(var_name),
+                    dtype: var_type,
+                };
+                Ok(Box::new(res))
+            }
+            _ => Err(error_at(token.span, format!("unexpected token `{}` in expression", token.value.escape_debug()))),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ParseError {
+    pub message: String,
+    pub span: Span,
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // Like a `Diagnostic`, an error without a location (line 0) is just its message
+        if self.span.line == 0 {
+            return write!(f, "{}", self.message);
+        }
+        write!(f, "{} ({}:{})", self.message, self.span.line, self.span.col)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// An error at `span`, which keeps its location apart from its message for the diagnostics of
+/// `analyze_str`. As an `anyhow::Error` it displays as `message (line:col)`.
+pub(crate) fn error_at(span: Span, message: impl Display) -> anyhow::Error {
+    ParseError { message: message.to_string(), span }.into()
+}
+
+/// Turns an error from parsing into a diagnostic, located at `fallback` unless it has a location
+/// of its own
+fn diagnostic_from(e: anyhow::Error, fallback: Span) -> Diagnostic {
+    match e.downcast::<ParseError>() {
+        Ok(e) if e.span.line != 0 => Diagnostic::error(e.message, e.span),
+        Ok(e) => Diagnostic::error(e.message, fallback),
+        Err(e) => Diagnostic::error(e.to_string(), fallback),
+    }
+}
+
+thread_local! {
+    /// Whether a `catch_parse` is running on this thread, so that its panics aren't reported
+    static CATCHING_PANICS: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Runs `parse`, turning a panic into a `ParseError` with the panic's message, so callers get an
+/// error rather than an abort while parts of the lexer and parser still panic on bad input. The
+/// location of a panic isn't known, so the error's span is empty.
+///
+/// The first call wraps the panic hook in one that stays quiet for panics inside `catch_parse`,
+/// and defers to the previous hook for any other panic, on any thread.
+pub fn catch_parse<T>(parse: impl FnOnce() -> T) -> Result<T, ParseError> {
+    static QUIET_HOOK: Once = Once::new();
+    QUIET_HOOK.call_once(|| {
+        let hook = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            if !CATCHING_PANICS.get() {
+                hook(info);
+            }
+        }));
+    });
+
+    let was_catching = CATCHING_PANICS.replace(true);
+    let res = panic::catch_unwind(AssertUnwindSafe(parse));
+    CATCHING_PANICS.set(was_catching);
+
+    res.map_err(|payload| {
+        let message = payload.downcast_ref::<&str>().map(|m| m.to_string())
+            .or_else(|| payload.downcast_ref::<String>().cloned())
+            .unwrap_or_else(|| "the parser panicked".to_string());
+        ParseError { message, span: Span::default() }
+    })
+}
+
+/// Runs `lexer` to the end of its source, turning a panic on a character it can't lex into an error
+pub fn lex_to_end(lexer: &mut Lexer) -> Result<(), ParseError> {
+    catch_parse(|| while lexer.next().is_some() {})
+}
+
+/// A cheap structural scan of the whole token stream, run before parsing to report gross errors
+/// (unbalanced brackets, malformed function headers) with their locations
+pub fn prevalidate(tokens: &[Token]) -> Vec<ParseError> {
+    let mut errors = vec![];
+    let mut open_brackets: Vec<&Token> = vec![];
+
+    for (i, tok) in tokens.iter().enumerate() {
+        match tok.token_type {
+            TokenType::OpenParen | TokenType::OpenCurlyBrace | TokenType::OpenSquareBracket => open_brackets.push(tok),
+            TokenType::CloseParen | TokenType::CloseCurlyBrace | TokenType::CloseSquareBracket => {
+                let expected_open = match tok.token_type {
+                    TokenType::CloseParen => TokenType::OpenParen,
+                    TokenType::CloseCurlyBrace => TokenType::OpenCurlyBrace,
+                    _ => TokenType::OpenSquareBracket,
+                };
+                // Brackets left open inside the matching one are reported as unclosed
+                match open_brackets.iter().rposition(|t| t.token_type == expected_open) {
+                    Some(pos) => {
+                        for open in open_brackets.drain(pos..).skip(1) {
+                            errors.push(ParseError {
+                                message: format!("unclosed `{}`", open.value),
+                                span: open.span,
+                            });
+                        }
+                    }
+                    None => errors.push(ParseError {
+                        message: format!("unmatched `{}`", tok.value),
+                        span: tok.span,
+                    }),
+                }
+            }
+            TokenType::Keyword if tok.keyword() == Some(Keyword::Fn) => {
+                match tokens.get(i + 1) {
+                    Some(name) if name.token_type == TokenType::Object => {
+                        if tokens.get(i + 2).map(|t| t.token_type) != Some(TokenType::OpenParen) {
+                            errors.push(ParseError {
+                                message: format!("expected `(` after function name `{}`", name.value),
+                                span: name.span,
+                            });
+                        }
+                        else if !has_body(&tokens[(i+2)..]) {
+                            errors.push(ParseError {
+                                message: format!("function `{}` is missing a body", name.value),
+                                span: name.span,
+                            });
+                        }
+                    }
+                    _ => errors.push(ParseError {
+                        message: "expected a function name after `fn`".to_string(),
+                        span: tok.span,
+                    }),
+                }
+            }
+            TokenType::Keyword if tok.keyword() == Some(Keyword::Pub) && tokens.get(i + 1).is_none_or(|t| t.keyword() != Some(Keyword::Fn)) => {
+                errors.push(ParseError {
+                    message: "`pub` may only appear before `fn`".to_string(),
+                    span: tok.span,
+                });
+            }
+            _ => {}
+        }
+    }
+
+    for open in open_brackets {
+        errors.push(ParseError {
+            message: format!("unclosed `{}`", open.value),
+            span: open.span,
+        });
+    }
+
+    errors
+}
+
+/// Returns false if the function header whose parameter list starts at `tokens[0]` isn't followed
+/// by a `{`, after the return type if there is one. Functions can't be declared without a body.
+fn has_body(tokens: &[Token]) -> bool {
+    // An unclosed parameter list is reported separately
+    let Ok(close) = Operation::matching_bracket(tokens) else { return true };
+    let mut i = close + 1;
+    match tokens.get(i).map(|t| t.token_type) {
+        Some(TokenType::DataType) => i += 1,
+        Some(TokenType::OpenParen) => match Operation::matching_bracket(&tokens[i..]) {
+            Ok(l) => i += l + 1,
+            Err(_) => return true,
+        },
+        _ => {}
+    }
+    tokens.get(i).is_some_and(|t| t.token_type == TokenType::OpenCurlyBrace)
+}
+
+/// Clears the caches that only last for one compilation when dropped, so that a long-running
+/// process compiling many programs doesn't keep every string it has seen
+struct CompilationScope;
+
+impl Drop for CompilationScope {
+    fn drop(&mut self) {
+        LITERALS.with(|cache| cache.borrow_mut().clear());
+        clear_interned();
+    }
+}
+
+/// Verifies that `src` can be assigned to `dst` per `DataType::is_assignable_from`. `span` is
+/// where the assigned variable's name appears.
+fn check_assignment(src: &dyn Value, dst: &Variable, span: Span) -> Result<()> {
+    if !dst.dtype.is_assignable_from(&src.dtype()) {
+        return Err(error_at(span, format!(
+            "cannot assign `{}` of type `{}` to `{}` of type `{}`", src.value(), src.dtype(), dst.name, dst.dtype
+        )));
+    }
+    Ok(())
+}
+
+/// Returns the error for initializing a variable of integer type `dtype` with the float literal
+/// `token`, such as `1e3`. Since that's often meant as an integer, the error suggests the integer
+/// literal it's equal to if there is one of that type.
+fn float_initializer_error(token: &Token, val: &dyn Value, dtype: &DataType) -> anyhow::Error {
+    let suggestion = val.value().parse::<f64>().ok()
+        .filter(|f| f.fract() == 0.0)
+        .map(|f| format!("{:.0}", f))
+        .filter(|digits| Literal::parse_typed(digits, dtype.clone()).is_ok())
+        .map_or(String::new(), |digits| format!("; use `{}` or cast", digits));
+    error_at(token.span, format!(
+        "float literal `{}` cannot initialize `{}`{}", token.value, dtype, suggestion
+    ))
+}
+
+/// Settings that change how a program is parsed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParserOptions {
+    /// The type of integer literals that don't take a type from their context
+    default_int: DataType,
+    /// The type of float literals
+    default_float: DataType,
+    /// Print the variables in scope before each statement and at the end of each block to
+    /// stderr, for debugging the parser
+    dump_scopes: bool,
+    /// The number of errors after which a parser recovering from them stops, see `analyze_str`
+    pub(crate) max_errors: usize,
+}
+
+/// How deeply an expression may nest, where a literal or variable has depth 1. Deeper ones are
+/// rejected rather than overflowing the stack of the passes that walk them.
+pub const MAX_EXPRESSION_DEPTH: usize = 1000;
+
+/// The default for `ParserOptions::with_max_errors`
+pub const DEFAULT_MAX_ERRORS: usize = 100;
+
+impl ParserOptions {
+    /// Fails if `default_int` isn't an integer type or `default_float` isn't a float type
+    pub fn new(default_int: DataType, default_float: DataType) -> Result<Self> {
+        if ![DataType::I64, DataType::U64, DataType::U8].contains(&default_int) {
+            return Err(anyhow!("default integer type must be an integer type, found `{}`", default_int));
+        }
+        if default_float != DataType::F64 {
+            return Err(anyhow!("default float type must be a float type, found `{}`", default_float));
+        }
+        Ok(ParserOptions { default_int, default_float, ..Default::default() })
+    }
+
+    pub fn with_dump_scopes(mut self, dump_scopes: bool) -> Self {
+        self.dump_scopes = dump_scopes;
+        self
+    }
+
+    /// Sets how many errors are collected when recovering from them before parsing stops. At
+    /// least one error is always reported.
+    pub fn with_max_errors(mut self, max_errors: usize) -> Self {
+        self.max_errors = max_errors;
+        self
+    }
+}
+
+impl Default for ParserOptions {
+    fn default() -> Self {
+        ParserOptions {
+            default_int: DataType::I64,
+            default_float: DataType::F64,
+            dump_scopes: false,
+            max_errors: DEFAULT_MAX_ERRORS,
+        }
+    }
+}
+
+/// Lexes, validates and parses a complete program, returning its functions
+pub fn compile_str(source: &str) -> Result<Vec<Function>> {
+    compile_str_with_options(source, ParserOptions::default())
+}
+
+/// Like `compile_str`, with `options` controlling how literals are typed
+pub fn compile_str_with_options(source: &str, options: ParserOptions) -> Result<Vec<Function>> {
+    let mut lexer = Lexer::new(source);
+    lex_to_end(&mut lexer).map_err(|e| anyhow!("{}", e))?;
+    compile_lexed(&lexer, options)
+}
+
+/// Validates and parses the tokens of a lexer that has already been run to the end of its source.
+/// Use `imports::compile_lexed_with_imports` for programs that import other files.
+pub fn compile_lexed(lexer: &Lexer, options: ParserOptions) -> Result<Vec<Function>> {
+    if let Some(t) = lexer.tokens.iter().find(|t| t.keyword() == Some(Keyword::Import)) {
+        return Err(error_at(t.span, "`import` can only be resolved when compiling a file"));
+    }
+    compile_tokens(lexer, options, FnLst::new())
+}
+
+/// Validates and parses the tokens of a fully lexed program. Functions the program may call
+/// without defining them, such as imported ones, are passed in `fn_lst`.
+pub(crate) fn compile_tokens(lexer: &Lexer, options: ParserOptions, mut fn_lst: FnLst) -> Result<Vec<Function>> {
+    let _scope = CompilationScope;
+    // Warnings are left for the caller to report, see `Lexer::validate_syntax`
+    // The errors become the message of an `Err`, so their severity is left out
+    let lexer_errors = lexer.validate_syntax().into_iter()
+        .filter(|d| d.is_error())
+        .map(|d| format!("{} ({}:{})", d.message, d.span.line, d.span.col))
+        .collect::<Vec<String>>();
+    if !lexer_errors.is_empty() {
+        return Err(anyhow!("{}", lexer_errors.join("\n")));
+    }
+
+    let errors = prevalidate(&lexer.tokens);
+    if !errors.is_empty() {
+        let messages = errors.iter().map(|e| e.to_string()).collect::<Vec<String>>();
+        return Err(anyhow!("{}", messages.join("\n")));
+    }
+
+    // Parts of the parser still panic on bad input, which callers should get as an error
+    let mut var_lst = VarLst::new().with_options(options);
+    let mut funcs = catch_parse(|| AstNode::generate_program(&lexer.tokens, &mut var_lst, &mut fn_lst))
+        .map_err(|e| anyhow!("{}", e.message))??;
+
+    for func in funcs.iter_mut() {
+        finish_function(func)?;
+    }
+    Ok(funcs)
+}
+
+/// Runs the checks that need a whole function, then folds constants and simplifies its control flow
+fn finish_function(func: &mut Function) -> Result<()> {
+    check_loop_control(&func.body, false)?;
+    check_returns(func)?;
+    ConstantFolder.transform_function(func);
+    simplify_control_flow(&mut func.body);
+    Ok(())
+}
+
+/// Like `compile_str`, but reports every diagnostic it finds, errors and warnings alike, rather
+/// than stopping at the first error. Statements that fail to parse are left out of their block,
+/// and functions that fail otherwise are left out of the program. The rest is returned, unless
+/// the program couldn't be parsed at all, e.g. because its brackets don't match.
+pub fn analyze_str(source: &str) -> (Option<Vec<Function>>, Vec<Diagnostic>) {
+    analyze_str_with_options(source, ParserOptions::default())
+}
+
+/// Like `analyze_str`, with `options` controlling how literals are typed and how many errors
+/// the parser recovers from before it stops
+pub fn analyze_str_with_options(source: &str, options: ParserOptions) -> (Option<Vec<Function>>, Vec<Diagnostic>) {
+    let res = catch_parse(|| {
+        let mut lexer = Lexer::new(source);
+        while lexer.next().is_some() {}
+        if let Some(t) = lexer.tokens.iter().find(|t| t.keyword() == Some(Keyword::Import)) {
+            let error = Diagnostic::error("`import` can only be resolved when compiling a file".to_string(), t.span);
+            return (None, vec![error]);
+        }
+
+        let (funcs, mut diagnostics) = analyze_tokens(&lexer, options, FnLst::new());
+        if let Some(funcs) = &funcs {
+            diagnostics.extend(lint(funcs));
+        }
+        (funcs, diagnostics)
+    });
+    res.unwrap_or_else(|e| (None, vec![Diagnostic::error(e.message, e.span)]))
+}
+
+/// The recovering counterpart of `compile_tokens`, for `analyze_str` and
+/// `imports::analyze_lexed_with_imports`. Lint warnings are left to the caller.
+pub(crate) fn analyze_tokens(lexer: &Lexer, options: ParserOptions, mut fn_lst: FnLst) -> (Option<Vec<Function>>, Vec<Diagnostic>) {
+    let _scope = CompilationScope;
+    let mut diagnostics = lexer.validate_syntax();
+    diagnostics.extend(prevalidate(&lexer.tokens).into_iter().map(|e| Diagnostic::error(e.message, e.span)));
+    if diagnostics.iter().any(|d| d.is_error()) {
+        return (None, diagnostics);
+    }
+
+    let mut var_lst = VarLst::new().with_options(options).with_recovery();
+    let funcs = AstNode::generate_program(&lexer.tokens, &mut var_lst, &mut fn_lst);
+    diagnostics.extend(var_lst.take_errors());
+    let mut funcs = match funcs {
+        Ok(funcs) => funcs,
+        Err(e) => {
+            diagnostics.push(diagnostic_from(e, Span::default()));
+            return (None, diagnostics);
+        }
+    };
+
+    // A function missing statements that failed to parse may fail the checks of the whole function
+    // because of it, e.g. when a `return` was skipped, so those checks are only reported for
+    // functions that parsed cleanly
+    let error_starts = diagnostics.iter().filter(|d| d.is_error()).map(|d| d.span.start).collect::<Vec<usize>>();
+    let has_recovered_errors = |func: &Function| {
+        let Some(name) = lexer.tokens.iter().position(|t| t.span == func.span) else { return false };
+        let end = lexer.tokens[name + AstNode::function_len(&lexer.tokens[name..]) - 1].span.end;
+        error_starts.iter().any(|start| (func.span.start..end).contains(start))
+    };
+    funcs.retain_mut(|func| match finish_function(func) {
+        Ok(()) => true,
+        Err(_) if has_recovered_errors(func) => false,
+        Err(e) => {
+            diagnostics.push(Diagnostic::error(e.to_string(), func.span));
+            false
+        }
+    });
+    (Some(funcs), diagnostics)
+}
+
+/// Runs the lint passes over every function, returning warnings that don't stop compilation.
+/// Values don't record where they are, so each warning is located at its function's name.
+pub fn lint(funcs: &[Function]) -> Vec<Diagnostic> {
+    let mut warnings = vec![];
+    for func in funcs.iter() {
+        let checks = [check_mixed_widths, check_noop_assignments, check_mixed_signs, check_unused_expressions, check_jagged_arrays];
+        for check in checks {
+            warnings.extend(check(func).into_iter().map(|w| Diagnostic::warning(w, func.span)));
+        }
+    }
+    warnings
+}
+
+/// Warns about arithmetic between integers of different widths (e.g. `byte + int`). The
+/// narrower operand is widened implicitly, but an explicit cast makes the intent clear.
+pub fn check_mixed_widths(func: &Function) -> Vec<String> {
+    let mut warnings = vec![];
+    for_each_value(&func.body, &mut |val| {
+        let Some(op) = val.as_operation() else { return };
+        let (dt_1, dt_2) = (op.opd_1.dtype(), op.opd_2.dtype());
+        let integers = [DataType::I64, DataType::U64, DataType::U8];
+
+        if op.op.is_arithmetic() && dt_1 != dt_2 && integers.contains(&dt_1) && integers.contains(&dt_2) {
+            warnings.push(format!(
+                "in function `{}`: `{}` mixes `{}` and `{}`; consider an explicit cast", func.name, op.value(), dt_1, dt_2
+            ));
+        }
+    });
+    warnings
+}
+
+/// Warns about comparisons between `int` and `uint`. They compare by value, so `-1 < x` holds for
+/// any `uint` x, but that surprises anyone used to C's conversions and an explicit cast is clearer.
+pub fn check_mixed_signs(func: &Function) -> Vec<String> {
+    let mut warnings = vec![];
+    for_each_value(&func.body, &mut |val| {
+        let Some(op) = val.as_operation() else { return };
+        let (dt_1, dt_2) = (op.opd_1.dtype(), op.opd_2.dtype());
+        let signs = [DataType::I64, DataType::U64];
+
+        if op.op.is_comparison() && dt_1 != dt_2 && signs.contains(&dt_1) && signs.contains(&dt_2) {
+            warnings.push(format!(
+                "in function `{}`: `{}` compares `{}` and `{}`, which differ in signedness; consider an explicit cast",
+                func.name, op.value(), dt_1, dt_2
+            ));
+        }
+    });
+    warnings
+}
+
+/// Warns about assignments of a variable to itself, such as `x = x`, which have no effect
+pub fn check_noop_assignments(func: &Function) -> Vec<String> {
+    fn check_block(block: &CodeBlock, func_name: &str, warnings: &mut Vec<String>) {
+        for stmt in block.statements.iter() {
+            match stmt {
+                AstNode::AssignmentStatement(assignment)
+                    if !assignment.is_declaration &&
+                        assignment.src.as_variable().is_some_and(|src| src.name == assignment.dst.name) =>
+                {
+                    warnings.push(format!(
+                        "in function `{}`: no-op assignment `{} = {}`", func_name, assignment.dst.name, assignment.dst.name
+                    ));
+                }
+                AstNode::If(if_obj) => {
+                    for block in if_obj.blocks() {
+                        check_block(block, func_name, warnings);
+                    }
+                }
+                AstNode::Loop(loop_obj) => check_block(&loop_obj.body, func_name, warnings),
+                AstNode::CodeBlock(inner) => check_block(inner, func_name, warnings),
+                _ => {}
+            }
+        }
+    }
+
+    let mut warnings = vec![];
+    check_block(&func.body, &func.name, &mut warnings);
+    warnings
+}
+
+/// Warns about nested array literals whose rows differ in length, such as `[[1, 2], [3]]`. That's
+/// allowed for vectors, but more often a typo than a deliberately jagged vector.
+pub fn check_jagged_arrays(func: &Function) -> Vec<String> {
+    let mut warnings = vec![];
+    for_each_value(&func.body, &mut |val| {
+        let Some(lit) = val.as_array_literal() else { return };
+        if lit.is_jagged() {
+            warnings.push(format!(
+                "in function `{}`: the rows of `{}` differ in length", func.name, lit.value()
+            ));
+        }
+    });
+    warnings
+}
+
+/// Warns about expression statements that can't have an effect, such as `1 + 2` on its own line.
+/// Only calls can have side effects, so any other expression's value is just discarded.
+pub fn check_unused_expressions(func: &Function) -> Vec<String> {
+    fn has_call(val: &dyn Value) -> bool {
+        val.as_call().is_some() || val.children().into_iter().any(has_call)
+    }
+
+    fn check_block(block: &CodeBlock, func_name: &str, warnings: &mut Vec<String>) {
+        for stmt in block.statements.iter() {
+            match stmt {
+                AstNode::ExpressionStatement(val) if !has_call(val.as_ref()) => {
+                    warnings.push(format!("in function `{}`: statement `{}` has no effect", func_name, val.value()));
+                }
+                AstNode::If(if_obj) => {
+                    for block in if_obj.blocks() {
+                        check_block(block, func_name, warnings);
+                    }
+                }
+                AstNode::Loop(loop_obj) => check_block(&loop_obj.body, func_name, warnings),
+                AstNode::CodeBlock(inner) => check_block(inner, func_name, warnings),
+                _ => {}
+            }
+        }
+    }
+
+    let mut warnings = vec![];
+    check_block(&func.body, &func.name, &mut warnings);
+    warnings
+}
+
+/// Returns the number of nodes in the function's AST: the function itself, every statement and
+/// every value, including the operands of each value
+pub fn count_nodes(func: &Function) -> usize {
+    let mut values = 0;
+    for_each_value(&func.body, &mut |_| values += 1);
+    1 + count_statements(&func.body) + values
+}
+
+/// Returns the number of statements in `block`, including those in nested blocks
+fn count_statements(block: &CodeBlock) -> usize {
+    block.statements.iter().map(|stmt| {
+        1 + match stmt {
+            AstNode::If(if_obj) => {
+                if_obj.blocks().map(count_statements).sum::<usize>()
+            }
+            AstNode::Loop(loop_obj) => count_statements(&loop_obj.body),
+            AstNode::CodeBlock(inner) => count_statements(inner),
+            AstNode::Function(func) => count_statements(&func.body),
+            _ => 0,
+        }
+    }).sum()
+}
+
+/// An overview of a parsed program, see `summarize`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ProgramSummary {
+    pub functions: usize,
+    /// Statements in every function body, including those in nested blocks
+    pub statements: usize,
+    /// Binary operations, so `a + b * c` counts as two
+    pub operations: usize,
+    /// How deeply the most nested expression nests, where a literal or variable has depth 1
+    pub max_expression_depth: usize,
+    /// Functions that are called but not defined in the program, other than builtins
+    pub undefined_functions: BTreeSet<String>,
+}
+
+/// Summarizes `program` in a single walk over its functions
+pub fn summarize(program: &[Function]) -> ProgramSummary {
+    let mut summary = ProgramSummary { functions: program.len(), ..Default::default() };
+    for func in program.iter() {
+        summary.statements += count_statements(&func.body);
+        // Each value is visited along with its operands, so this finds the deepest root as well
+        for_each_value(&func.body, &mut |val| {
+            summary.max_expression_depth = summary.max_expression_depth.max(expression_depth(val));
+            if val.as_operation().is_some() {
+                summary.operations += 1;
+            }
+            if let Some(call) = val.as_call() {
+                let defined = program.iter().any(|f| f.name == call.function_name()) || BUILTINS.contains(&&*call.name);
+                if !defined {
+                    summary.undefined_functions.insert(call.name.to_string());
+                }
+            }
+        });
+    }
+    summary
+}
+
+/// How deeply `val` nests, where a literal or variable has depth 1
+fn expression_depth(val: &dyn Value) -> usize {
+    1 + val.children().into_iter().map(expression_depth).max().unwrap_or(0)
+}
+
+/// Calls `f` on every value in `block`, including nested blocks and the operands of each value
+fn for_each_value<'a>(block: &'a CodeBlock, f: &mut impl FnMut(&'a dyn Value)) {
+    fn visit<'a>(val: &'a dyn Value, f: &mut impl FnMut(&'a dyn Value)) {
+        f(val);
+        if let Some(block_expr) = val.as_block_expression() {
+            for_each_value(&block_expr.block, f);
+        }
+        for child in val.children() {
+            visit(child, f);
+        }
+    }
+
+    for stmt in block.statements.iter() {
+        match stmt {
+            AstNode::AssignmentStatement(assignment) => visit(assignment.src.as_ref(), f),
+            AstNode::ExpressionStatement(val) => visit(val.as_ref(), f),
+            AstNode::IndexAssignment(assignment) => {
+                visit(assignment.index.as_ref(), f);
+                visit(assignment.src.as_ref(), f);
+            }
+            AstNode::ReturnStatement(ret) => {
+                if let Some(val) = &ret.value {
+                    visit(val.as_ref(), f);
+                }
+            }
+            AstNode::If(if_obj) => {
+                for arm in if_obj.chain() {
+                    visit(arm.condition.as_ref(), f);
+                    for_each_value(&arm.body, f);
+                }
+                if let Some(else_body) = if_obj.final_else() {
+                    for_each_value(else_body, f);
+                }
+            }
+            AstNode::Loop(loop_obj) => {
+                visit(loop_obj.condition.as_ref(), f);
+                for_each_value(&loop_obj.body, f);
+            }
+            AstNode::CodeBlock(inner) => for_each_value(inner, f),
+            AstNode::Operation(op) => visit(op, f),
+            AstNode::Variable(var) => visit(var, f),
+            AstNode::Function(func) => for_each_value(&func.body, f),
+            AstNode::Break | AstNode::Continue => {}
+        }
+    }
+}
+
+/// Rewrites an AST, taking ownership of each node and returning its replacement. The default
+/// methods rebuild every node from its transformed children, so an implementation only needs to
+/// override the nodes it changes. Types aren't recomputed, so replacements should keep the type
+/// of the node they replace.
+pub trait Transformer {
+    fn transform_value(&mut self, val: Box<dyn Value>) -> Box<dyn Value> {
+        val.map_children(&mut |child| self.transform_value(child))
+    }
+
+    /// Returns the statement that replaces `node`, or `None` to remove it
+    fn transform_node(&mut self, node: AstNode) -> Option<AstNode> {
+        Some(walk_node(self, node))
+    }
+
+    fn transform_block(&mut self, block: CodeBlock) -> CodeBlock {
+        CodeBlock {
+            statements: block.statements.into_iter().filter_map(|stmt| self.transform_node(stmt)).collect(),
+        }
+    }
+
+    fn transform_function(&mut self, func: &mut Function) {
+        let body = std::mem::replace(&mut func.body, CodeBlock { statements: vec![] });
+        func.body = self.transform_block(body);
+    }
+}
+
+/// Transforms the values and blocks inside `node`, but not `node` itself. Implementations of
+/// `Transformer::transform_node` can call this to recurse before or after replacing a node.
+pub fn walk_node<T: Transformer + ?Sized>(t: &mut T, node: AstNode) -> AstNode {
+    match node {
+        AstNode::AssignmentStatement(mut assignment) => {
+            assignment.src = t.transform_value(assignment.src);
+            AstNode::AssignmentStatement(assignment)
+        }
+        AstNode::ExpressionStatement(val) => AstNode::ExpressionStatement(t.transform_value(val)),
+        AstNode::IndexAssignment(mut assignment) => {
+            assignment.index = t.transform_value(assignment.index);
+            assignment.src = t.transform_value(assignment.src);
+            AstNode::IndexAssignment(assignment)
+        }
+        AstNode::ReturnStatement(mut ret) => {
+            ret.value = ret.value.map(|val| t.transform_value(val));
+            AstNode::ReturnStatement(ret)
+        }
+        AstNode::If(mut if_obj) => {
+            if_obj.condition = t.transform_value(if_obj.condition);
+            if_obj.body = t.transform_block(if_obj.body);
+            // An `else if` goes through `transform_node` like any other statement, but it's put
+            // back as a block if it doesn't come out as an `If`
+            if_obj.else_branch = match if_obj.else_branch {
+                Some(ElseBranch::Block(else_body)) => Some(ElseBranch::Block(t.transform_block(else_body))),
+                Some(ElseBranch::If(next)) => match t.transform_node(AstNode::If(*next)) {
+                    Some(AstNode::If(next)) => Some(ElseBranch::If(Box::new(next))),
+                    Some(AstNode::CodeBlock(block)) => Some(ElseBranch::Block(block)),
+                    Some(other) => Some(ElseBranch::Block(CodeBlock::new(vec![other]))),
+                    None => None,
+                },
+                None => None,
+            };
+            AstNode::If(if_obj)
+        }
+        AstNode::Loop(mut loop_obj) => {
+            loop_obj.condition = t.transform_value(loop_obj.condition);
+            loop_obj.body = t.transform_block(loop_obj.body);
+            AstNode::Loop(loop_obj)
+        }
+        AstNode::CodeBlock(inner) => AstNode::CodeBlock(t.transform_block(inner)),
+        AstNode::Operation(mut op) => {
+            op.opd_1 = t.transform_value(op.opd_1);
+            op.opd_2 = t.transform_value(op.opd_2);
+            AstNode::Operation(op)
+        }
+        AstNode::Function(mut func) => {
+            t.transform_function(&mut func);
+            AstNode::Function(func)
+        }
+        other @ (AstNode::Variable(_) | AstNode::Break | AstNode::Continue) => other,
+    }
+}
+
+/// Applies a function to every value in a block, for `Value::map_children`
+struct MapValues<'f>(&'f mut dyn FnMut(Box<dyn Value>) -> Box<dyn Value>);
+
+impl Transformer for MapValues<'_> {
+    fn transform_value(&mut self, val: Box<dyn Value>) -> Box<dyn Value> {
+        (self.0)(val)
+    }
+}
+
+/// Prunes branches whose condition is a constant: `if true { A } else { B }` becomes the block
+/// `A`, `if false { A }` is removed, and so is `while false { ... }`. Conditions can't have side
+/// effects yet, so dropping them is always safe.
+pub fn simplify_control_flow(block: &mut CodeBlock) {
+    let statements = std::mem::take(&mut block.statements);
+    *block = SimplifyControlFlow.transform_block(CodeBlock { statements });
+}
+
+struct SimplifyControlFlow;
+
+/// Replaces operations whose operands are all literals with the literal they evaluate to, e.g.
+/// `5.0 * 2.0` with `10.0`, keeping the sign of `-0.0`. Operations that fail to evaluate, such as
+/// integer overflow, are left for the interpreter to report, and so are float results that have
+/// no literal form (infinities and NaN). Dividing by a literal zero is already rejected by the
+/// parser.
+pub struct ConstantFolder;
+
+impl Transformer for ConstantFolder {
+    fn transform_value(&mut self, val: Box<dyn Value>) -> Box<dyn Value> {
+        let val = val.map_children(&mut |child| self.transform_value(child));
+        let Some(op) = val.as_operation() else { return val };
+        if op.opd_1.as_literal().is_none() || op.opd_2.as_literal().is_none() {
+            return val;
+        }
+
+        match eval_constant(op).and_then(|res| res.to_literal(&op.dtype())) {
+            Some(lit) => Box::new(lit),
+            None => val,
+        }
+    }
+}
+
+impl Transformer for SimplifyControlFlow {
+    fn transform_node(&mut self, node: AstNode) -> Option<AstNode> {
+        // Nested blocks are simplified first, so a pruned branch is already in its final form
+        match walk_node(self, node) {
+            AstNode::If(if_obj) => match if_obj.condition.as_bool_constant() {
+                Some(true) => Some(AstNode::CodeBlock(if_obj.body)),
+                Some(false) => match if_obj.else_branch {
+                    Some(ElseBranch::Block(else_body)) => Some(AstNode::CodeBlock(else_body)),
+                    Some(ElseBranch::If(next)) => Some(AstNode::If(*next)),
+                    None => None,
+                },
+                None => Some(AstNode::If(if_obj)),
+            },
+            AstNode::Loop(loop_obj) if loop_obj.condition.as_bool_constant() == Some(false) => None,
+            other => Some(other),
+        }
+    }
+}
+
+/// Returns true if `block` has a `return` statement, including in nested blocks
+fn contains_return(block: &CodeBlock) -> bool {
+    block.statements.iter().any(|stmt| match stmt {
+        AstNode::ReturnStatement(_) => true,
+        AstNode::If(if_obj) => if_obj.blocks().any(contains_return),
+        AstNode::Loop(loop_obj) => contains_return(&loop_obj.body),
+        AstNode::CodeBlock(inner) => contains_return(inner),
+        _ => false,
+    })
+}
+
+/// Verifies that `break` and `continue` only appear inside a loop body
+pub fn check_loop_control(block: &CodeBlock, in_loop: bool) -> Result<()> {
+    for stmt in block.statements.iter() {
+        match stmt {
+            AstNode::Break if !in_loop => return Err(anyhow!("`break` outside of a loop")),
+            AstNode::Continue if !in_loop => return Err(anyhow!("`continue` outside of a loop")),
+            AstNode::If(if_obj) => {
+                for block in if_obj.blocks() {
+                    check_loop_control(block, in_loop)?;
+                }
+            }
+            AstNode::Loop(loop_obj) => check_loop_control(&loop_obj.body, true)?,
+            AstNode::CodeBlock(inner) => check_loop_control(inner, in_loop)?,
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Verifies that every `return` in a function has its return type, and that every path through
+/// a function with a return type ends in one. Functions without a return type return `Unit` and
+/// may fall off the end of their body.
+pub fn check_returns(func: &Function) -> Result<()> {
+    let ret_type = &func.return_type;
+    check_return_types(&func.body, ret_type, &func.name)?;
+    if *ret_type == DataType::Unit {
+        return Ok(());
+    }
+
+    if !block_always_returns(&func.body) {
+        return Err(anyhow!("function `{}` may reach the end of its body without returning a `{}`", func.name, ret_type));
+    }
+    Ok(())
+}
+
+fn check_return_types(block: &CodeBlock, ret_type: &DataType, func_name: &str) -> Result<()> {
+    for stmt in block.statements.iter() {
+        match stmt {
+            AstNode::ReturnStatement(ret) => {
+                match &ret.value {
+                    Some(val) if ret_type.is_assignable_from(&val.dtype()) => {}
+                    Some(val) if *ret_type == DataType::Unit => return Err(anyhow!(
+                        "function `{}` has no return type but returns `{}` of type `{}`", func_name, val.value(), val.dtype()
+                    )),
+                    Some(val) => return Err(anyhow!(
+                        "function `{}` returns `{}` but is declared to return `{}`", func_name, val.dtype(), ret_type
+                    )),
+                    None if *ret_type == DataType::Unit => {}
+                    None => return Err(anyhow!("function `{}` must return a `{}`", func_name, ret_type)),
+                }
+            }
+            AstNode::If(if_obj) => {
+                for block in if_obj.blocks() {
+                    check_return_types(block, ret_type, func_name)?;
+                }
+            }
+            AstNode::Loop(loop_obj) => check_return_types(&loop_obj.body, ret_type, func_name)?,
+            AstNode::CodeBlock(inner) => check_return_types(inner, ret_type, func_name)?,
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Returns true if every path through the block ends in a `return`. Loops are never assumed
+/// to run, so a `return` inside a loop body doesn't count.
+fn block_always_returns(block: &CodeBlock) -> bool {
+    block.statements.iter().any(|stmt| {
+        match stmt {
+            AstNode::ReturnStatement(_) => true,
+            AstNode::If(if_obj) => {
+                // Without a final `else`, none of the arms may run
+                if_obj.final_else().is_some() && if_obj.blocks().all(block_always_returns)
+            }
+            AstNode::CodeBlock(inner) => block_always_returns(inner),
+            _ => false,
+        }
+    })
+}
 
 pub struct Parser;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+
+    fn lex(src: &str) -> Lexer<'_> {
+        let mut lexer = Lexer::new(src);
+        while lexer.next().is_some() {}
+        lexer
+    }
+
+    /// Returns the error compiling `src` fails with
+    fn compile_error(src: &str) -> String {
+        match compile_str(src) {
+            Ok(funcs) => panic!("expected {:?} not to compile, got {:?}", src, funcs),
+            Err(e) => e.to_string(),
+        }
+    }
+
+    /// Parses the first function of `src` without validating the tokens first, as the parser
+    /// must cope with input `prevalidate` would have rejected
+    fn parse_function(src: &str) -> Result<Function> {
+        let lexer = lex(src);
+        AstNode::generate_function(&lexer.tokens, &mut VarLst::new(), &mut FnLst::new()).map(|(func, _)| func)
+    }
+
+    /// Returns the error parsing and checking the function in `src` fails with
+    fn check_error(src: &str) -> String {
+        match parse_function(src).and_then(|func| check_returns(&func)) {
+            Ok(()) => panic!("expected {:?} to fail", src),
+            Err(e) => e.to_string(),
+        }
+    }
+
+    #[test]
+    fn literals_are_validated_against_their_type() {
+        assert_eq!(Literal::parse_typed("1_000", DataType::I64).unwrap().value.as_ref(), "1000");
+        let e = Literal::parse_typed("9223372036854775808", DataType::I64).unwrap_err();
+        assert_eq!(e.to_string(), "integer literal `9223372036854775808` is too large for `int`");
+        let e = Literal::parse_typed("256", DataType::U8).unwrap_err();
+        assert_eq!(e.to_string(), "integer literal `256` is too large for `byte`");
+        let e = Literal::parse_typed("1.2.3", DataType::F64).unwrap_err();
+        assert_eq!(e.to_string(), "malformed float literal: `1.2.3`");
+        let e = Literal::parse_typed("1x", DataType::I64).unwrap_err();
+        assert_eq!(e.to_string(), "integer literal `1x` is not a valid `int`");
+        assert!(Literal::parse_typed("'ab'", DataType::Char).is_err());
+    }
+
+    #[test]
+    fn returning_in_both_branches_always_returns() {
+        let func = parse_function("fn f(int x) int {\n    if x > 0 {\n        return 1\n    } else {\n        return 2\n    }\n}\n").unwrap();
+        assert!(check_returns(&func).is_ok());
+    }
+
+    #[test]
+    fn returning_in_one_branch_may_not_return() {
+        let e = check_error("fn f(int x) int {\n    if x > 0 {\n        return 1\n    }\n}\n");
+        assert_eq!(e, "function `f` may reach the end of its body without returning a `int`");
+    }
+
+    #[test]
+    fn returns_are_checked_against_the_return_type() {
+        let e = check_error("fn f() int {\n    return true\n}\n");
+        assert_eq!(e, "function `f` returns `bool` but is declared to return `int`");
+        let e = check_error("fn f() float {\n    return\n}\n");
+        assert_eq!(e, "function `f` must return a `float`");
+    }
+
+    #[test]
+    fn else_needs_a_block() {
+        let e = check_error("fn f(int x) int {\n    if x > 0 {\n        return 0\n    } else return 1;\n}\n");
+        assert_eq!(e, "expected `{` after `else`, found `return` (4:12)");
+        let e = check_error("fn f(int x) int {\n    if x > 0\n    {\n        return 0\n    }\n    return 1\n}\n");
+        assert_eq!(e, "expected `{` after the condition of `if`, found `\\n` (2:13)");
+    }
+
+    #[test]
+    fn constants_cannot_be_reassigned() {
+        let e = compile_error("fn main() {\n    const int x = 1\n    x = 2\n}\n");
+        assert_eq!(e, "cannot assign to constant `x` (3:5)");
+        let e = compile_error("fn main() {\n    const int x = 1\n    x += 2\n}\n");
+        assert_eq!(e, "cannot assign to constant `x` (3:5)");
+        let e = compile_error("fn main() {\n    int y = 0\n    const int x = 1\n    y = x = 2\n}\n");
+        assert_eq!(e, "cannot assign to constant `x` (4:9)");
+        assert!(parse_function("fn main() {\n    const int x = 1\n    int y = x\n}\n").is_ok());
+    }
+
+    #[test]
+    fn pub_marks_functions_public() {
+        assert!(parse_function("pub fn f() {\n}\n").unwrap().is_public);
+        assert!(!parse_function("fn g() {\n}\n").unwrap().is_public);
+
+        let e = parse_function("pub pub fn f() {\n}\n").unwrap_err();
+        assert_eq!(e.to_string(), "`pub` may only appear before `fn` (1:1)");
+        let e = parse_function("fn f() {\n    pub int x = 1\n}\n").unwrap_err();
+        assert_eq!(e.to_string(), "`pub` may only appear before `fn` (2:5)");
+    }
+
+    #[test]
+    fn postfix_operators_chain() {
+        let func = parse_function("fn f(int[][] m) int {\n    int x = m[0][1]\n    return x\n}\n").unwrap();
+        let AstNode::AssignmentStatement(first) = &func.body.statements[0] else { panic!() };
+        assert_eq!(first.src.value(), "m[0][1]");
+        assert_eq!(first.src.dtype(), DataType::I64);
+
+        let e = parse_function("fn f(int[] xs) int {\n    int x = xs[0].y\n    return x\n}\n").unwrap_err();
+        assert_eq!(e.to_string(), "type `int` has no field `y`");
+        let e = parse_function("fn f(int y) int {\n    int x = y[0]\n    return x\n}\n").unwrap_err();
+        assert_eq!(e.to_string(), "cannot index into `y` of type `int`");
+        let e = parse_function("fn f(int[] xs) int {\n    int x = xs[true]\n    return x\n}\n").unwrap_err();
+        assert_eq!(e.to_string(), "index `true` must be an integer, found `bool`");
+    }
+
+    #[test]
+    fn variables_know_whether_they_are_parameters() {
+        let func = parse_function("fn f(int a) int {\n    int b = a\n    a = b\n    return b\n}\n").unwrap();
+        assert!(func.parameters[0].is_parameter);
+
+        let AstNode::AssignmentStatement(decl) = &func.body.statements[0] else { panic!() };
+        assert!(!decl.dst.is_parameter);
+        let AstNode::AssignmentStatement(reassignment) = &func.body.statements[1] else { panic!() };
+        assert!(reassignment.dst.is_parameter);
+    }
+
+    #[test]
+    fn negative_literals_are_range_checked() {
+        let func = parse_function("fn f() int {\n    int x = -5\n    return x\n}\n").unwrap();
+        let AstNode::AssignmentStatement(decl) = &func.body.statements[0] else { panic!() };
+        assert_eq!(decl.src.value(), "-5");
+        assert!(parse_function("fn f() int {\n    int x = -9223372036854775808\n    return x\n}\n").is_ok());
+
+        let e = parse_function("fn f() uint {\n    uint x = -5\n    return x\n}\n").unwrap_err();
+        assert_eq!(e.to_string(), "cannot negate unsigned value `5` of type `uint`");
+        let e = parse_function("fn f(uint u) uint {\n    uint x = -u\n    return x\n}\n").unwrap_err();
+        assert_eq!(e.to_string(), "cannot negate unsigned value `u` of type `uint`");
+        let e = parse_function("fn f() int {\n    int x = -9223372036854775809\n    return x\n}\n").unwrap_err();
+        assert_eq!(e.to_string(), "integer literal `-9223372036854775809` is too small for `int` (2:13)");
+    }
+
+    #[test]
+    fn every_operator_is_described() {
+        // Matching every variant makes this fail to compile when one is added, as a reminder to
+        // add it to `OperationType::ALL` too
+        let index = |op: OperationType| match op {
+            OperationType::Add => 0, OperationType::Subtract => 1, OperationType::Div => 2,
+            OperationType::Mult => 3, OperationType::Mod => 4, OperationType::GreaterThan => 5,
+            OperationType::LessThan => 6, OperationType::GreaterThanOrEq => 7,
+            OperationType::LessThanOrEq => 8, OperationType::Eq => 9, OperationType::NotEq => 10,
+            OperationType::In => 11,
+        };
+        let mut seen = OperationType::ALL.map(index);
+        seen.sort();
+        assert_eq!(seen, std::array::from_fn(|i| i));
+
+        for op in OperationType::ALL {
+            let info = describe_operator(op);
+            let lexer = lex(info.symbol);
+            assert_eq!(OperationType::new(&lexer.tokens[0]).unwrap(), op, "{:?}", info);
+            assert!(info.precedence > 0, "{:?}", info);
+            assert_eq!(info.result == ResultType::Bool, !op.is_arithmetic(), "{:?}", info);
+            assert_eq!(info.operands == OperandTypes::Numeric, op.is_arithmetic(), "{:?}", info);
+        }
+    }
+
+    #[test]
+    fn prevalidate_checks_structure() {
+        let messages = |src: &str| prevalidate(&lex(src).tokens).iter().map(|e| e.to_string()).collect::<Vec<_>>();
+        assert_eq!(messages("fn f() {\n    if true {\n}\n"), ["unclosed `{` (1:8)"]);
+        assert_eq!(messages("fn f() {\n}\n}\n"), ["unmatched `}` (3:1)"]);
+        assert_eq!(messages("fn f() {\n    int x = (1]\n}\n"), ["unmatched `]` (2:15)", "unclosed `(` (2:13)"]);
+        assert_eq!(messages("fn () {\n}\n"), ["expected a function name after `fn` (1:1)"]);
+        assert_eq!(messages("fn f {\n}\n"), ["expected `(` after function name `f` (1:4)"]);
+        assert_eq!(messages("pub int x = 1\n"), ["`pub` may only appear before `fn` (1:1)"]);
+        assert!(messages("fn f() {\n    int x = (1)\n}\n").is_empty());
+    }
+
+    #[test]
+    fn blank_lines_and_comments_around_functions_are_ignored() {
+        let funcs = compile_str("\n\n// a comment\n\nfn f() int {\n    return 1\n}\n\n// another\n\n").unwrap();
+        assert_eq!(funcs.len(), 1);
+        assert_eq!(funcs[0].name, "f");
+        assert_eq!((funcs[0].span.line, funcs[0].span.col), (5, 4));
+        assert_eq!(parse_function("\n\n").unwrap_err().to_string(), "expected a function definition");
+    }
+
+    #[test]
+    fn interned_strings_are_shared_within_a_compilation_only() {
+        let funcs = compile_str("fn f(int a) {\n    int b = a\n    b = a + 1\n}\n").unwrap();
+        let AstNode::AssignmentStatement(decl) = &funcs[0].body.statements[0] else { panic!() };
+        let AstNode::AssignmentStatement(assign) = &funcs[0].body.statements[1] else { panic!() };
+        assert!(Rc::ptr_eq(&decl.dst.name, &assign.dst.name));
+        // The interner is emptied once the compilation is done
+        assert!(!Rc::ptr_eq(&intern("b"), &decl.dst.name));
+    }
+
+    #[test]
+    fn constant_conditions_prune_branches() {
+        let funcs = compile_str("fn f() int {\n    if true {\n        return 1\n    } else {\n        return 2\n    }\n}\n").unwrap();
+        let [AstNode::CodeBlock(taken)] = &funcs[0].body.statements[..] else { panic!("{:?}", funcs[0].body) };
+        let [AstNode::ReturnStatement(ret)] = &taken.statements[..] else { panic!("{:?}", taken) };
+        assert_eq!(ret.value.as_ref().unwrap().value(), "1");
+
+        let src = "fn f() int {\n    if false {\n        return 1\n    }\n    while false {\n    }\n    return 2\n}\n";
+        let funcs = compile_str(src).unwrap();
+        assert!(matches!(&funcs[0].body.statements[..], [AstNode::ReturnStatement(_)]), "{:?}", funcs[0].body);
+    }
+
+    #[test]
+    fn conditionals_take_the_type_of_their_branches() {
+        let funcs = compile_str("fn f(bool c) float {\n    float x = c ? 1 : 2.5\n    return x\n}\n").unwrap();
+        let AstNode::AssignmentStatement(decl) = &funcs[0].body.statements[0] else { panic!() };
+        assert_eq!(decl.src.dtype(), DataType::F64);
+        assert_eq!(decl.src.value(), "(c ? 1 : 2.5)");
+
+        let e = compile_error("fn f(bool c) int {\n    int x = c ? 1 : \"a\"\n    return x\n}\n");
+        assert_eq!(e, "branches `1` and `\"a\"` have incompatible types `int` and `byte[]`");
+        let e = compile_error("fn f(int c) int {\n    int x = c ? 1 : 2\n    return x\n}\n");
+        assert_eq!(e, "condition `c` must be a `bool`, found `int`");
+    }
+
+    #[test]
+    fn locals_are_listed_in_declaration_order() {
+        let src = "fn f(int a) int {\n    int b = a\n    if true {\n        float c = 1.5\n    }\n    const bool d = false\n    return b\n}\n";
+        let funcs = compile_str(src).unwrap();
+        let locals = funcs[0].locals().iter()
+            .map(|(name, dtype, span)| (name.as_str(), dtype.clone(), span.line))
+            .collect::<Vec<_>>();
+        assert_eq!(locals, [("b", DataType::I64, 2), ("c", DataType::F64, 4), ("d", DataType::Bool, 6)]);
+    }
+
+    #[test]
+    fn integer_literals_must_fit_their_type() {
+        let e = compile_error("fn f() int {\n    int x = 99999999999999999999\n    return x\n}\n");
+        assert_eq!(e, "integer literal `99999999999999999999` is too large for `int` (2:13)");
+        let e = compile_error("fn f() byte {\n    byte x = 256\n    return x\n}\n");
+        assert_eq!(e, "integer literal `256` is too large for `byte` (2:14)");
+
+        for (dtype, max) in [("int", "9223372036854775807"), ("uint", "18446744073709551615"), ("byte", "255")] {
+            let src = format!("fn f() {} {{\n    {} x = {}\n    return x\n}}\n", dtype, dtype, max);
+            assert!(compile_str(&src).is_ok(), "{}", src);
+        }
+    }
+
+    #[test]
+    fn mixed_integer_widths_are_warned_about() {
+        let funcs = compile_str("fn f(byte b, int i) int {\n    int x = b + i\n    int y = i + i\n    return x\n}\n").unwrap();
+        assert_eq!(check_mixed_widths(&funcs[0]), ["in function `f`: `(b + i)` mixes `byte` and `int`; consider an explicit cast"]);
+    }
+
+    #[test]
+    fn break_and_continue_must_be_in_a_loop() {
+        let src = "fn f() {\n    while true {\n        if false {\n            continue\n        }\n        break\n    }\n}\n";
+        assert!(compile_str(src).is_ok());
+        assert_eq!(compile_error("fn f() {\n    break\n}\n"), "`break` outside of a loop");
+        assert_eq!(compile_error("fn f() {\n    if true {\n        continue\n    }\n}\n"), "`continue` outside of a loop");
+    }
+
+    #[test]
+    fn equality_works_on_bools_and_strings() {
+        let funcs = compile_str("fn f(string s) bool {\n    bool a = true == false\n    bool b = s != \"x\"\n    return a\n}\n").unwrap();
+        let AstNode::AssignmentStatement(decl) = &funcs[0].body.statements[0] else { panic!() };
+        assert_eq!(decl.src.value(), "false");
+
+        // Strings and bools are equatable but not ordered
+        let e = compile_error("fn f() bool {\n    bool a = \"a\" < \"b\"\n    return a\n}\n");
+        assert_eq!(e, "`<` requires numeric or `char` operands, found `byte[]` and `byte[]` in `(\"a\" < \"b\")`");
+        let e = compile_error("fn f() bool {\n    bool a = 1 == true\n    return a\n}\n");
+        assert_eq!(e, "cannot compare `int` and `bool` with `==` in `(1 == true)`");
+        let e = compile_error("fn f(int i, uint u) int {\n    int x = i + u\n    return x\n}\n");
+        assert_eq!(e, "mismatched operand types `int` and `uint` in `(i + u)`");
+        let e = compile_error("fn f() int {\n    int x = 1 + true\n    return x\n}\n");
+        assert_eq!(e, "`+` requires numeric operands, found `int` and `bool` in `(1 + true)`");
+    }
+
+    #[test]
+    fn print_calls_parse_as_statements() {
+        let funcs = compile_str("fn main() {\n    print(\"hi\")\n    print(1 + 2)\n}\n").unwrap();
+        let args = funcs[0].body.statements.iter().map(|stmt| {
+            let AstNode::ExpressionStatement(val) = stmt else { panic!("{:?}", stmt) };
+            let call = val.as_call().unwrap();
+            assert_eq!((&*call.name, val.dtype()), ("print", DataType::Unit));
+            call.args.iter().map(|a| a.value()).collect::<Vec<_>>()
+        }).collect::<Vec<_>>();
+        // `1 + 2` is folded
+        assert_eq!(args, [["\"hi\""], ["3"]]);
+        assert_eq!(compile_error("fn main() {\n    print(1, 2)\n}\n"), "`print` takes 1 argument but 2 were given");
+    }
+
+    #[test]
+    fn calls_are_typed_by_their_return_type() {
+        let add = "fn add(int a, int b) int {\n    return a + b\n}\n";
+        let funcs = compile_str(&format!("{}fn main() int {{\n    int x = add(1, 2)\n    return x\n}}\n", add)).unwrap();
+        let AstNode::AssignmentStatement(decl) = &funcs[1].body.statements[0] else { panic!() };
+        assert_eq!((decl.src.value(), decl.src.dtype()), ("add(1, 2)".to_string(), DataType::I64));
+
+        let e = compile_error(&format!("{}fn main() {{\n    string y = add(1, 2)\n}}\n", add));
+        assert_eq!(e, "cannot assign `add(1, 2)` of type `int` to `y` of type `string` (5:12)");
+    }
+
+    #[test]
+    fn returned_values_must_fit_the_return_type() {
+        let e = compile_error("fn main() int {\n    return 1.5\n}\n");
+        assert_eq!(e, "function `main` returns `float` but is declared to return `int`");
+    }
+
+    #[test]
+    fn folding_reduces_the_node_count() {
+        let src = "fn f() int {\n    return 1 + 2 * 3\n}\n";
+        // The function, the `return`, both operations and their three literals
+        assert_eq!(count_nodes(&parse_function(src).unwrap()), 7);
+        // The function, the `return` and the literal `7`
+        assert_eq!(count_nodes(&compile_str(src).unwrap()[0]), 3);
+        // `fn f ( ) int { \n return 1 + 2 * 3 \n } \n` and the final `Eof`
+        assert_eq!(lex(src).token_count(), 17);
+    }
+
+    #[test]
+    fn transformers_replace_values_everywhere() {
+        struct DoubleIntegers;
+        impl Transformer for DoubleIntegers {
+            fn transform_value(&mut self, val: Box<dyn Value>) -> Box<dyn Value> {
+                let val = val.map_children(&mut |child| self.transform_value(child));
+                match val.as_literal() {
+                    Some(lit) if lit.dtype == DataType::I64 => {
+                        let doubled = lit.value.parse::<i64>().unwrap() * 2;
+                        Box::new(Literal::parse_typed(&doubled.to_string(), DataType::I64).unwrap())
+                    }
+                    _ => val,
+                }
+            }
+        }
+
+        let mut func = parse_function("fn f(int a) int {\n    int b = a + 1\n    if a > 2 {\n        return b * 3\n    }\n    return 4\n}\n").unwrap();
+        DoubleIntegers.transform_function(&mut func);
+        let mut values = vec![];
+        for_each_value(&func.body, &mut |val| if val.as_operation().is_some() || val.as_literal().is_some() {
+            values.push(val.value());
+        });
+        assert_eq!(values, ["(a + 2)", "2", "(a > 4)", "4", "(b * 6)", "6", "8"]);
+    }
+
+    #[test]
+    fn empty_arrays_take_their_type_from_the_declaration() {
+        let funcs = compile_str("fn f() {\n    int[] xs = []\n    string[][] ys = []\n}\n").unwrap();
+        let types = funcs[0].body.statements.iter().map(|stmt| {
+            let AstNode::AssignmentStatement(decl) = stmt else { panic!("{:?}", stmt) };
+            decl.src.dtype().to_string()
+        }).collect::<Vec<_>>();
+        assert_eq!(types, ["int[]", "string[][]"]);
+
+        // Without a declared type there's nothing to infer the element type from
+        let e = compile_error("fn f() bool {\n    bool b = [] == []\n    return b\n}\n");
+        assert_eq!(e, "cannot infer element type of empty array");
+    }
+
+    #[test]
+    fn vector_elements_can_be_assigned() {
+        let funcs = compile_str("fn f(int[] xs) {\n    xs[0] = 5\n}\n").unwrap();
+        assert!(matches!(&funcs[0].body.statements[..], [AstNode::IndexAssignment(_)]), "{:?}", funcs[0].body);
+
+        let e = compile_error("fn f(int[] xs) {\n    xs[0] = \"a\"\n}\n");
+        assert_eq!(e, "cannot assign `\"a\"` of type `byte[]` to an element of `xs` of type `int[]`");
+        let e = compile_error("fn f(int x) {\n    x[0] = 5\n}\n");
+        assert_eq!(e, "cannot index into `x` of type `int`");
+    }
+
+    #[test]
+    fn self_assignments_are_reported() {
+        let e = compile_error("fn f() int {\n    int x = x + 1\n    return x\n}\n");
+        assert_eq!(e, "variable `x` used in its own initializer (2:9)");
+        // An `x` from an enclosing scope may initialize a new one
+        assert!(compile_str("fn f(int x) {\n    if true {\n        int x = x\n    }\n}\n").is_ok());
+
+        let funcs = compile_str("fn f(int x) int {\n    x = x\n    return x\n}\n").unwrap();
+        assert_eq!(check_noop_assignments(&funcs[0]), ["in function `f`: no-op assignment `x = x`"]);
+    }
+
+    #[test]
+    fn tuples_type_each_element() {
+        let funcs = compile_str("fn pair() (int, bool) {\n    return (1, true)\n}\n").unwrap();
+        assert_eq!(funcs[0].return_type(), &DataType::Tuple(vec![DataType::I64, DataType::Bool]));
+        let AstNode::ReturnStatement(ret) = &funcs[0].body.statements[0] else { panic!() };
+        let value = ret.value.as_ref().unwrap();
+        assert_eq!((value.value(), value.dtype().to_string()), ("(1, true)".to_string(), "(int, bool)".to_string()));
+
+        let e = compile_error("fn pair() (int, bool) {\n    return (true, 1)\n}\n");
+        assert_eq!(e, "function `pair` returns `(bool, int)` but is declared to return `(int, bool)`");
+    }
+
+    #[test]
+    fn function_names_are_unique() {
+        let e = compile_error("fn f() {\n}\nfn f() {\n}\n");
+        assert_eq!(e, "function `f` is defined more than once (1:4 and 3:4)");
+        assert!(compile_str("fn f() {\n}\nfn g() {\n}\n").is_ok());
+    }
+
+    #[test]
+    fn omitted_arguments_take_their_default() {
+        let greet = "fn greet(int a, int times = 3) int {\n    return a * times\n}\n";
+        let funcs = compile_str(&format!("{}fn main() int {{\n    return greet(2)\n}}\n", greet)).unwrap();
+        assert_eq!(funcs[0].defaults.iter().map(|d| d.as_ref().map(|v| v.value())).collect::<Vec<_>>(), [None, Some("3".to_string())]);
+
+        let e = compile_error("fn g(int a = 1, int b) {\n}\n");
+        assert_eq!(e, "required parameter `b` cannot follow a parameter with a default value");
+        let e = compile_error("fn g(int a, int b = a) {\n}\n");
+        assert_eq!(e, "default value for parameter `b` must be a constant expression, found `a` (1:21)");
+    }
+
+    #[test]
+    fn functions_without_a_return_type_return_unit() {
+        let funcs = compile_str("fn f() {\n    print(1)\n}\nfn g() int {\n    return 1\n}\n").unwrap();
+        assert_eq!(funcs[0].return_type(), &DataType::Unit);
+        assert_eq!(funcs[1].return_type(), &DataType::I64);
+
+        let e = compile_error("fn f() {\n    return 1\n}\n");
+        assert_eq!(e, "function `f` has no return type but returns `1` of type `int`");
+    }
+
+    #[test]
+    fn modulo_works_on_integers_and_floats() {
+        let src = "fn f() {\n    int a = 5 % 2\n    float b = 1.5 % 0.5\n    float c = -5.5 % 2.0\n}\n";
+        let func = parse_function(src).unwrap();
+        let types = func.body.statements.iter().map(|stmt| {
+            let AstNode::AssignmentStatement(decl) = stmt else { panic!("{:?}", stmt) };
+            decl.src.dtype()
+        }).collect::<Vec<_>>();
+        assert_eq!(types, [DataType::I64, DataType::F64, DataType::F64]);
+
+        // Folding computes the remainder like C's `fmod`, with the sign of the left operand
+        let funcs = compile_str(src).unwrap();
+        let values = funcs[0].body.statements.iter().map(|stmt| {
+            let AstNode::AssignmentStatement(decl) = stmt else { panic!("{:?}", stmt) };
+            decl.src.value()
+        }).collect::<Vec<_>>();
+        assert_eq!(values, ["1", "0.0", "-1.5"]);
+    }
+
+    #[test]
+    fn comparisons_are_bool_values() {
+        let funcs = compile_str("fn f(int a, int b) bool {\n    bool ok = a < b\n    return ok\n}\n").unwrap();
+        let AstNode::AssignmentStatement(decl) = &funcs[0].body.statements[0] else { panic!() };
+        assert_eq!((decl.src.value(), decl.src.dtype()), ("(a < b)".to_string(), DataType::Bool));
+
+        let e = compile_error("fn f(int a, int b) int {\n    int ok = a < b\n    return ok\n}\n");
+        assert_eq!(e, "cannot assign `(a < b)` of type `bool` to `ok` of type `int` (2:9)");
+    }
+
+    #[test]
+    fn declarations_are_checked_against_their_type() {
+        let e = compile_error("fn f() int {\n    int x = true\n    return x\n}\n");
+        assert_eq!(e, "cannot assign `true` of type `bool` to `x` of type `int` (2:9)");
+        let e = compile_error("fn f() bool {\n    bool b = 1 + 2\n    return b\n}\n");
+        assert_eq!(e, "cannot assign `(1 + 2)` of type `int` to `b` of type `bool` (2:10)");
+
+        // Integers widen to floats, and bytes to the other integer types
+        assert!(compile_str("fn f(byte b) float {\n    float x = 1 + 2\n    int y = b\n    return x\n}\n").is_ok());
+    }
+
+    #[test]
+    fn integer_literals_take_the_configured_default_type() {
+        let src = "fn f() bool {\n    bool b = 1\n    return b\n}\n";
+        let error = |options| compile_str_with_options(src, options).unwrap_err().to_string();
+        let signed = ParserOptions::new(DataType::I64, DataType::F64).unwrap();
+        let unsigned = ParserOptions::new(DataType::U64, DataType::F64).unwrap();
+        assert_eq!(error(signed), "cannot assign `1` of type `int` to `b` of type `bool` (2:10)");
+        assert_eq!(error(unsigned), "cannot assign `1` of type `uint` to `b` of type `bool` (2:10)");
+
+        assert!(ParserOptions::new(DataType::Bool, DataType::F64).is_err());
+        assert!(ParserOptions::new(DataType::I64, DataType::I64).is_err());
+    }
+
+    #[test]
+    fn doc_comments_attach_to_the_following_function() {
+        let funcs = compile_str("/// Adds one\n/// to x\nfn f(int x) int {\n    return x + 1\n}\n// plain\nfn g() {\n}\n").unwrap();
+        assert_eq!(funcs[0].doc(), Some("Adds one\nto x"));
+        assert_eq!(funcs[1].doc(), None);
+
+        let funcs = compile_str("/// detached\n\nfn f() {\n}\n").unwrap();
+        assert_eq!(funcs[0].doc(), None);
+    }
+
+    #[test]
+    fn folded_floats_render_like_literals() {
+        let funcs = compile_str("fn f() float {\n    return 5.0 * 2.0\n}\nfn g() float {\n    return 10.0\n}\n").unwrap();
+        let rendered = |func: &Function| {
+            let mut values = vec![];
+            for_each_value(&func.body, &mut |val| values.push(val.value()));
+            values
+        };
+        assert_eq!(rendered(&funcs[0]), vec!["10.0"]);
+        assert_eq!(rendered(&funcs[0]), rendered(&funcs[1]));
+        assert_eq!(format_float(1e20), "1e20");
+    }
+
+    #[test]
+    fn in_tests_vector_membership() {
+        let funcs = compile_str("fn main(int[] xs) bool {\n    return 3 in xs\n}\n").unwrap();
+        assert_eq!(funcs[0].return_type(), &DataType::Bool);
+
+        let e = compile_error("fn f(int x) bool {\n    bool b = x in 5\n    return b\n}\n");
+        assert_eq!(e, "`in` requires a vector on the right, found `5` of type `int`");
+        let e = compile_error("fn f(string[] xs) bool {\n    bool b = 3 in xs\n    return b\n}\n");
+        assert_eq!(e, "cannot look for `3` of type `int` in `xs` of type `string[]`");
+    }
+
+    #[test]
+    fn stray_tokens_after_the_last_function_are_errors() {
+        let e = compile_error("fn f() {\n}\nint\n");
+        assert_eq!(e, "unexpected tokens after function definition, starting with `int` (3:1)");
+        assert!(compile_str("fn f() {\n}\n\n\n").is_ok());
+    }
+
+    #[test]
+    fn signatures_render_parameter_and_return_types() {
+        let funcs = compile_str("fn add(int a, int b) int {\n    return a + b\n}\nfn show(string s) {\n    print(s)\n}\n").unwrap();
+        assert_eq!(funcs[0].signature_string(), "add(int, int) -> int");
+        assert_eq!(funcs[1].signature_string(), "show(string)");
+
+        let e = compile_error("fn add(int a, int b) int {\n    return a + b\n}\nfn main() int {\n    int x = add(1, true)\n    return x\n}\n");
+        assert!(e.ends_with("expected `add(int, int) -> int`, got `add(int, bool)`"), "{}", e);
+    }
+
+    #[test]
+    fn truncated_headers_are_eof_errors() {
+        for src in ["fn", "fn f", "fn f(", "fn f(int", "fn f(int x", "fn f(int x) int"] {
+            let e = parse_function(src).unwrap_err().to_string();
+            assert!(e.starts_with("unexpected end of input"), "{:?}: {}", src, e);
+        }
+        assert!(compile_error("fn f(").starts_with("unclosed `(` (1:5)"));
+    }
+
+    #[test]
+    fn truncated_statements_are_eof_errors() {
+        for src in ["fn main() {\n    int x =", "fn main() {\n    int x = 1 +", "fn main() {\n    return", "fn main() {\n    while true"] {
+            let e = parse_function(src).unwrap_err().to_string();
+            assert!(e.starts_with("unexpected end of input") || e.starts_with("expected an expression"), "{:?}: {}", src, e);
+        }
+        assert!(compile_error("fn main() {\n    int x =").contains("unclosed `{`"));
+    }
+
+    #[test]
+    fn malformed_headers_are_errors() {
+        assert_eq!(parse_function("fn (int x) {\n}\n").unwrap_err().to_string(), "expected a function name, found `(` (1:4)");
+        assert_eq!(parse_function("fn f[] {\n}\n").unwrap_err().to_string(), "expected `(` after the function name, found `[` (1:5)");
+        assert_eq!(parse_function("fn f(1) {\n}\n").unwrap_err().to_string(), "expected a parameter type, found `1` (1:6)");
+        assert_eq!(parse_function("fn f(int 1) {\n}\n").unwrap_err().to_string(), "expected a parameter name, found `1` (1:10)");
+    }
+
+    #[test]
+    fn unexpected_tokens_in_statements_are_errors() {
+        assert_eq!(compile_error("fn main() {\n    ,\n}\n"), "unexpected `,` (2:5)");
+        assert_eq!(compile_error("fn main() {\n    int x = 0\n    const x = 1\n}\n"), "expected a type after `const`, found `x` (3:11)");
+        let e = compile_error("fn main() {\n    while true\n    {\n    }\n}\n");
+        assert_eq!(e, "expected `{` after the condition of `while`, found `\\n` (2:15)");
+    }
+
+    #[test]
+    fn every_arithmetic_operator_has_a_compound_assignment() {
+        for symbol in crate::lexer::ARITHMETIC_OPERATORS {
+            let op = OperationType::from_compound_assignment(&format!("{}=", symbol)).unwrap();
+            assert_eq!(describe_operator(op).symbol, symbol);
+        }
+        // `<=` is a comparison rather than a compound `<`
+        assert_eq!(OperationType::from_compound_assignment("<="), None);
+    }
+
+    #[test]
+    fn assignments_inside_expressions_are_errors() {
+        let e = compile_error("fn f() int {\n    int y = 1\n    int x = (y = 5)\n    return x\n}\n");
+        assert_eq!(e, "assignment is not an expression; did you mean `==`? (3:16)");
+    }
+
+    #[test]
+    fn float_folding_handles_ieee_edge_cases() {
+        let e = compile_error("fn f() float {\n    return 0.0 / 0.0\n}\n");
+        assert_eq!(e, "division by zero in `(0.0 / 0.0)`");
+        let e = compile_error("fn f() float {\n    return 1.0 / 0.0\n}\n");
+        assert_eq!(e, "division by zero in `(1.0 / 0.0)`");
+
+        let rendered = |src: &str| {
+            let funcs = compile_str(src).unwrap();
+            let mut values = vec![];
+            for_each_value(&funcs[0].body, &mut |val| values.push(val.value()));
+            values
+        };
+        assert_eq!(rendered("fn f() float {\n    return 0.0 * -1.0\n}\n"), ["-0.0"]);
+        // An infinite result has no literal form, so it's left for the interpreter
+        assert_eq!(rendered("fn f() float {\n    return 1e308 * 10.0\n}\n"), ["(1e308 * 10.0)", "1e308", "10.0"]);
+    }
+
+    #[test]
+    fn panics_are_caught_as_parse_errors() {
+        let e = catch_parse(|| panic!("bad input `{}`", 1)).unwrap_err();
+        assert_eq!(e.to_string(), "bad input `1`");
+        assert_eq!(catch_parse(|| 1 + 1).unwrap(), 2);
+
+        // Names the lexer hasn't seen declared are left to the parser, and characters it can't
+        // lex are an error from the lexer's panic
+        assert_eq!(compile_error("fn f() {\n    x = 1\n}\n"), "undefined variable `x` (2:5)");
+        assert!(compile_error("fn f() {\n    int x = 1 @ 2\n}\n").starts_with("No condition parsing met at 2:15"));
+    }
+
+    #[test]
+    fn comparing_signed_and_unsigned_integers_is_warned_about() {
+        let funcs = compile_str("fn f(uint u, int i, int j) bool {\n    bool a = u < i\n    bool b = i < j\n    return a\n}\n").unwrap();
+        assert_eq!(check_mixed_signs(&funcs[0]), [
+            "in function `f`: `(u < i)` compares `uint` and `int`, which differ in signedness; consider an explicit cast",
+        ]);
+    }
+
+    #[test]
+    fn summaries_count_the_program() {
+        let src = "fn sq(int x) int {\n    return x * x\n}\nfn main(int a) int {\n    int b = sq(a + 1) * 2\n    print(b)\n    return b\n}\n";
+        let summary = summarize(&compile_str(src).unwrap());
+        assert_eq!(summary, ProgramSummary {
+            functions: 2,
+            statements: 4,
+            operations: 3,
+            // `(sq((a + 1)) * 2)`, the call, `(a + 1)` and `a`
+            max_expression_depth: 4,
+            undefined_functions: BTreeSet::new(),
+        });
+    }
+
+    #[test]
+    fn type_names_and_keywords_are_not_variable_names() {
+        assert_eq!(compile_error("fn f() {\n    int int = 5\n}\n"), "expected variable name, found type `int` (2:9)");
+        assert_eq!(compile_error("fn f() {\n    int if = 5\n}\n"), "expected variable name, found keyword `if` (2:9)");
+    }
+
+    #[test]
+    fn a_single_trailing_comma_is_allowed() {
+        let add = "fn add(int a, int b) int {\n    return a + b\n}\n";
+        assert!(compile_str(&format!("{}fn main() int {{\n    int[] xs = [1, 2, 3,]\n    return add(xs[2], 2,)\n}}\n", add)).is_ok());
+
+        assert_eq!(compile_error("fn f() {\n    int[] xs = [,]\n}\n"), "expected an expression before `,` (2:17)");
+        assert_eq!(compile_error("fn f() {\n    int[] xs = [1,,2]\n}\n"), "expected an expression before `,` (2:19)");
+        let e = compile_error(&format!("{}fn main() {{\n    int x = add(1,,2)\n}}\n", add));
+        assert_eq!(e, "expected an expression before `,` (5:19)");
+    }
+
+    #[test]
+    fn blocks_without_a_trailing_expression_are_unit() {
+        let e = compile_error("fn main() int {\n    int x = {\n        int a = 1\n    }\n    return x\n}\n");
+        assert_eq!(e, "cannot assign `{ ... }` of type `()` to `x` of type `int` (2:9)");
+    }
+
+    #[test]
+    fn passes_reach_the_statements_of_block_expressions() {
+        let funcs = compile_str("fn main() int {\n    int x = {\n        int a = 1 + 2\n        a\n    }\n    return x\n}\n").unwrap();
+        let mut values = vec![];
+        for_each_value(&funcs[0].body, &mut |val| values.push(val.value()));
+        assert_eq!(values, ["{ ...; a }", "3", "a", "x"]);
+
+        let funcs = compile_str("fn main() int {\n    byte b = 1\n    int x = {\n        int a = b + 2\n        a\n    }\n    return x\n}\n").unwrap();
+        let warnings = lint(&funcs).iter().map(|w| w.to_string()).collect::<Vec<_>>();
+        assert_eq!(warnings, ["Warning: in function `main`: `(b + 2)` mixes `byte` and `int`; consider an explicit cast (1:4)"]);
+    }
+
+    #[test]
+    fn visible_variables_are_listed_by_name() {
+        let mut var_lst = VarLst::new();
+        for name in ["delta", "alpha", "charlie"] {
+            var_lst.insert(name.to_string(), DataType::I64);
+        }
+        var_lst.push_scope();
+        var_lst.insert("bravo".to_string(), DataType::Bool);
+        var_lst.insert("alpha".to_string(), DataType::F64);
+
+        assert_eq!(var_lst.all_visible(), [
+            ("alpha", DataType::F64),
+            ("bravo", DataType::Bool),
+            ("charlie", DataType::I64),
+            ("delta", DataType::I64),
+        ]);
+        assert_eq!(var_lst.scope_names(), [vec!["alpha", "charlie", "delta"], vec!["alpha", "bravo"]]);
+    }
+
+    #[test]
+    fn exponents_and_unary_plus_parse_as_numbers() {
+        let tokens = lex("+1e5 1e-5").tokens.iter().map(|t| (t.token_type, t.value)).collect::<Vec<_>>();
+        assert_eq!(tokens, [
+            (TokenType::ArithmeticOperator, "+"),
+            (TokenType::FloatLiteral, "1e5"),
+            (TokenType::FloatLiteral, "1e-5"),
+            (TokenType::Eof, ""),
+        ]);
+
+        let rendered = |src: &str| {
+            let funcs = compile_str(src).unwrap();
+            let mut values = vec![];
+            for_each_value(&funcs[0].body, &mut |val| values.push((val.value(), val.dtype())));
+            values
+        };
+        // Unary plus leaves its operand as it is
+        assert_eq!(rendered("fn f() int {\n    return +5\n}\n"), [("5".to_string(), DataType::I64)]);
+        assert_eq!(rendered("fn f() float {\n    return +1e5\n}\n"), [("100000.0".to_string(), DataType::F64)]);
+        assert_eq!(rendered("fn f() float {\n    return 1e-5\n}\n"), [("1e-5".to_string(), DataType::F64)]);
+    }
+
+    #[test]
+    fn code_blocks_can_be_built_outside_the_parser() {
+        let mut block = CodeBlock::new(vec![AstNode::Continue]);
+        let one = Literal::parse_typed("1", DataType::I64).unwrap();
+        block.push(AstNode::ExpressionStatement(Box::new(one)));
+        block.push(AstNode::Break);
+
+        assert_eq!(block.statements().len(), 3);
+        assert!(matches!(block.statements()[0], AstNode::Continue));
+        match &block.statements()[1] {
+            AstNode::ExpressionStatement(val) => assert_eq!(val.value(), "1"),
+            stmt => panic!("expected an expression statement, got {:?}", stmt),
+        }
+        assert!(matches!(block.statements()[2], AstNode::Break));
+    }
+
+    #[test]
+    fn fixed_size_arrays_check_their_length() {
+        assert_eq!(DataType::new("int[3]"), DataType::Array { inner: Box::new(DataType::I64), len: 3 });
+        assert!(compile_str("fn f() {\n    int[3] xs = [1, 2, 3]\n}\n").is_ok());
+
+        let e = compile_error("fn f() {\n    int[2] xs = [1, 2, 3]\n}\n");
+        assert_eq!(e, "array literal has 3 elements, but `int[2]` holds 2");
+        let e = compile_error("fn f() {\n    int[3] xs = [1, 2, 3]\n    int y = xs[3]\n}\n");
+        assert_eq!(e, "index 3 is out of bounds for `xs` of type `int[3]`");
+    }
+
+    #[test]
+    fn chained_assignments_assign_every_target() {
+        let src = |stmt: &str| format!("fn main() int {{\n    int a = 0\n    int b = 0\n    bool c = false\n    {}\n    return a + b\n}}\n", stmt);
+        assert!(compile_str(&src("a = b = 5")).is_ok());
+
+        assert_eq!(compile_error(&src("a = c = 5")), "cannot assign `5` of type `int` to `c` of type `bool` (5:9)");
+        let e = compile_error(&src("int d = b = 5"));
+        assert_eq!(e, "a declaration can't be part of a chained assignment; declare `d` first, then assign `d = b = ...` (5:9)");
+    }
+
+    #[test]
+    fn operations_expose_their_parts() {
+        let funcs = compile_str("fn f(int a, float b) bool {\n    return a < b\n}\n").unwrap();
+        let mut ops = vec![];
+        for_each_value(&funcs[0].body, &mut |val| ops.extend(val.as_operation()));
+        let [op] = ops[..] else { panic!("expected one operation, got {:?}", ops) };
+        assert_eq!(op.op(), OperationType::LessThan);
+        assert_eq!((op.lhs().value(), op.lhs().dtype()), ("a".to_string(), DataType::I64));
+        assert_eq!((op.rhs().value(), op.rhs().dtype()), ("b".to_string(), DataType::F64));
+        assert_eq!(op.return_type(), &DataType::Bool);
+    }
+
+    #[test]
+    fn scalars_are_copied_and_heap_types_moved() {
+        for dtype in [DataType::I64, DataType::U64, DataType::F64, DataType::U8, DataType::Bool, DataType::Char, DataType::Unit] {
+            assert!(dtype.is_copy(), "{}", dtype);
+        }
+        for dtype in [DataType::String, DataType::new("int[]")] {
+            assert!(!dtype.is_copy(), "{}", dtype);
+        }
+        // Fixed-size arrays and tuples are copied if their elements are
+        assert!(DataType::new("int[4]").is_copy());
+        assert!(!DataType::new("string[4]").is_copy());
+        assert!(DataType::Tuple(vec![DataType::I64, DataType::Bool]).is_copy());
+        assert!(!DataType::Tuple(vec![DataType::I64, DataType::String]).is_copy());
+    }
+
+    #[test]
+    fn expressions_can_be_statements() {
+        let src = "fn foo() {\n    print(1)\n}\nfn main(int x) {\n    foo()\n    1 + 2\n}\n";
+        let funcs = compile_str(src).unwrap();
+        assert!(matches!(funcs[1].body.statements()[0], AstNode::ExpressionStatement(_)));
+        // Calls may have side effects, but the folded `1 + 2` can't
+        assert_eq!(check_unused_expressions(&funcs[1]), ["in function `main`: statement `3` has no effect"]);
+    }
+
+    #[test]
+    fn long_sums_parse_without_deep_recursion() {
+        let sum = |terms: usize| format!("fn main(int x) int {{\n    return {}\n}}\n", vec!["x"; terms].join(" + "));
+        let funcs = compile_str(&sum(MAX_EXPRESSION_DEPTH)).unwrap();
+        let [AstNode::ReturnStatement(ret)] = &funcs[0].body.statements[..] else { panic!("expected a single `return`") };
+        // Left-associative, so the last `+` is the root and the last `x` its right operand
+        let op = ret.value.as_deref().and_then(|val| val.as_operation()).unwrap();
+        assert_eq!((op.op(), op.rhs().value()), (OperationType::Add, "x".to_string()));
+        assert_eq!(op.return_type(), &DataType::I64);
+        assert_eq!(summarize(&funcs).max_expression_depth, MAX_EXPRESSION_DEPTH);
+
+        // The passes over the AST would overflow the stack on much deeper expressions
+        assert_eq!(compile_error(&sum(10_000)), "expression is nested more than 1000 deep (2:4010)");
+        let (_, diagnostics) = analyze_str(&sum(MAX_EXPRESSION_DEPTH + 1));
+        assert_eq!(diagnostics[0].to_string(), "Error: expression is nested more than 1000 deep (2:4010)");
+    }
+
+    #[test]
+    fn byte_strings_are_byte_vectors() {
+        assert_eq!(parse_byte_string("hex\"00FF\"").unwrap(), [0x00, 0xFF]);
+        assert_eq!(parse_byte_string(r#"b"\x00\xFF""#).unwrap(), [0x00, 0xFF]);
+
+        let funcs = compile_str("fn f() byte[] {\n    return hex\"00FF\"\n}\n").unwrap();
+        let mut values = vec![];
+        for_each_value(&funcs[0].body, &mut |val| values.push((val.value(), val.dtype())));
+        // A vector literal of the two bytes
+        assert_eq!(values, [
+            ("[0, 255]".to_string(), DataType::Vec { inner: Box::new(DataType::U8) }),
+            ("0".to_string(), DataType::U8),
+            ("255".to_string(), DataType::U8),
+        ]);
+
+        assert_eq!(compile_error("fn f() byte[] {\n    return hex\"0G\"\n}\n"), "invalid hex digit `G` in `hex\"0G\"` (2:12)");
+    }
+
+    #[test]
+    fn analysis_reports_errors_and_warnings_together() {
+        let src = "fn f() {\n    int a = true\n}\nfn g(byte b, int i) int {\n    int x = b + i\n    return x\n}\n";
+        let (funcs, diagnostics) = analyze_str(src);
+        let diagnostics = diagnostics.iter().map(|d| d.to_string()).collect::<Vec<_>>();
+        assert_eq!(diagnostics, [
+            "Error: cannot assign `true` of type `bool` to `a` of type `int` (2:9)",
+            "Warning: in function `g`: `(b + i)` mixes `byte` and `int`; consider an explicit cast (4:4)",
+        ]);
+        // `f` is kept without the broken statement
+        let names = funcs.unwrap().iter().map(|f| f.name.clone()).collect::<Vec<_>>();
+        assert_eq!(names, ["f", "g"]);
+    }
+
+    /// Returns the error messages of the diagnostics `analyze_str` finds in `src`, and the
+    /// functions it recovers
+    /// The errors are rendered with the location of their span, which the messages leave out
+    fn analyze_errors(src: &str) -> (Vec<String>, Vec<Function>) {
+        let (funcs, diagnostics) = analyze_str(src);
+        let errors = diagnostics.into_iter()
+            .filter(|d| d.is_error())
+            .map(|d| format!("{} ({}:{})", d.message, d.span.line, d.span.col))
+            .collect();
+        (errors, funcs.unwrap_or_default())
+    }
+
+    #[test]
+    fn broken_statements_are_skipped() {
+        let (errors, funcs) = analyze_errors("fn f() {\n    int a = 1\n    int b = true\n    int c = 2\n    bool d = 3\n}\n");
+        assert_eq!(errors, [
+            "cannot assign `true` of type `bool` to `b` of type `int` (3:9)",
+            "cannot assign `3` of type `int` to `d` of type `bool` (5:10)",
+        ]);
+        assert_eq!(funcs[0].body.statements.len(), 2);
+    }
+
+    #[test]
+    fn failed_declarations_still_declare_their_name() {
+        let (errors, funcs) = analyze_errors("fn f() int {\n    int x = 1e3\n    int y = x + 1\n    return y\n}\n");
+        assert_eq!(errors, ["float literal `1e3` cannot initialize `int`; use `1000` or cast (2:13)"]);
+        assert_eq!(funcs[0].body.statements.len(), 2);
+    }
+
+    #[test]
+    fn recovered_errors_are_located_by_their_span() {
+        let (_, diagnostics) = analyze_str("fn f() {\n    int a = true\n}\n");
+        assert_eq!((diagnostics[0].message.as_str(), diagnostics[0].span.line, diagnostics[0].span.col), (
+            "cannot assign `true` of type `bool` to `a` of type `int`", 2, 9,
+        ));
+
+        // An error without a location of its own is located at its statement, and a skipped
+        // `return` doesn't make the function look like it falls off its end
+        let (errors, _) = analyze_errors("fn f(int x) int {\n    return x + true\n}\nfn g() int {\n}\n");
+        assert_eq!(errors, [
+            "`+` requires numeric operands, found `int` and `bool` in `(x + true)` (2:5)",
+            "function `g` may reach the end of its body without returning a `int` (4:4)",
+        ]);
+    }
+
+    #[test]
+    fn misplaced_keywords_and_undefined_variables_are_errors() {
+        assert_eq!(compile_error("fn main() {\n    else\n}\n"), "unexpected keyword `else` (2:5)");
+        let e = compile_error("fn f() int {\n    if true {\n        int y = 1\n    }\n    return y\n}\n");
+        assert_eq!(e, "undefined variable `y` (5:12)");
+    }
+
+    #[test]
+    fn unary_operators_bind_tighter_than_binary_ones() {
+        let rendered = |src: &str| {
+            let func = parse_function(src).unwrap();
+            let mut values = vec![];
+            for_each_value(&func.body, &mut |val| values.push(val.value()));
+            values.swap_remove(0)
+        };
+        assert_eq!(rendered("fn f(int a, int b) int {\n    return ~a + b\n}\n"), "((~a) + b)");
+        assert_eq!(rendered("fn f(bool a, bool b) bool {\n    return !a == b\n}\n"), "((!a) == b)");
+        assert_eq!(rendered("fn f(bool a) bool {\n    return !!a\n}\n"), "(!(!a))");
+
+        let e = compile_error("fn f() int {\n    int x = ~1.0\n    return x\n}\n");
+        assert_eq!(e, "`~` requires an integer operand, found `1.0` of type `float`");
+        let e = compile_error("fn f() bool {\n    bool b = !1\n    return b\n}\n");
+        assert_eq!(e, "`!` requires a `bool` operand, found `1` of type `int`");
+    }
+
+    #[test]
+    fn repeated_literals_get_the_same_type() {
+        let funcs = compile_str("fn f() byte {\n    byte a = 0\n    byte b = 0\n    return a\n}\n").unwrap();
+        let mut literals = vec![];
+        for_each_value(&funcs[0].body, &mut |val| literals.extend(val.as_literal().map(|lit| lit.dtype())));
+        assert_eq!(literals, [DataType::U8, DataType::U8]);
+        // The cache only lasts for the one compilation
+        assert!(LITERALS.with(|cache| cache.borrow().is_empty()));
+    }
+
+    #[test]
+    fn functions_without_a_body_are_errors() {
+        assert_eq!(compile_error("fn f(int a)\nfn main() {\n}\n"), "function `f` is missing a body (1:4)");
+        assert_eq!(compile_error("fn f(int a) int\n"), "function `f` is missing a body (1:4)");
+    }
+
+    #[test]
+    fn values_identify_their_kind() {
+        let funcs = compile_str("fn g() int {\n    return 1\n}\nfn f(int a) int {\n    return a + 1 * g()\n}\n").unwrap();
+        let mut kinds = vec![];
+        for_each_value(&funcs[1].body, &mut |val| {
+            kinds.push((
+                val.value(),
+                val.as_literal().is_some(),
+                val.as_variable().is_some(),
+                val.as_operation().is_some(),
+                val.as_call().is_some(),
+            ));
+        });
+        assert_eq!(kinds, [
+            ("(a + (1 * g()))".to_string(), false, false, true, false),
+            ("a".to_string(), false, true, false, false),
+            ("(1 * g())".to_string(), false, false, true, false),
+            ("1".to_string(), true, false, false, false),
+            ("g()".to_string(), false, false, false, true),
+        ]);
+    }
+
+    #[test]
+    fn nested_array_literals_unify_their_element_types() {
+        let funcs = compile_str("fn f() {\n    int[][] xs = [[1, 2], [3, 4]]\n    int[][] ys = [[1, 2], [3]]\n}\n").unwrap();
+        let mut types = vec![];
+        for_each_value(&funcs[0].body, &mut |val| types.push(val.dtype().to_string()));
+        assert_eq!(types[..3], ["int[][]", "int[]", "int"]);
+        assert_eq!(check_jagged_arrays(&funcs[0]), ["in function `f`: the rows of `[[1, 2], [3]]` differ in length"]);
+
+        let e = compile_error("fn f() {\n    int[][] xs = [[1], [\"a\"]]\n}\n");
+        assert_eq!(e, "array element `\"a\"` has type `byte[]`, expected `int`");
+    }
+
+    #[test]
+    fn tokens_keep_the_spelling_that_literals_normalize() {
+        let lexer = lex("1_000 0xFF 0x1.8p3 7");
+        let raw = lexer.tokens.iter().map(|t| t.value).collect::<Vec<_>>();
+        assert_eq!(raw, ["1_000", "0xFF", "0x1.8p3", "7", ""]);
+        let normalized = lexer.tokens.iter().map(|t| t.normalized.as_deref()).collect::<Vec<_>>();
+        assert_eq!(normalized, [Some("1000"), Some("255"), None, None, None]);
+        assert_eq!(lexer.tokens[1].normalized_value(), "255");
+        assert_eq!(lexer.tokens[3].normalized_value(), "7");
+
+        assert_eq!(Literal::parse_typed("1_000", DataType::I64).unwrap().value(), "1000");
+        assert_eq!(Literal::parse_typed("0xFF", DataType::U8).unwrap().value(), "255");
+        assert_eq!(Literal::parse_typed("0x1.8p3", DataType::F64).unwrap().value(), "12.0");
+        let e = compile_error("fn f() byte {\n    byte b = 0x1_00\n    return b\n}\n");
+        assert_eq!(e, "integer literal `0x1_00` is too large for `byte` (2:14)");
+    }
+
+    #[test]
+    fn else_if_chains_stay_flat() {
+        let src = "fn main(int x) int {\n    if x > 0 {\n        return 1\n    } else if x < 0 {\n        return 2\n    } else {\n        return 3\n    }\n}\n";
+        let func = parse_function(src).unwrap();
+        let [AstNode::If(if_obj)] = &func.body.statements[..] else { panic!("expected a single `if`") };
+        let conditions = if_obj.chain().map(|arm| arm.condition.value()).collect::<Vec<_>>();
+        assert_eq!(conditions, ["(x > 0)", "(x < 0)"]);
+        assert!(if_obj.final_else().is_some());
+        assert_eq!(if_obj.blocks().count(), 3);
+
+        let rendered = func.to_source();
+        assert_eq!(rendered, src.replace("x > 0", "(x > 0)").replace("x < 0", "(x < 0)"));
+        assert_eq!(parse_function(&rendered).unwrap().to_source(), rendered);
+    }
+
+    #[test]
+    fn if_expressions_need_an_else() {
+        let e = compile_error("fn main(bool c) int {\n    int x = if c { 1 }\n    return x\n}\n");
+        assert_eq!(e, "an `if` used as a value needs an `else`, as its type is `()` without one (2:13)");
+    }
+
+    #[test]
+    fn float_literals_do_not_initialize_integers() {
+        assert_eq!(
+            compile_error("fn f() int {\n    int x = 1e3\n    return x\n}\n"),
+            "float literal `1e3` cannot initialize `int`; use `1000` or cast (2:13)",
+        );
+        assert_eq!(
+            compile_error("fn f() int {\n    int x = 2.5\n    return x\n}\n"),
+            "float literal `2.5` cannot initialize `int` (2:13)",
+        );
+    }
+
+    #[test]
+    fn casts_convert_between_numeric_types() {
+        let funcs = compile_str("fn f() int {\n    int x = 1e3 as int\n    float y = x as float\n    return x\n}\n").unwrap();
+        assert_eq!(funcs.len(), 1);
+        assert_eq!(
+            compile_error("fn f() int {\n    int x = true as int\n    return x\n}\n"),
+            "cannot cast `true` of type `bool` to `int`",
+        );
+    }
+}
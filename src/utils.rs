@@ -1,29 +1,153 @@
-use std::collections::HashMap;
-use crate::parser::DataType;
+use std::{cell::RefCell, collections::{BTreeMap, HashMap, HashSet}, rc::Rc};
+use anyhow::{Result, anyhow};
+use crate::{lexer::{Diagnostic, Span}, parser::{DataType, ParserOptions}};
 
+thread_local! {
+    static INTERNED: RefCell<HashSet<Rc<str>>> = RefCell::new(HashSet::new());
+}
+
+/// Returns a shared handle to `s`, so repeated identifiers and literals in the AST share one
+/// allocation. The string is only copied the first time it's interned on the current thread.
+pub fn intern(s: &str) -> Rc<str> {
+    INTERNED.with(|pool| {
+        let mut pool = pool.borrow_mut();
+        if let Some(rc) = pool.get(s) {
+            return rc.clone();
+        }
+        let rc: Rc<str> = Rc::from(s);
+        pool.insert(rc.clone());
+        rc
+    })
+}
+
+/// Empties the current thread's interner. Strings already handed out stay valid, but are no
+/// longer shared with later calls to `intern`.
+pub fn clear_interned() {
+    INTERNED.with(|pool| pool.borrow_mut().clear());
+}
+
+
+struct VarInfo {
+    dtype: DataType,
+    mutable: bool,
+    is_parameter: bool,
+}
 
 pub struct VarLst {
-    vars: Vec<HashMap<String, DataType>>
+    vars: Vec<HashMap<String, VarInfo>>,
+    /// Locals declared since the last call to `take_locals`, in declaration order
+    locals: Vec<(String, DataType, Span)>,
+    /// Kept here since every parsing function has the variable list at hand
+    pub(crate) options: ParserOptions,
+    /// Whether parsing continues past errors where it can, collecting them in `errors`
+    recovering: bool,
+    errors: Vec<Diagnostic>,
 }
 
 impl VarLst {
     pub fn new() -> Self {
         VarLst {
-            vars: vec![HashMap::new()]
+            vars: vec![HashMap::new()],
+            locals: vec![],
+            options: ParserOptions::default(),
+            recovering: false,
+            errors: vec![],
         }
     }
 
+    pub fn with_options(mut self, options: ParserOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Makes the parser skip over parts of the program it fails to parse where it can, recording
+    /// the errors for `take_errors` rather than stopping at the first one
+    pub fn with_recovery(mut self) -> Self {
+        self.recovering = true;
+        self
+    }
+
+    pub fn is_recovering(&self) -> bool {
+        self.recovering
+    }
+
+    /// Records an error that parsing recovered from. Returns false once there are more than
+    /// `max_errors` of them, which tells the caller about the limit being exceeded rather than
+    /// just reached, and parsing should stop.
+    pub fn record_error(&mut self, error: Diagnostic) -> bool {
+        self.errors.push(error);
+        !self.has_too_many_errors()
+    }
+
+    pub fn has_too_many_errors(&self) -> bool {
+        self.errors.len() > self.options.max_errors.max(1)
+    }
+
+    pub fn take_errors(&mut self) -> Vec<Diagnostic> {
+        std::mem::take(&mut self.errors)
+    }
+
     pub fn insert(&mut self, var: String, dtype: DataType) {
-        self.vars.last_mut().unwrap().insert(var, dtype);
+        self.vars.last_mut().unwrap().insert(var, VarInfo { dtype, mutable: true, is_parameter: false });
+    }
+
+    pub fn insert_const(&mut self, var: String, dtype: DataType) {
+        self.vars.last_mut().unwrap().insert(var, VarInfo { dtype, mutable: false, is_parameter: false });
+    }
+
+    pub fn insert_parameter(&mut self, var: String, dtype: DataType) {
+        self.vars.last_mut().unwrap().insert(var, VarInfo { dtype, mutable: true, is_parameter: true });
+    }
+
+    fn get_info(&self, var: &str) -> Option<&VarInfo> {
+        self.vars.iter().rev().find_map(|scope| scope.get(var))
+    }
+
+    pub fn get(&self, var: &str) -> Option<DataType> {
+        self.get_info(var).map(|d| d.dtype.clone())
+    }
+
+    /// Returns whether the innermost variable named `var` may be reassigned
+    pub fn is_mutable(&self, var: &str) -> Option<bool> {
+        self.get_info(var).map(|d| d.mutable)
     }
 
-    pub fn get(&self, var: &String) -> Option<DataType> {
-        for scope in self.vars.iter().rev() {
-            if let Some(d) = scope.get(var) {
-                return Some(d.clone());
+    /// Returns whether the innermost variable named `var` is a function parameter
+    pub fn is_parameter(&self, var: &str) -> Option<bool> {
+        self.get_info(var).map(|d| d.is_parameter)
+    }
+
+    /// Returns every variable in scope with its type, sorted by name so listings are stable.
+    /// A variable shadowed by one in an inner scope is listed once, with the inner one's type.
+    pub fn all_visible(&self) -> Vec<(&str, DataType)> {
+        let mut visible = BTreeMap::new();
+        for scope in self.vars.iter() {
+            for (name, info) in scope.iter() {
+                visible.insert(name.as_str(), info.dtype.clone());
             }
         }
-        None
+        visible.into_iter().collect()
+    }
+
+    /// Returns the names of the variables in each scope, outermost first, sorted by name
+    pub fn scope_names(&self) -> Vec<Vec<&str>> {
+        self.vars.iter()
+            .map(|scope| {
+                let mut names = scope.keys().map(|name| name.as_str()).collect::<Vec<&str>>();
+                names.sort_unstable();
+                names
+            })
+            .collect()
+    }
+
+    /// Records that a local named `var` was declared at `span`. Unlike `insert`, this outlives
+    /// the local's scope so the whole function's locals can be reported once it's parsed.
+    pub fn record_local(&mut self, var: &str, dtype: DataType, span: Span) {
+        self.locals.push((var.to_string(), dtype, span));
+    }
+
+    pub fn take_locals(&mut self) -> Vec<(String, DataType, Span)> {
+        std::mem::take(&mut self.locals)
     }
 
     pub fn push_scope(&mut self) {
@@ -33,10 +157,54 @@ impl VarLst {
     pub fn pop_scope(&mut self) {
         self.vars.pop();
     }
+
+    /// Returns the number of scopes, which `truncate_scopes` can restore after a failed parse
+    /// left some of them open
+    pub fn scope_depth(&self) -> usize {
+        self.vars.len()
+    }
+
+    pub fn truncate_scopes(&mut self, depth: usize) {
+        self.vars.truncate(depth);
+    }
+}
+
+impl Default for VarLst {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The parameters and return type of a function, and where its name is defined
+pub struct FnSignature {
+    pub params: Vec<(String, DataType)>,
+    /// The number of leading parameters without a default value
+    pub required: usize,
+    pub ret_type: DataType,
+    pub span: Span,
+    /// The `Function::module` of an imported function, or `None` for one in the file being compiled
+    pub module: Option<usize>,
+}
+
+impl FnSignature {
+    /// Renders the signature of the function called `name` as in `add(int, int) -> int`
+    pub fn signature_string(&self, name: &str) -> String {
+        format_signature(name, self.params.iter().map(|(_, dtype)| dtype), &self.ret_type)
+    }
+}
+
+/// Renders a call signature such as `add(int, int) -> int`. The arrow is left out for functions
+/// without a return type.
+pub fn format_signature<'a>(name: &str, params: impl Iterator<Item = &'a DataType>, ret_type: &DataType) -> String {
+    let params = params.map(|dtype| dtype.to_string()).collect::<Vec<_>>().join(", ");
+    match ret_type {
+        DataType::Unit => format!("{}({})", name, params),
+        _ => format!("{}({}) -> {}", name, params, ret_type),
+    }
 }
 
 pub struct FnLst {
-    funcs: Vec<HashMap<String, (Vec<(String, DataType)>, DataType)>>
+    funcs: Vec<HashMap<String, FnSignature>>
 }
 
 impl FnLst {
@@ -46,17 +214,31 @@ impl FnLst {
         }
     }
 
-    pub fn insert(&mut self, var: String, res_type: DataType, args: Vec<(String, DataType)>) {
-        self.funcs.last_mut().unwrap().insert(var, (args, res_type));
+    /// Registers a function defined at `span`, failing if the scope already has one by that name
+    pub fn insert(&mut self, var: String, signature: FnSignature) -> Result<()> {
+        let scope = self.funcs.last_mut().unwrap();
+        if let Some(prev) = scope.get(&var) {
+            return Err(anyhow!(
+                "function `{}` is defined more than once ({}:{} and {}:{})",
+                var, prev.span.line, prev.span.col, signature.span.line, signature.span.col
+            ));
+        }
+        scope.insert(var, signature);
+        Ok(())
     }
 
-    pub fn get(&self, var: &String) -> Option<(&Vec<(String, DataType)>, DataType)> {
-        for scope in self.funcs.iter().rev() {
-            if let Some(d) = scope.get(var) {
-                return Some((&d.0, d.1.clone()));
-            }
-        }
-        None
+    pub fn get(&self, var: &str) -> Option<&FnSignature> {
+        self.funcs.iter().rev().find_map(|scope| scope.get(var))
+    }
+
+    /// Returns a name such as `m.sqrt` that `name` is registered under when its file was
+    /// imported with an alias. With several aliases for the file, the first by name is returned.
+    pub fn qualified_name(&self, name: &str) -> Option<&str> {
+        self.funcs.iter()
+            .flat_map(|scope| scope.keys())
+            .filter(|qualified| qualified.rsplit_once('.').is_some_and(|(_, n)| n == name))
+            .min()
+            .map(|qualified| qualified.as_str())
     }
 
     pub fn push_scope(&mut self) {
@@ -66,4 +248,10 @@ impl FnLst {
     pub fn pop_scope(&mut self) {
         self.funcs.pop();
     }
+}
+
+impl Default for FnLst {
+    fn default() -> Self {
+        Self::new()
+    }
 }
\ No newline at end of file
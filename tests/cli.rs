@@ -0,0 +1,290 @@
+//! Runs the `compiler-pilot` binary on programs written to temporary files
+
+use std::{fs, path::PathBuf, process::{Command, Output}};
+
+use tempfile::TempDir;
+
+/// Writes `source` to `main.tr` in a new temporary directory, which is deleted when the returned
+/// `TempDir` is dropped
+fn write_program(source: &str) -> (TempDir, PathBuf) {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("main.tr");
+    fs::write(&path, source).unwrap();
+    (dir, path)
+}
+
+fn compiler(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_compiler-pilot")).args(args).output().unwrap()
+}
+
+fn stdout(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+#[test]
+fn check_exit_status_reports_errors() {
+    let (_dir, path) = write_program("fn main() int {\n    return 1\n}\n");
+    let output = compiler(&["--check", path.to_str().unwrap()]);
+    assert_eq!(stdout(&output), "");
+    assert_eq!(output.status.code(), Some(0));
+
+    let (_dir, path) = write_program("fn main() int {\n    int x = true\n    return 1\n}\n");
+    let output = compiler(&["--check", path.to_str().unwrap()]);
+    assert_eq!(stdout(&output), "Error: cannot assign `true` of type `bool` to `x` of type `int` (2:9)\n");
+    assert_eq!(output.status.code(), Some(1));
+}
+
+#[test]
+fn check_reports_unterminated_strings() {
+    let (_dir, path) = write_program("fn main() {\n    string s = \"\"\"never closed\n}\n");
+    // The string runs to the end of the file, leaving the function's `{` unclosed
+    let output = compiler(&["--check", path.to_str().unwrap()]);
+    assert_eq!(stdout(&output), "Error: unterminated `\"\"\"` string (2:16)\nError: unclosed `{` (1:11)\n");
+    assert_eq!(output.status.code(), Some(1));
+
+    let output = compiler(&["run", path.to_str().unwrap()]);
+    assert_eq!(stdout(&output), "Error: unterminated `\"\"\"` string (2:16)\n");
+    assert_eq!(output.status.code(), Some(1));
+}
+
+#[test]
+fn run_passes_arguments_to_main() {
+    let (_dir, path) = write_program("fn main(int a, int b) int {\n    return a + b\n}\n");
+    let output = compiler(&["run", path.to_str().unwrap(), "--", "2", "40"]);
+    assert_eq!(stdout(&output), "42\n");
+    assert_eq!(output.status.code(), Some(0));
+}
+
+#[test]
+fn run_reports_integer_overflow() {
+    let source = "fn main() uint {\n    uint a = 18446744073709551615\n    uint b = a * a\n    return b\n}\n";
+    let (_dir, path) = write_program(source);
+    let output = compiler(&["run", path.to_str().unwrap()]);
+    assert_eq!(stdout(&output), "Error: integer overflow in `18446744073709551615 * 18446744073709551615`\n");
+    assert_eq!(output.status.code(), Some(1));
+}
+
+#[test]
+fn run_reports_bad_arguments() {
+    let (_dir, path) = write_program("fn main(int a, int b) int {\n    return a + b\n}\n");
+    let output = compiler(&["run", path.to_str().unwrap(), "--", "9223372036854775807", "1"]);
+    assert_eq!(stdout(&output), "Error: integer overflow in `9223372036854775807 + 1`\n");
+    assert_eq!(output.status.code(), Some(1));
+
+    let output = compiler(&["run", path.to_str().unwrap(), "--", "x", "1"]);
+    assert_eq!(stdout(&output), "Error: invalid argument for parameter `a`: `x` is not a valid `int`\n");
+    assert_eq!(output.status.code(), Some(1));
+}
+
+#[test]
+fn check_reports_unreadable_files() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("missing.tr");
+    let output = compiler(&["--check", path.to_str().unwrap()]);
+    let expected = format!("Error: cannot read `{}`: ", path.display());
+    assert!(stdout(&output).starts_with(&expected), "{:?}", stdout(&output));
+    assert_eq!(output.status.code(), Some(1));
+}
+
+#[test]
+fn check_reports_invalid_utf8() {
+    let (_dir, path) = write_program("fn main() {\n}\n");
+    fs::write(&path, b"fn main() {\n    \xff\n}\n").unwrap();
+    let output = compiler(&["--check", path.to_str().unwrap()]);
+    assert_eq!(stdout(&output), "Error: invalid UTF-8 byte `0xFF` at offset 16 (2:5)\n");
+    assert_eq!(output.status.code(), Some(1));
+}
+
+#[test]
+fn run_stops_unbounded_recursion() {
+    let (_dir, path) = write_program("fn f(int n) int {\n    return f(n + 1)\n}\nfn main() int {\n    return f(0)\n}\n");
+    let output = compiler(&["run", path.to_str().unwrap()]);
+    assert_eq!(stdout(&output), "Error: calls nested more than 1000 deep, in `f`\n");
+    assert_eq!(output.status.code(), Some(1));
+
+    let (_dir, path) = write_program("fn f(int n) int {\n    if n == 0 {\n        return 0\n    }\n    return f(n - 1) + 1\n}\nfn main() int {\n    return f(999)\n}\n");
+    let output = compiler(&["run", path.to_str().unwrap()]);
+    assert_eq!(stdout(&output), "999\n");
+}
+
+#[test]
+fn run_keeps_string_literals_as_written() {
+    // Runs of spaces, `//` and `;` inside strings were once altered before lexing
+    let (_dir, path) = write_program("fn main() string {\n    return \"\"\"a  b // c;\\t  d\"\"\"\n}\n");
+    let output = compiler(&["run", path.to_str().unwrap()]);
+    assert_eq!(stdout(&output), "a  b // c;\\t  d\n");
+
+    let (_dir, path) = write_program("fn main() string {\n    return \"a  b // c;\\t  d\"\n}\n");
+    let output = compiler(&["run", path.to_str().unwrap()]);
+    assert_eq!(stdout(&output), "a  b // c;\t  d\n");
+}
+
+#[test]
+fn time_reports_each_phase_to_stderr() {
+    let (_dir, path) = write_program("fn main() int {\n    return 1\n}\n");
+    let output = compiler(&["--check", "--time", path.to_str().unwrap()]);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let phases = stderr.lines().map(|line| line.split(':').next().unwrap().trim()).collect::<Vec<_>>();
+    assert_eq!(phases, ["lex", "parse", "lint"]);
+    assert_eq!(stdout(&output), "");
+
+    let output = compiler(&["--check", path.to_str().unwrap()]);
+    assert!(output.stderr.is_empty());
+}
+
+#[test]
+fn run_calls_imported_functions() {
+    let (dir, path) = write_program("import \"lib.tr\"\nfn main(int a) int {\n    return double(a)\n}\n");
+    fs::write(dir.path().join("lib.tr"), "pub fn double(int x) int {\n    return x * 2\n}\n").unwrap();
+    let output = compiler(&["run", path.to_str().unwrap(), "--", "21"]);
+    assert_eq!(stdout(&output), "42\n");
+
+    fs::write(dir.path().join("lib.tr"), "import \"main.tr\"\npub fn double(int x) int {\n    return x * 2\n}\n").unwrap();
+    let output = compiler(&["--check", path.to_str().unwrap()]);
+    assert!(stdout(&output).ends_with("cyclic import: main.tr -> lib.tr -> main.tr\n"), "{}", stdout(&output));
+    assert_eq!(output.status.code(), Some(1));
+}
+
+#[test]
+fn check_reports_undeclared_names() {
+    let (_dir, path) = write_program("fn main() {\n    x = 1\n}\n");
+    let output = compiler(&["--check", path.to_str().unwrap()]);
+    assert_eq!(stdout(&output), "Error: undefined variable `x` (2:5)\n");
+    assert_eq!(output.status.code(), Some(1));
+
+    // Characters the lexer can't lex are an error rather than a crash
+    let (_dir, path) = write_program("fn main() {\n    int x = 1 @ 2\n}\n");
+    let output = compiler(&["--check", path.to_str().unwrap()]);
+    assert!(stdout(&output).starts_with("Error: No condition parsing met at 2:15"), "{:?}", stdout(&output));
+    assert_eq!(output.status.code(), Some(1));
+}
+
+#[test]
+fn run_calls_functions_through_an_import_alias() {
+    let (dir, path) = write_program("import \"lib.tr\" as m\nfn main(int a) int {\n    return m.double(a)\n}\n");
+    fs::write(dir.path().join("lib.tr"), "pub fn double(int x) int {\n    return x * 2\n}\n").unwrap();
+    let output = compiler(&["run", path.to_str().unwrap(), "--", "4"]);
+    assert_eq!(stdout(&output), "8\n");
+
+    fs::write(&path, "import \"lib.tr\" as m\nfn main(int a) int {\n    return double(a)\n}\n").unwrap();
+    let output = compiler(&["run", path.to_str().unwrap(), "--", "4"]);
+    assert_eq!(stdout(&output), "Error: `double` was imported with an alias and must be called as `m.double`\n");
+    assert_eq!(output.status.code(), Some(1));
+}
+#[test]
+fn functions_in_different_files_may_share_a_name() {
+    // An aliased import's `add` is `m.add`, so the program can have an `add` of its own
+    let (dir, path) = write_program("import \"a.tr\" as m\nimport \"b.tr\" as n\nfn add(int x, int y) int {\n    return x - y\n}\nfn main(int x) int {\n    return m.add(x, 1) * 100 + n.add(x, 1) * 10 + add(x, 1)\n}\n");
+    fs::write(dir.path().join("a.tr"), "fn helper(int x) int {\n    return x\n}\npub fn add(int x, int y) int {\n    return helper(x) + y\n}\n").unwrap();
+    fs::write(dir.path().join("b.tr"), "fn helper(int x) int {\n    return x * 2\n}\npub fn add(int x, int y) int {\n    return helper(x + y)\n}\n").unwrap();
+    let output = compiler(&["run", path.to_str().unwrap(), "--", "3"]);
+    assert_eq!(stdout(&output), "482\n");
+
+    // A helper that isn't `pub` can share its name with a function of the importing file
+    fs::write(&path, "import \"a.tr\"\nfn helper(int x) int {\n    return 0\n}\nfn main(int x) int {\n    return add(x, 1) + helper(x)\n}\n").unwrap();
+    let output = compiler(&["run", path.to_str().unwrap(), "--", "3"]);
+    assert_eq!(stdout(&output), "4\n");
+}
+
+
+#[test]
+fn check_passes_with_only_warnings() {
+    let (_dir, path) = write_program("fn main(byte b, int i) int {\n    int x = b + i\n    return x\n}\n");
+    let output = compiler(&["--check", path.to_str().unwrap()]);
+    assert_eq!(stdout(&output), "Warning: in function `main`: `(b + i)` mixes `byte` and `int`; consider an explicit cast (1:4)\n");
+    assert_eq!(output.status.code(), Some(0));
+
+    let (_dir, path) = write_program("fn main(byte b, int i) int {\n    int x = b + i\n    bool y = 1\n    return x\n}\n");
+    let output = compiler(&["--check", path.to_str().unwrap()]);
+    assert_eq!(stdout(&output), "Error: cannot assign `1` of type `int` to `y` of type `bool` (3:10)\n");
+    assert_eq!(output.status.code(), Some(1));
+}
+
+#[test]
+fn dump_scopes_shows_blocks_opening_and_closing() {
+    let (_dir, path) = write_program("fn main(int a) {\n    int b = a\n    if true {\n        int c = b\n    }\n    int d = b\n}\n");
+    let output = compiler(&["--check", "--dump-scopes", path.to_str().unwrap()]);
+    assert_eq!(String::from_utf8_lossy(&output.stderr), concat!(
+        "scopes at 2:5 `int`: depth 3: {} {a} {}\n",
+        "scopes at 3:5 `if`: depth 3: {} {a} {b}\n",
+        "scopes at 4:9 `int`: depth 4: {} {a} {b} {}\n",
+        "scopes at 5:5 `}`: depth 4: {} {a} {b} {c}\n",
+        "scopes at 6:5 `int`: depth 3: {} {a} {b}\n",
+        "scopes at 7:1 `}`: depth 3: {} {a} {b, d}\n",
+    ));
+
+    let output = compiler(&["--check", path.to_str().unwrap()]);
+    assert!(output.stderr.is_empty());
+}
+
+#[test]
+fn deeply_nested_expressions_are_errors() {
+    let sum = |terms: usize| vec!["x"; terms].join(" + ");
+    let (_dir, path) = write_program(&format!("fn main(int x) {{\n    int y = {}\n}}\n", sum(10_000)));
+    for mode in ["--check", "run"] {
+        let output = compiler(&[mode, path.to_str().unwrap()]);
+        assert_eq!(stdout(&output), "Error: expression is nested more than 1000 deep (2:4011)\n");
+        assert_eq!(output.status.code(), Some(1));
+    }
+
+    let (_dir, path) = write_program(&format!("fn main(int x) int {{\n    return {}\n}}\n", sum(1000)));
+    let output = compiler(&["run", path.to_str().unwrap(), "--", "2"]);
+    assert_eq!(stdout(&output), "2000\n");
+}
+
+#[test]
+fn check_stops_at_the_error_limit() {
+    let source = "fn main() {\n    int a = true\n    int b = true\n    int c = true\n    int d = true\n}\n";
+    let (_dir, path) = write_program(source);
+    // The parser stops at the first error past the limit, without looking at `d`
+    let output = compiler(&["--check", "--max-errors", "2", path.to_str().unwrap()]);
+    assert_eq!(stdout(&output), concat!(
+        "Error: cannot assign `true` of type `bool` to `a` of type `int` (2:9)\n",
+        "Error: cannot assign `true` of type `bool` to `b` of type `int` (3:9)\n",
+        "... and 1 more errors\n",
+    ));
+    assert_eq!(output.status.code(), Some(1));
+
+    // Reaching the limit without passing it leaves nothing out
+    let (_dir, path) = write_program("fn main() {\n    int a = true\n}\n");
+    let output = compiler(&["--check", "--max-errors", "1", path.to_str().unwrap()]);
+    assert_eq!(stdout(&output), "Error: cannot assign `true` of type `bool` to `a` of type `int` (2:9)\n");
+    assert_eq!(output.status.code(), Some(1));
+}
+
+#[test]
+fn dumps_are_identical_across_runs() {
+    let source = "fn add(int a, int b) int {\n    int c = a + b\n    int d = c * 2\n    return d\n}\nfn main() int {\n    return add(1, 2)\n}\n";
+    let (_dir, path) = write_program(source);
+    // Each run hashes with different keys, so any map iterated into the output would show here
+    let first = compiler(&[path.to_str().unwrap()]);
+    assert!(first.status.success());
+    for _ in 0..3 {
+        assert_eq!(compiler(&[path.to_str().unwrap()]).stdout, first.stdout);
+    }
+}
+
+#[test]
+fn run_truncates_floats_cast_to_integers() {
+    let (_dir, path) = write_program("fn main() int {\n    int x = -2.75 as int\n    byte b = 300.0 as byte\n    return x\n}\n");
+    let output = compiler(&["run", path.to_str().unwrap()]);
+    assert_eq!(stdout(&output), "Error: cannot cast `300.0` to `byte`\n");
+    assert_eq!(output.status.code(), Some(1));
+
+    let (_dir, path) = write_program("fn main() int {\n    int x = -2.75 as int\n    return x\n}\n");
+    let output = compiler(&["run", path.to_str().unwrap()]);
+    assert_eq!(stdout(&output), "-2\n");
+}
+
+#[test]
+fn output_writes_to_a_file_instead_of_stdout() {
+    let (dir, path) = write_program("fn main() int {\n    int x = true\n    return 1\n}\n");
+    let out_path = dir.path().join("out.txt");
+    let output = compiler(&["--check", "--output", out_path.to_str().unwrap(), path.to_str().unwrap()]);
+    assert_eq!(stdout(&output), "");
+    assert_eq!(fs::read_to_string(&out_path).unwrap(), "Error: cannot assign `true` of type `bool` to `x` of type `int` (2:9)\n");
+    assert_eq!(output.status.code(), Some(1));
+
+    let output = compiler(&["--check", path.to_str().unwrap(), "--output"]);
+    assert_eq!(stdout(&output), "Error: `--output` must be followed by a file path\n");
+}
@@ -0,0 +1,75 @@
+//! Lexes random source, renders the tokens back with `tokens_to_source` and checks that lexing
+//! the result gives the same token stream
+
+use compiler_pilot::lexer::{tokens_to_source, Lexer, Token};
+use proptest::prelude::*;
+
+/// A piece of generated source. Names are only lexed once declared, so a `Name` is rendered as a
+/// declaration the first time it appears.
+#[derive(Debug, Clone)]
+enum Fragment {
+    Text(String),
+    Name(usize),
+}
+
+fn fragment() -> impl Strategy<Value = Fragment> {
+    let text = prop_oneof![
+        "[0-9]{1,4}",
+        "[1-9]_[0-9]{3}",
+        "[0-9]{1,3}\\.[0-9]{1,3}",
+        "\\.[0-9]{1,2}",
+        "[0-9]{1,2}[eE][+-]?[0-9]{1,2}",
+        "0x1\\.[0-9a-f]p[+-]?[0-9]",
+        "\"([a-z ]|\\\\n|\\\\\"|\\\\x41|\\\\u\\{e9\\})*\"",
+        "'([a-z]|\\\\t|\\\\')'",
+        "b\"([a-z]|\\\\xff)*\"",
+        "hex\"([0-9A-F]{2})*\"",
+        "\"\"\"[a-z\n ]*\"\"\"",
+        "(true|false)",
+        "(\\+|-|\\*|/|%|==|!=|<=|>=|<|>|=|\\+=|:=|!|~|\\.\\.|\\.\\.=)",
+        "(\\(|\\)|\\[|\\]|\\{|\\}|,|;|\\?|:|\n)",
+        "(if|else|while|for|in|return|break|continue|const|pub)",
+        "(int|uint|float|bool|char|byte|string)(\\[\\]|\\[[1-9]\\])?",
+        "// [a-z ]*\n",
+    ];
+    prop_oneof![
+        4 => text.prop_map(Fragment::Text),
+        1 => (0..5usize).prop_map(Fragment::Name),
+    ]
+}
+
+fn render(fragments: &[Fragment]) -> String {
+    let mut declared = [false; 5];
+    let pieces = fragments.iter().map(|f| match f {
+        Fragment::Text(text) => text.clone(),
+        Fragment::Name(i) if declared[*i] => format!("v{}", i),
+        Fragment::Name(i) => {
+            declared[*i] = true;
+            format!("int v{}", i)
+        }
+    });
+    pieces.collect::<Vec<String>>().join(" ")
+}
+
+fn lex(source: &str) -> Vec<Token<'_>> {
+    let mut lexer = Lexer::new(source);
+    while lexer.next().is_some() {}
+    lexer.tokens
+}
+
+proptest! {
+    // The failing case is printed shrunk, so there's no need to save it
+    #![proptest_config(ProptestConfig { cases: 32, failure_persistence: None, ..ProptestConfig::default() })]
+
+    #[test]
+    fn relexing_rendered_tokens_is_stable(fragments in prop::collection::vec(fragment(), 0..30)) {
+        let source = render(&fragments);
+        let tokens = lex(&source);
+        let rendered = tokens_to_source(&tokens);
+        let relexed = lex(&rendered);
+
+        let same = tokens.len() == relexed.len() && tokens.iter().zip(relexed.iter()).all(|(a, b)| a.eq_ignore_span(b));
+        prop_assert!(same, "source {:?} rendered as {:?}\n{:?}\n{:?}", source, rendered, tokens, relexed);
+        prop_assert_eq!(tokens_to_source(&relexed), rendered);
+    }
+}